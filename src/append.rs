@@ -0,0 +1,163 @@
+use anyhow::{ Context, Result };
+use std::collections::HashMap;
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ BufWriter, Write };
+use std::path::Path;
+use crate::csv_writer::csv_rows;
+use crate::timeline::CSV_HEADER;
+use crate::writer::{ to_json, ThreadWriter };
+use crate::{ OutputFormat, OutputOptions, Thread };
+use log::info;
+
+// JSON, NDJSON or CSV output to `--output` that an interrupted run can pick up again.
+// After each thread is written and flushed, a line `<output length>\t<thread id>\t<source
+// path>` is added to the `--state` file. With `--append` the output is cut back to the
+// last recorded length, dropping a thread that was only half written, and as many
+// threads of each key are skipped as the state lists, so a key that comes up more than
+// once in a run (a file read twice with `--allow-duplicate-files`) is skipped only as
+// often as it was written. The CSV header counts as part of the first thread's output,
+// so it is written once, on a fresh start.
+pub struct AppendWriter {
+    out: BufWriter<File>,
+    state: File,
+    options: OutputOptions,
+    /// How many threads of each key the output holds that haven't come up again yet
+    written: HashMap<String, usize>,
+    /// Bytes of output that are complete, as last recorded in the state
+    offset: u64,
+    threads: usize,
+    skipped: usize,
+}
+
+impl AppendWriter {
+    // Start a fresh output and state, or with `append` resume the ones left behind
+    pub fn open(output: &Path, state: &Path, append: bool, options: OutputOptions) -> Result<Self> {
+        if !append {
+            let out = File::create(output).with_context(|| format!("Failed to create output file: {:?}", output))?;
+            let state = File::create(state).with_context(|| format!("Failed to create state file: {:?}", state))?;
+            return Ok(AppendWriter::new(out, state, options, HashMap::new(), 0));
+        }
+        let (written, offset) = load_state(output, state)?;
+        let out = open_append(output)?;
+        let length = out.metadata()?.len();
+        if length < offset {
+            anyhow::bail!(
+                "{:?} is shorter than the state file {:?} records ({} of {} bytes); it was changed since",
+                output,
+                state,
+                length,
+                offset
+            );
+        }
+        if length > offset {
+            info!("Dropping {} bytes of incomplete output from {:?}", length - offset, output);
+            out.set_len(offset).with_context(|| format!("Failed to truncate output file: {:?}", output))?;
+        }
+        Ok(AppendWriter::new(out, open_append(state)?, options, written, offset))
+    }
+
+    fn new(out: File, state: File, options: OutputOptions, written: HashMap<String, usize>, offset: u64) -> Self {
+        // Every NDJSON line is one thread, whatever `--compact` says
+        let options = match options.format {
+            OutputFormat::Ndjson => OutputOptions { compact_json: true, ..options },
+            _ => options,
+        };
+        AppendWriter { out: BufWriter::new(out), state, options, written, offset, threads: 0, skipped: 0 }
+    }
+
+    // The output of one thread, line break included
+    fn entry(&self, thread: &Thread) -> Result<String> {
+        let entry = match self.options.format {
+            OutputFormat::Csv if self.offset == 0 => format!("{}\n{}", CSV_HEADER, csv_rows(thread)),
+            OutputFormat::Csv => csv_rows(thread),
+            _ => format!("{}\n", to_json(thread, &self.options)?),
+        };
+        Ok(entry)
+    }
+}
+
+impl ThreadWriter for AppendWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        let key = thread_key(&thread.thread_id, &thread.source_path);
+        if let Some(count) = self.written.get_mut(&key).filter(|count| **count > 0) {
+            *count -= 1;
+            self.skipped += 1;
+            return Ok(());
+        }
+        let entry = self.entry(&thread)?;
+        self.out.write_all(entry.as_bytes()).context("Failed to write output")?;
+        self.out.flush().context("Failed to flush output")?;
+        self.offset += entry.len() as u64;
+        writeln!(self.state, "{}\t{}", self.offset, key).context("Failed to write state file")?;
+        self.threads += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush().context("Failed to flush output")?;
+        self.state.sync_data().context("Failed to sync state file")?;
+        if self.skipped > 0 {
+            info!("Skipped {} threads already written according to the state file", self.skipped);
+        }
+        info!("Appended {} threads", self.threads);
+        Ok(())
+    }
+}
+
+fn thread_key(thread_id: &str, source_path: &str) -> String {
+    format!("{}\t{}", thread_id, source_path)
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {:?} for appending", path))
+}
+
+// The threads a previous run wrote and the output length after the last of them. A
+// line cut short by the interruption is removed from the state, as the thread it
+// stands for is past the recorded length. A missing state is only accepted when there
+// is no output to resume either, since resuming from nothing would throw the existing
+// output away.
+fn load_state(output: &Path, state: &Path) -> Result<(HashMap<String, usize>, u64)> {
+    if !state.exists() {
+        let has_output = fs::metadata(output).map(|m| m.len() > 0).unwrap_or(false);
+        if has_output {
+            anyhow::bail!(
+                "--append: the state file {:?} doesn't exist, so it isn't known what {:?} holds",
+                state,
+                output
+            );
+        }
+        return Ok((HashMap::new(), 0));
+    }
+    let content = fs::read_to_string(state).with_context(|| format!("Failed to read state file: {:?}", state))?;
+    let mut written = HashMap::new();
+    let mut threads = 0;
+    let mut offset = 0;
+    let mut complete = 0;
+    for (number, line) in content.split_inclusive('\n').enumerate() {
+        let Some(line) = line.strip_suffix('\n') else {
+            break;
+        };
+        let (length, key) = line
+            .split_once('\t')
+            .with_context(|| format!("Invalid line {} in the state file {:?}", number + 1, state))?;
+        offset = length
+            .parse()
+            .with_context(|| format!("Invalid length on line {} of the state file {:?}", number + 1, state))?;
+        *written.entry(key.to_string()).or_insert(0) += 1;
+        threads += 1;
+        complete += line.len() + 1;
+    }
+    if complete < content.len() {
+        info!("Dropping an incomplete last line from the state file {:?}", state);
+        open_append(state)?
+            .set_len(complete as u64)
+            .with_context(|| format!("Failed to truncate state file: {:?}", state))?;
+    }
+    info!("Resuming {:?} after {} threads ({} bytes)", output, threads, offset);
+    Ok((written, offset))
+}
@@ -0,0 +1,85 @@
+use anyhow::{ Context, Result };
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use crate::writer::ThreadWriter;
+use crate::Thread;
+use log::info;
+
+// A single self-describing JSON document for the whole run:
+// `{"metadata": {...}, "threads": [...], "statistics": {...}}`. The metadata is written
+// up front and the statistics last, so threads stream straight through (one per line)
+// instead of being collected in memory.
+pub struct ArchiveWriter {
+    out: Box<dyn Write>,
+    threads: usize,
+    messages: usize,
+    participants: HashSet<String>,
+    source_files: HashSet<String>,
+}
+
+#[derive(Serialize)]
+struct Metadata {
+    tool: &'static str,
+    version: &'static str,
+    run_timestamp: String,
+    source_root: String,
+    /// Command-line arguments the run was started with, excluding the program name
+    arguments: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Statistics {
+    source_files: usize,
+    threads: usize,
+    messages: usize,
+    unique_participants: usize,
+}
+
+impl ArchiveWriter {
+    pub fn create(out: Box<dyn Write>, source_root: &Path) -> Result<Self> {
+        let metadata = Metadata {
+            tool: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            run_timestamp: Utc::now().to_rfc3339(),
+            source_root: source_root.to_string_lossy().to_string(),
+            arguments: std::env::args().skip(1).collect(),
+        };
+        let mut writer = ArchiveWriter {
+            out,
+            threads: 0,
+            messages: 0,
+            participants: HashSet::new(),
+            source_files: HashSet::new(),
+        };
+        write!(writer.out, "{{\n\"metadata\": {},\n\"threads\": [", serde_json::to_string(&metadata)?)?;
+        Ok(writer)
+    }
+}
+
+impl ThreadWriter for ArchiveWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        let separator = if self.threads == 0 { "\n" } else { ",\n" };
+        write!(self.out, "{}{}", separator, serde_json::to_string(&thread)?)?;
+        self.threads += 1;
+        self.messages += thread.message_count;
+        self.participants.extend(thread.participants.into_iter().map(|p| p.phone));
+        self.source_files.insert(thread.source_path);
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let statistics = Statistics {
+            source_files: self.source_files.len(),
+            threads: self.threads,
+            messages: self.messages,
+            unique_participants: self.participants.len(),
+        };
+        writeln!(self.out, "\n],\n\"statistics\": {}\n}}", serde_json::to_string(&statistics)?)?;
+        self.out.flush().context("Failed to flush archive output")?;
+        info!("Archive output complete. Total threads written: {}", self.threads);
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+// Benchmarks on exports generated at run time, too slow for every `cargo test` and only
+// meaningful in a release build, so they are ignored by default. Run them one at a time,
+// since memory is measured for the whole process:
+//
+//     cargo test --release bench -- --ignored --nocapture --test-threads=1
+use clap::Parser;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, Instant };
+use crate::{
+    parse_html,
+    parse_threads,
+    selectors,
+    Cli,
+    ParseOptions,
+    ATTACHMENT_SELECTOR,
+    CONTENT_SELECTOR,
+    DATETIME_SELECTOR,
+    NAME_SELECTOR,
+    READ_BUFFER,
+    READ_BUFFER_RETAIN,
+    REPLY_SELECTOR,
+    REPLY_SENDER_SELECTOR,
+    SENDER_SELECTOR,
+    TEL_SELECTOR,
+};
+
+fn options(args: &[&str]) -> ParseOptions {
+    let cli = Cli::parse_from(["google_voice_importer", "test"].into_iter().chain(args.iter().copied()));
+    ParseOptions::from_cli(&cli).unwrap()
+}
+
+// A one-on-one export with Pat of `messages` messages, taking turns a minute apart
+fn large_export(messages: usize) -> String {
+    let mut html = String::from(
+        "<html><head><title>Pat Lee</title></head><body><div class=\"hChatLog hfeed\">\n"
+    );
+    for index in 0..messages {
+        let time = chrono::DateTime::UNIX_EPOCH + chrono::Duration::minutes(index as i64);
+        let title = time.format("%Y-%m-%dT%H:%M:%S%.3f+00:00");
+        let sender = if index % 2 == 0 {
+            "<a class=\"tel\" href=\"tel:+16502074150\"><abbr class=\"fn\" title=\"\">Me</abbr></a>"
+        } else {
+            "<a class=\"tel\" href=\"tel:+12065550199\"><span class=\"fn\">Pat Lee</span></a>"
+        };
+        let _ = writeln!(
+            html,
+            "<div class=\"message\"><abbr class=\"dt\" title=\"{}\">{}</abbr>:\n\
+             <cite class=\"sender vcard\">{}</cite>:\n\
+             <q>Message number {} about the plans for the weekend,<br>with a second line</q></div>",
+            title,
+            title,
+            sender,
+            index
+        );
+    }
+    html.push_str("</div></body></html>\n");
+    html
+}
+
+// Write `export` to a file named like a Takeout text in a fresh directory
+fn write_export(name: &str, export: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gvi-bench-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("Pat Lee - Text - 2024-01-01T00_00_00Z.html");
+    fs::write(&path, export).unwrap();
+    path
+}
+
+// Peak resident memory of the process in KiB, after resetting it when the kernel allows
+fn peak_rss_kib() -> usize {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("VmHWM:"))
+                .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+        })
+        .unwrap_or(0)
+}
+
+fn reset_peak_rss() {
+    let _ = fs::write("/proc/self/clear_refs", "5");
+}
+
+// Peak memory while parsing a large file, read whole as before and through the reused
+// read buffer now, which drops the raw HTML once the DOM is built and gives back what
+// it holds past `READ_BUFFER_RETAIN`
+#[test]
+#[ignore]
+fn bench_large_file_memory() {
+    let path = write_export("memory", &large_export(150_000));
+    let size_mib = fs::metadata(&path).unwrap().len() / (1024 * 1024);
+    let options = options(&[]);
+
+    reset_peak_rss();
+    let before = peak_rss_kib();
+    let content = fs::read_to_string(&path).unwrap();
+    let whole = parse_html(&content, Path::new(&path), &options).unwrap();
+    let whole_peak = peak_rss_kib() - before;
+    drop((whole, content));
+
+    reset_peak_rss();
+    let before = peak_rss_kib();
+    let buffered = parse_threads(&path, &options).unwrap();
+    let buffered_peak = peak_rss_kib() - before;
+    let retained = READ_BUFFER.with(|buffer| buffer.borrow().capacity());
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    println!("{} MiB file, {} messages", size_mib, buffered[0].messages.len());
+    println!("  read whole:      peak +{} MiB", whole_peak / 1024);
+    println!("  through buffer:  peak +{} MiB", buffered_peak / 1024);
+    assert!(retained <= READ_BUFFER_RETAIN, "read buffer kept {} bytes", retained);
+}
+
+// Parse throughput on a long conversation, next to what cloning its messages costs, as
+// building the thread did before `messages` was moved into it
+#[test]
+#[ignore]
+fn bench_parse_throughput() {
+    let export = large_export(50_000);
+    let path = Path::new("Pat Lee - Text - 2024-01-01T00_00_00Z.html");
+    let options = options(&[]);
+    let runs = 5;
+    let start = Instant::now();
+    let mut threads = Vec::new();
+    for _ in 0..runs {
+        threads = parse_html(&export, path, &options).unwrap();
+    }
+    let parse = start.elapsed() / runs;
+    let start = Instant::now();
+    let copy = threads[0].messages.clone();
+    let clone = start.elapsed();
+
+    let mib = export.len() as f64 / (1024.0 * 1024.0);
+    println!("{} messages, {:.1} MiB", copy.len(), mib);
+    println!(
+        "  parse: {:?} ({:.0} messages/s, {:.1} MiB/s)",
+        parse,
+        copy.len() as f64 / parse.as_secs_f64(),
+        mib / parse.as_secs_f64()
+    );
+    println!("  cloning the messages: {:?} ({:.1}% of the parse)", clone, 100.0 * clone.as_secs_f64() / parse.as_secs_f64());
+}
+
+// Peak memory while `--jobs 4` parse corpora of 20 and 80 files for a writer slower than
+// them: the queue of parsed files is bounded, so the peak stays the same as the corpus
+// grows instead of holding every parsed file
+#[test]
+#[ignore]
+fn bench_concurrent_parse_memory() {
+    let dir = std::env::temp_dir().join(format!("gvi-bench-corpus-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let export = large_export(2_000);
+    let files: Vec<PathBuf> = (0..80)
+        .map(|index| {
+            let path = dir.join(format!("Pat Lee - Text - 2024-01-01T00_00_{:02}Z-{}.html", index % 60, index));
+            fs::write(&path, &export).unwrap();
+            path
+        })
+        .collect();
+    let options = options(&["--jobs", "4", "--channel-capacity", "2"]);
+
+    // Measured from the same start, so the second run raises the peak only if it needs
+    // more memory than the first did
+    reset_peak_rss();
+    let before = peak_rss_kib();
+    let mut peaks = Vec::new();
+    for corpus in [&files[..20], &files[..]] {
+        let mut written = 0;
+        crate::parse_concurrently(corpus, &options, |_, parsed| {
+            written += parsed?.len();
+            std::thread::sleep(Duration::from_millis(200));
+            Ok(())
+        }).unwrap();
+        assert_eq!(written, corpus.len());
+        peaks.push(peak_rss_kib() - before);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+
+    let file_mib = export.len() as f64 / (1024.0 * 1024.0);
+    println!("{:.1} MiB files, --jobs 4, --channel-capacity 2", file_mib);
+    println!("  20 files ({:.0} MiB):  peak +{} MiB", 20.0 * file_mib, peaks[0] / 1024);
+    println!("  80 files ({:.0} MiB):  peak +{} MiB", 80.0 * file_mib, peaks[1] / 1024);
+    assert!(peaks[1] < peaks[0] * 3 / 2, "{:?}", peaks);
+}
+
+// What compiling the per-message selectors for every message, as parsing did before they
+// were compiled once per run, costs next to parsing the same conversation
+#[test]
+#[ignore]
+fn bench_selector_compilation() {
+    let messages = 50_000;
+    let per_message = [
+        DATETIME_SELECTOR,
+        SENDER_SELECTOR,
+        NAME_SELECTOR,
+        TEL_SELECTOR,
+        CONTENT_SELECTOR,
+        ATTACHMENT_SELECTOR,
+        REPLY_SELECTOR,
+        REPLY_SENDER_SELECTOR,
+    ];
+    let start = Instant::now();
+    for _ in 0..messages {
+        for selector in per_message {
+            std::hint::black_box(crate::error::compile(selector).unwrap());
+        }
+    }
+    let compiling = start.elapsed();
+    selectors::compile().unwrap();
+    let start = Instant::now();
+    for _ in 0..messages {
+        std::hint::black_box(selectors::get());
+    }
+    let reusing = start.elapsed();
+
+    let export = large_export(messages);
+    let start = Instant::now();
+    parse_html(&export, Path::new("Pat Lee - Text - 2024-01-01T00_00_00Z.html"), &options(&[])).unwrap();
+    let parse = start.elapsed();
+
+    println!("{} messages, parsed in {:?}", messages, parse);
+    println!(
+        "  compiling {} selectors per message: {:?} ({:.1}% of the parse)",
+        per_message.len(),
+        compiling,
+        100.0 * compiling.as_secs_f64() / parse.as_secs_f64()
+    );
+    println!("  reusing the compiled set:          {:?}", reusing);
+    assert!(reusing < compiling);
+}
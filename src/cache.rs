@@ -0,0 +1,99 @@
+use anyhow::{ Context, Result };
+use std::fs;
+use std::io::{ BufReader, BufWriter, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use crate::error::ParseError;
+use crate::{ timestamp, Thread };
+use log::warn;
+
+// `--cache`: the threads parsed from each file, stored in CBOR under a directory so a
+// re-run with other output options only parses files that changed. An entry is keyed
+// by a hash of the file's content, its path (recorded in the threads) and the parse
+// options (`fingerprint`), so any of those changing is simply a miss. CBOR rather than
+// a schema-less format like bincode because the thread types skip empty fields and
+// flatten the message body, which only self-describing formats read back. Entries are
+// never evicted; deleting the directory clears the cache.
+#[derive(Debug)]
+pub struct ThreadCache {
+    dir: PathBuf,
+    fingerprint: String,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl ThreadCache {
+    pub fn open(dir: &Path, fingerprint: String) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(||
+            format!("Failed to create cache directory: {:?}", dir)
+        )?;
+        Ok(ThreadCache {
+            dir: dir.to_path_buf(),
+            fingerprint,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        })
+    }
+
+    // The file's threads from its cache entry, or from `parse` (stored for next time)
+    // when there is none. An unreadable entry is reparsed and replaced, and failing to
+    // store one only warns, as the run itself isn't affected.
+    pub fn parse(
+        &self,
+        filename: &Path,
+        parse: impl FnOnce() -> Result<Vec<Thread>, ParseError>
+    ) -> Result<Vec<Thread>, ParseError> {
+        let entry = self.entry_path(filename)?;
+        if entry.exists() {
+            match read_entry(&entry) {
+                Ok(threads) => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(threads);
+                }
+                Err(e) => warn!("Ignoring unreadable cache entry {:?}: {:#}", entry, e),
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let threads = parse()?;
+        if let Err(e) = write_entry(&entry, &threads) {
+            warn!("Failed to cache {:?}: {:#}", filename, e);
+        }
+        Ok(threads)
+    }
+
+    // Files loaded from the cache and files parsed, so far in the run
+    pub fn counts(&self) -> (usize, usize) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+
+    // `<dir>/<first two hex digits>/<hash>.cbor`, so no directory holds every entry
+    fn entry_path(&self, filename: &Path) -> Result<PathBuf, ParseError> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(self.fingerprint.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(filename.to_string_lossy().as_bytes());
+        hasher.update(&[0]);
+        hasher.update_reader(fs::File::open(filename)?)?;
+        let hash = hasher.finalize().to_hex();
+        Ok(self.dir.join(&hash[..2]).join(format!("{}.cbor", hash)))
+    }
+}
+
+fn read_entry(path: &Path) -> Result<Vec<Thread>> {
+    let reader = BufReader::new(fs::File::open(path)?);
+    Ok(ciborium::from_reader(reader)?)
+}
+
+// Written to a temporary file and renamed into place, so an interrupted run never
+// leaves a truncated entry behind
+fn write_entry(path: &Path, threads: &[Thread]) -> Result<()> {
+    let dir = path.parent().context("Cache entry without a directory")?;
+    fs::create_dir_all(dir)?;
+    let partial = path.with_extension(format!("cbor.{}.partial", std::process::id()));
+    let mut out = BufWriter::new(fs::File::create(&partial)?);
+    timestamp::canonical(|| ciborium::into_writer(threads, &mut out))?;
+    out.flush()?;
+    drop(out);
+    fs::rename(&partial, path)?;
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use anyhow::{ Context, Result };
+use std::collections::HashSet;
+use std::fs::{ File, OpenOptions };
+use std::io::{ BufRead, BufReader, Write };
+use std::path::Path;
+use log::info;
+
+// Append-only record of the source files whose threads have been committed to Neo4j,
+// one source path per line. Lines are only written after a batch commits, so on
+// resume every listed file can be skipped safely.
+pub struct Checkpoint {
+    file: File,
+}
+
+impl Checkpoint {
+    // Open the checkpoint for appending, creating it if it doesn't exist
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open checkpoint file: {:?}", path))?;
+        Ok(Checkpoint { file })
+    }
+
+    // Truncate the checkpoint so a fresh (non-resumed) import starts from nothing
+    pub fn reset(path: &Path) -> Result<()> {
+        File::create(path).with_context(|| format!("Failed to reset checkpoint file: {:?}", path))?;
+        Ok(())
+    }
+
+    // Load the set of already committed source paths; a missing file means nothing
+    // has been committed yet
+    pub fn load(path: &Path) -> Result<HashSet<String>> {
+        if !path.exists() {
+            return Ok(HashSet::new());
+        }
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open checkpoint file: {:?}", path))?;
+        let committed = BufReader::new(file)
+            .lines()
+            .collect::<Result<HashSet<String>, _>>()
+            .context("Failed to read checkpoint file")?;
+        info!("Loaded checkpoint with {} committed files", committed.len());
+        Ok(committed)
+    }
+
+    // Record committed source paths and sync them to disk
+    pub fn record<'a>(&mut self, sources: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        for source in sources {
+            writeln!(self.file, "{}", source).context("Failed to write checkpoint")?;
+        }
+        self.file.sync_data().context("Failed to sync checkpoint")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{ Context, Result };
+use std::collections::{ HashMap, VecDeque };
+use std::fs::{ self, File };
+use std::io::{ BufWriter, Write };
+use std::path::Path;
+use crate::{ Message, Thread };
+use log::info;
+
+const HEADER: &str = "checksum\tthread_id\tsource_path";
+
+// `--checksums` / `--verify-checksums`: a content checksum per thread for integrity
+// audits. Each message is hashed from its id (itself derived from sender, time and
+// text), sender, direction, kind, timestamp, content and attachments, and the thread's
+// checksum is the BLAKE3 hash of its message hashes in order, so any change to what a
+// message says, or to which messages a thread has and in what order, changes it. The
+// manifest is a TSV file with a `checksum thread_id source_path` header.
+pub struct Checksums {
+    out: Option<(BufWriter<File>, usize)>,
+    expected: Option<Expected>,
+}
+
+// Checksums of a manifest being verified, by thread id and source path. Manifests from
+// before thread ids included the source can list several threads of a file with the same
+// id (conversations with the same people), which are matched in order.
+struct Expected {
+    threads: HashMap<(String, String), VecDeque<String>>,
+    matched: usize,
+    changed: Vec<String>,
+    added: Vec<String>,
+}
+
+impl Checksums {
+    pub fn create(write: Option<&Path>, verify: Option<&Path>) -> Result<Self> {
+        let out = write
+            .map(|path| -> Result<_> {
+                let file = File::create(path).with_context(|| format!("Failed to create checksum manifest: {:?}", path))?;
+                let mut out = BufWriter::new(file);
+                writeln!(out, "{}", HEADER)?;
+                Ok((out, 0))
+            })
+            .transpose()?;
+        let expected = verify.map(load).transpose()?;
+        Ok(Checksums { out, expected })
+    }
+
+    pub fn record(&mut self, thread: &Thread) -> Result<()> {
+        let checksum = thread_checksum(thread);
+        if let Some((out, rows)) = &mut self.out {
+            writeln!(out, "{}\t{}\t{}", checksum, thread.thread_id, thread.source_path)?;
+            *rows += 1;
+        }
+        if let Some(expected) = &mut self.expected {
+            let key = (thread.thread_id.clone(), thread.source_path.clone());
+            let description = describe(&thread.thread_id, &thread.source_path);
+            match expected.threads.get_mut(&key).and_then(VecDeque::pop_front) {
+                Some(known) if known == checksum => expected.matched += 1,
+                Some(_) => expected.changed.push(description),
+                None => expected.added.push(description),
+            }
+        }
+        Ok(())
+    }
+
+    // Finish the manifest, then report how the run compares to the verified one. Returns
+    // the number of threads that differ: changed, new or missing from the run.
+    pub fn finish(self) -> Result<usize> {
+        if let Some((mut out, rows)) = self.out {
+            out.flush().context("Failed to write checksum manifest")?;
+            info!("Checksum manifest complete. Total threads: {}", rows);
+        }
+        let Some(expected) = self.expected else {
+            return Ok(0);
+        };
+        let mut missing: Vec<String> = expected.threads
+            .into_iter()
+            .flat_map(|((thread_id, source_path), left)| {
+                left.into_iter().map(move |_| describe(&thread_id, &source_path))
+            })
+            .collect();
+        missing.sort();
+        for thread in &expected.changed {
+            eprintln!("Changed: {}", thread);
+        }
+        for thread in &expected.added {
+            eprintln!("New: {}", thread);
+        }
+        for thread in &missing {
+            eprintln!("Missing: {}", thread);
+        }
+        eprintln!(
+            "Checksums: {} threads match, {} changed, {} new, {} missing",
+            expected.matched,
+            expected.changed.len(),
+            expected.added.len(),
+            missing.len()
+        );
+        Ok(expected.changed.len() + expected.added.len() + missing.len())
+    }
+}
+
+// Source and shortened id, as the threads of a file can share an id with another's
+fn describe(thread_id: &str, source_path: &str) -> String {
+    let short: String = thread_id.chars().take(12).collect();
+    format!("{} (thread {})", source_path, short)
+}
+
+fn load(path: &Path) -> Result<Expected> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read checksum manifest: {:?}", path))?;
+    let mut threads: HashMap<(String, String), VecDeque<String>> = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.is_empty() || (index == 0 && line == HEADER) {
+            continue;
+        }
+        let mut fields = line.splitn(3, '\t');
+        let (Some(checksum), Some(thread_id), Some(source_path)) = (fields.next(), fields.next(), fields.next()) else {
+            anyhow::bail!("Invalid line {} in checksum manifest {:?}", index + 1, path);
+        };
+        threads
+            .entry((thread_id.to_string(), source_path.to_string()))
+            .or_default()
+            .push_back(checksum.to_string());
+    }
+    Ok(Expected { threads, matched: 0, changed: Vec::new(), added: Vec::new() })
+}
+
+fn thread_checksum(thread: &Thread) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for message in &thread.messages {
+        hasher.update(message_hash(message).as_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+// Every field is followed by a NUL byte so neighbouring fields can't run together
+fn message_hash(message: &Message) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new();
+    let mut field = |value: &str| {
+        hasher.update(value.as_bytes());
+        hasher.update(b"\0");
+    };
+    field(&message.id);
+    field(&message.from.phone);
+    field(message.direction.as_str());
+    field(message.kind.as_str());
+    field(&message.timestamp.timestamp_millis().to_string());
+    // Media-only messages have no content, unlike an empty text
+    field(message.body.kind());
+    field(message.body.text().unwrap_or(""));
+    for attachment in message.body.media() {
+        field(&format!("{:?}", attachment.kind));
+        field(&attachment.src);
+    }
+    hasher.finalize()
+}
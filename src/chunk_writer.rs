@@ -0,0 +1,84 @@
+use anyhow::{ Context, Result };
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::path::{ Path, PathBuf };
+use crate::writer::{ to_json, ThreadWriter };
+use crate::{ OutputOptions, Thread };
+use log::info;
+
+// JSON output split across numbered files for `--chunk-output`: every `chunk_size`
+// threads go to `<prefix>-0001.json`, `<prefix>-0002.json`, ..., each a complete JSON
+// array that can be loaded on its own
+pub struct ChunkWriter {
+    prefix: PathBuf,
+    chunk_size: usize,
+    options: OutputOptions,
+    current: Option<BufWriter<File>>,
+    /// Threads written to the current chunk
+    in_chunk: usize,
+    chunks: usize,
+    threads: usize,
+}
+
+impl ChunkWriter {
+    pub fn new(prefix: &Path, chunk_size: usize, options: OutputOptions) -> Self {
+        ChunkWriter {
+            prefix: prefix.to_path_buf(),
+            chunk_size,
+            options,
+            current: None,
+            in_chunk: 0,
+            chunks: 0,
+            threads: 0,
+        }
+    }
+
+
+    fn close_chunk(&mut self) -> Result<()> {
+        if let Some(mut chunk) = self.current.take() {
+            writeln!(chunk, "\n]")?;
+            chunk.flush().context("Failed to flush output chunk")?;
+        }
+        Ok(())
+    }
+}
+
+impl ThreadWriter for ChunkWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        if self.current.is_some() && self.in_chunk == self.chunk_size {
+            self.close_chunk()?;
+        }
+        if self.current.is_none() {
+            let path = chunk_path(&self.prefix, self.chunks + 1);
+            let file = File::create(&path).with_context(||
+                format!("Failed to create output chunk: {:?}", path)
+            )?;
+            let mut chunk = BufWriter::new(file);
+            write!(chunk, "[")?;
+            self.current = Some(chunk);
+            self.chunks += 1;
+            self.in_chunk = 0;
+        }
+        let json = to_json(&thread, &self.options)?;
+        let chunk = self.current.as_mut().expect("chunk opened above");
+        let separator = if self.in_chunk == 0 { "\n" } else { ",\n" };
+        write!(chunk, "{}{}", separator, json)?;
+        self.in_chunk += 1;
+        self.threads += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.close_chunk()?;
+        info!("Chunked output complete. Total threads written: {}", self.threads);
+        eprintln!("Chunks written: {} ({} threads)", self.chunks, self.threads);
+        Ok(())
+    }
+}
+
+// `<prefix>-<number>.json`, numbered from 1
+pub fn chunk_path(prefix: &Path, number: usize) -> PathBuf {
+    let mut name = prefix.as_os_str().to_os_string();
+    name.push(format!("-{:04}.json", number));
+    PathBuf::from(name)
+}
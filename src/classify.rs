@@ -0,0 +1,172 @@
+use anyhow::{ Context, Result };
+use regex::{ Regex, RegexBuilder };
+use serde::{ Deserialize, Serialize };
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use crate::{ phone_digits, Participant };
+
+// What kind of sender a participant is, from `--classify-participants`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParticipantType {
+    Person,
+    /// A company or organization, by name
+    Business,
+    /// Short codes and other machine senders (verification codes, alerts)
+    Automated,
+}
+
+impl ParticipantType {
+    pub const ALL: [ParticipantType; 3] = [
+        ParticipantType::Person,
+        ParticipantType::Business,
+        ParticipantType::Automated,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ParticipantType::Person => "person",
+            ParticipantType::Business => "business",
+            ParticipantType::Automated => "automated",
+        }
+    }
+}
+
+// The `--participant-rules` file (TOML). Every key is optional and replaces the
+// built-in default for that rule only.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Rules {
+    /// Numbers of at most this many digits (and no other characters) are short codes
+    short_code_max_digits: usize,
+    /// Names or phone numbers that are always businesses
+    businesses: Vec<String>,
+    /// Names or phone numbers that are always automated
+    automated: Vec<String>,
+    /// Case-insensitive regexes for the names of businesses
+    business_patterns: Vec<String>,
+    /// Case-insensitive regexes for the names of automated senders
+    automated_patterns: Vec<String>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            short_code_max_digits: 6,
+            businesses: Vec::new(),
+            automated: Vec::new(),
+            business_patterns: vec![
+                r"\b(inc|llc|ltd|corp|co|gmbh|plc)\b".to_string(),
+                concat!(
+                    r"\b(bank|credit union|insurance|pharmacy|clinic|hospital|dental|medical|salon|",
+                    r"restaurant|pizza|cafe|hotel|airlines?|realty|motors|services?|support|store|",
+                    r"shop|delivery)\b"
+                ).to_string()
+            ],
+            automated_patterns: vec![
+                r"\b(no-?reply|do not reply|alerts?|notifications?|verify|verification)\b".to_string()
+            ],
+        }
+    }
+}
+
+// Heuristic person/business/automated classification. Checked in order: the explicit
+// lists (by phone digits or exact name), short codes, automated name patterns, business
+// name patterns; anyone left is a person.
+#[derive(Debug, Clone)]
+pub struct ParticipantClassifier {
+    short_code_max_digits: usize,
+    business_phones: HashSet<String>,
+    business_names: HashSet<String>,
+    automated_phones: HashSet<String>,
+    automated_names: HashSet<String>,
+    business_patterns: Vec<Regex>,
+    automated_patterns: Vec<Regex>,
+}
+
+impl ParticipantClassifier {
+    // The built-in rules, or those of a rules file where it sets them
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let rules = match path {
+            Some(path) => {
+                let content = fs::read_to_string(path).with_context(||
+                    format!("Failed to read participant rules: {:?}", path)
+                )?;
+                toml::from_str(&content).with_context(|| format!("Invalid participant rules: {:?}", path))?
+            }
+            None => Rules::default(),
+        };
+        let (business_phones, business_names) = split_entries(&rules.businesses);
+        let (automated_phones, automated_names) = split_entries(&rules.automated);
+        Ok(ParticipantClassifier {
+            short_code_max_digits: rules.short_code_max_digits,
+            business_phones,
+            business_names,
+            automated_phones,
+            automated_names,
+            business_patterns: compile(&rules.business_patterns)?,
+            automated_patterns: compile(&rules.automated_patterns)?,
+        })
+    }
+
+    pub fn classify(&self, participant: &Participant) -> ParticipantType {
+        let digits = phone_digits(&participant.phone);
+        let name = participant.name.trim();
+        let listed = |phones: &HashSet<String>, names: &HashSet<String>| {
+            (!digits.is_empty() && phones.contains(&digits)) || names.contains(name)
+        };
+        if listed(&self.automated_phones, &self.automated_names) {
+            return ParticipantType::Automated;
+        }
+        if listed(&self.business_phones, &self.business_names) {
+            return ParticipantType::Business;
+        }
+        if self.is_short_code(&participant.phone) || self.automated_patterns.iter().any(|p| p.is_match(name)) {
+            return ParticipantType::Automated;
+        }
+        if self.business_patterns.iter().any(|p| p.is_match(name)) {
+            return ParticipantType::Business;
+        }
+        ParticipantType::Person
+    }
+
+    // `22395` or `+22395`; placeholders for unknown senders contain digits from their
+    // hash, so anything but digits and a leading `+` rules a number out
+    fn is_short_code(&self, phone: &str) -> bool {
+        let number = phone.trim().strip_prefix('+').unwrap_or(phone.trim());
+        !number.is_empty() &&
+            number.len() <= self.short_code_max_digits &&
+            number.chars().all(|c| c.is_ascii_digit())
+    }
+}
+
+// List entries with digits and nothing but phone punctuation are numbers, others names
+fn split_entries(entries: &[String]) -> (HashSet<String>, HashSet<String>) {
+    let mut phones = HashSet::new();
+    let mut names = HashSet::new();
+    for entry in entries {
+        let entry = entry.trim();
+        let is_phone =
+            entry.chars().any(|c| c.is_ascii_digit()) &&
+            entry.chars().all(|c| c.is_ascii_digit() || "+-() .".contains(c));
+        if is_phone {
+            phones.insert(phone_digits(entry));
+        } else {
+            names.insert(entry.to_string());
+        }
+    }
+    (phones, names)
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .with_context(|| format!("Invalid participant rule pattern: {:?}", pattern))
+        })
+        .collect()
+}
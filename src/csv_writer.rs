@@ -0,0 +1,46 @@
+use anyhow::{ Context, Result };
+use std::io::Write;
+use crate::timeline::{ csv_row, participant_labels, CSV_HEADER };
+use crate::writer::ThreadWriter;
+use crate::{ is_group_thread, Thread };
+use log::info;
+
+// One CSV row per message with its thread's context, in the columns of a `.csv`
+// `--timeline` but streamed in thread order instead of sorted by time
+pub struct CsvWriter {
+    out: Box<dyn Write>,
+    messages: usize,
+}
+
+impl CsvWriter {
+    pub fn create(mut out: Box<dyn Write>) -> Result<Self> {
+        writeln!(out, "{}", CSV_HEADER)?;
+        Ok(CsvWriter { out, messages: 0 })
+    }
+}
+
+impl ThreadWriter for CsvWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        write!(self.out, "{}", csv_rows(&thread))?;
+        self.messages += thread.messages.len();
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush().context("Failed to flush output")?;
+        info!("CSV output complete. Total messages written: {}", self.messages);
+        Ok(())
+    }
+}
+
+// The rows of a thread's messages, each ending in a line break
+pub fn csv_rows(thread: &Thread) -> String {
+    let participants = participant_labels(thread);
+    let is_group = is_group_thread(thread);
+    let mut rows = String::new();
+    for message in &thread.messages {
+        rows.push_str(&csv_row(thread, message, &participants, is_group));
+        rows.push('\n');
+    }
+    rows
+}
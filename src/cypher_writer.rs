@@ -1,42 +1,171 @@
 use anyhow::{ Result, Context };
-use neo4rs::{ Graph, Query, BoltMap, BoltType, BoltString, BoltList };
-use tokio::sync::mpsc::Receiver;
+use neo4rs::{ Graph, Query, Txn, BoltMap, BoltType, BoltString, BoltList, BoltNull, BoltInteger };
+use tokio::sync::mpsc::{ self, Receiver, Sender };
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::{ Duration, Instant };
+use crate::checkpoint::Checkpoint;
+use crate::timestamp::{ self, TimestampValue };
+use crate::writer::ThreadWriter;
 use crate::{
+    MessageKind,
     Thread,
-    Message,
-    Participant,
     THREAD_BATCH_SIZE,
     NEO4J_URI,
     NEO4J_USER,
     NEO4J_PASSWORD,
 };
-use log::{ info, warn };
+use log::{ error, info };
 
-pub async fn neo4j_writer(mut rx: Receiver<Thread>) -> Result<()> {
+// Hands threads to `neo4j_writer`, which runs on its own thread with a Tokio runtime,
+// over a bounded channel. One writer serves the whole run, so batches span files.
+// Threads are sent in groups of `send_batch` rather than one by one, so runs of many tiny
+// files don't pay a channel handoff per thread; the rest are sent by `finish`.
+pub struct Neo4jWriter {
+    checkpoint: Option<PathBuf>,
+    channel_capacity: usize,
+    send_batch: usize,
+    /// Threads not yet sent to the writer
+    pending: Vec<Thread>,
+    max_rate: Option<f64>,
+    target: GraphTarget,
+    /// Maintain `CONVERSED_WITH` relationships, see `conversed_deltas`
+    conversed_with: bool,
+    tx: Option<Sender<Vec<Thread>>>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl Neo4jWriter {
+    pub fn new(
+        checkpoint: Option<PathBuf>,
+        channel_capacity: usize,
+        send_batch: usize,
+        max_rate: Option<f64>,
+        target: GraphTarget,
+        conversed_with: bool
+    ) -> Self {
+        Neo4jWriter {
+            checkpoint,
+            channel_capacity,
+            send_batch: send_batch.max(1),
+            pending: Vec::with_capacity(send_batch),
+            max_rate,
+            target,
+            conversed_with,
+            tx: None,
+            handle: None,
+        }
+    }
+
+    // Wait for the writer thread and return its result
+    fn join(&mut self) -> Result<()> {
+        match self.handle.take() {
+            Some(handle) =>
+                handle
+                    .join()
+                    .map_err(|e| anyhow::anyhow!("Failed to join Neo4j writer thread: {:?}", e))?,
+            None => Ok(()),
+        }
+    }
+
+    // Send the pending threads to the writer thread
+    fn send_pending(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let tx = self.tx.as_ref().context("Neo4j writer was not started")?;
+        let threads = std::mem::replace(&mut self.pending, Vec::with_capacity(self.send_batch));
+        // Use blocking send for synchronous context
+        if tx.blocking_send(threads).is_err() {
+            // The writer only hangs up early when it failed, so report its error
+            self.tx = None;
+            self.join()?;
+            anyhow::bail!("Neo4j writer stopped before all threads were sent");
+        }
+        Ok(())
+    }
+}
+
+impl ThreadWriter for Neo4jWriter {
+    fn begin(&mut self) -> Result<()> {
+        // The capacity is given in threads
+        let (tx, rx) = mpsc::channel(self.channel_capacity.div_ceil(self.send_batch).max(1));
+        let checkpoint = self.checkpoint.clone();
+        let limiter = self.max_rate.map(RateLimiter::new);
+        let target = self.target.clone();
+        let conversed_with = self.conversed_with;
+        self.handle = Some(
+            std::thread::spawn(move || {
+                let rt = tokio::runtime::Runtime::new().context("Failed to start Tokio runtime")?;
+                let result = rt.block_on(neo4j_writer(rx, checkpoint, limiter, target, conversed_with));
+                if let Err(e) = &result {
+                    error!("Error in Neo4j writer: {:?}", e);
+                }
+                result
+            })
+        );
+        self.tx = Some(tx);
+        Ok(())
+    }
+
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        self.pending.push(thread);
+        if self.pending.len() >= self.send_batch {
+            self.send_pending()?;
+        }
+        Ok(())
+    }
+
+    // Send what is left, then drop the sender so the writer commits its last batch and
+    // wait for it
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.send_pending()?;
+        self.tx = None;
+        self.join()
+    }
+}
+
+// Consume threads from `rx` and write them to Neo4j in batches of whole files, see
+// `FileBatches`. When a checkpoint path is given, the source paths of each committed
+// batch are appended to it so an interrupted import can be resumed. With a `limiter`,
+// each batch waits for its messages' share of the write rate before it is sent.
+pub async fn neo4j_writer(
+    mut rx: Receiver<Vec<Thread>>,
+    checkpoint: Option<PathBuf>,
+    mut limiter: Option<RateLimiter>,
+    target: GraphTarget,
+    conversed_with: bool
+) -> Result<()> {
     info!("Starting Neo4j writer");
-    let graph = Graph::new(NEO4J_URI, NEO4J_USER, NEO4J_PASSWORD).await.context(
-        "Failed to connect to Neo4j"
-    )?;
+    let mut checkpoint = checkpoint.as_deref().map(Checkpoint::open).transpose()?;
+    let graph = connect().await?;
     info!("Connected to Neo4j successfully");
-    let mut batch = Vec::new();
+    ensure_constraints(&graph, &target).await?;
+    let mut batches = FileBatches::new(THREAD_BATCH_SIZE);
     let mut total_threads = 0;
 
-    while let Some(thread) = rx.recv().await {
-        batch.push(thread);
-        total_threads += 1;
-
-        if batch.len() >= THREAD_BATCH_SIZE {
-            info!("Sending batch of {} threads to Neo4j", batch.len());
-            send_batch_to_neo4j(&graph, &batch).await?;
-            info!("Batch sent successfully. Total threads processed: {}", total_threads);
-            batch.clear();
+    while let Some(threads) = rx.recv().await {
+        for thread in threads {
+            if let Some(batch) = batches.push(thread) {
+                total_threads += batch.len();
+                throttle(limiter.as_mut(), &batch).await;
+                info!("Sending batch of {} threads to Neo4j", batch.len());
+                send_batch_to_neo4j(&graph, &target, &batch, conversed_with).await?;
+                record_checkpoint(checkpoint.as_mut(), &batch)?;
+                info!("Batch sent successfully. Total threads processed: {}", total_threads);
+            }
         }
     }
 
     // Send any remaining threads
+    let batch = batches.finish();
     if !batch.is_empty() {
+        total_threads += batch.len();
+        throttle(limiter.as_mut(), &batch).await;
         info!("Sending final batch of {} threads to Neo4j", batch.len());
-        send_batch_to_neo4j(&graph, &batch).await?;
+        send_batch_to_neo4j(&graph, &target, &batch, conversed_with).await?;
+        record_checkpoint(checkpoint.as_mut(), &batch)?;
         info!("Final batch sent successfully. Total threads processed: {}", total_threads);
     }
 
@@ -44,45 +173,443 @@ pub async fn neo4j_writer(mut rx: Receiver<Thread>) -> Result<()> {
     Ok(())
 }
 
-async fn send_batch_to_neo4j(graph: &Graph, batch: &[Thread]) -> Result<()> {
+// Groups threads into transactions of at least `size`, cut only between source files.
+// The checkpoint lists a batch's files once it commits and `--resume` skips them, so a
+// file whose threads were split over two batches would lose the second half if the run
+// stopped in between.
+struct FileBatches {
+    size: usize,
+    pending: Vec<Thread>,
+}
+
+impl FileBatches {
+    fn new(size: usize) -> Self {
+        FileBatches { size, pending: Vec::new() }
+    }
+
+    // Add a thread, returning the pending batch when it is full and `thread` starts
+    // another file; `thread` then begins the next batch
+    fn push(&mut self, thread: Thread) -> Option<Vec<Thread>> {
+        let starts_file = self.pending
+            .last()
+            .is_some_and(|last| last.source_path != thread.source_path);
+        let full = (self.pending.len() >= self.size && starts_file).then(||
+            std::mem::take(&mut self.pending)
+        );
+        self.pending.push(thread);
+        full
+    }
+
+    // The threads left once the input ends
+    fn finish(self) -> Vec<Thread> {
+        self.pending
+    }
+}
+
+// Where in the server the import goes: `--neo4j-database` (the server's default
+// database when unset) and `--node-label-prefix`, which is put in front of the
+// `Participant`, `Conversation` and `Message` labels so several datasets can share
+// one database
+#[derive(Debug, Clone, Default)]
+pub struct GraphTarget {
+    pub database: Option<String>,
+    pub label_prefix: String,
+}
+
+impl GraphTarget {
+    fn label(&self, name: &str) -> String {
+        format!("{}{}", self.label_prefix, name)
+    }
+
+    // Single queries outside a transaction of ours; the driver runs each in its own and
+    // retries it with backoff on transient errors such as a leader election
+    async fn run(&self, graph: &Graph, query: Query) -> neo4rs::Result<()> {
+        match &self.database {
+            Some(database) => graph.run_on(database, query).await,
+            None => graph.run(query).await,
+        }
+    }
+
+    async fn count(&self, graph: &Graph, query: Query) -> Result<i64> {
+        let mut rows = match &self.database {
+            Some(database) => graph.execute_on(database, query).await?,
+            None => graph.execute(query).await?,
+        };
+        let row = rows.next().await?.context("Query returned no rows")?;
+        Ok(row.get("count")?)
+    }
+
+    async fn start_txn(&self, graph: &Graph) -> neo4rs::Result<Txn> {
+        match &self.database {
+            Some(database) => graph.start_txn_on(database.as_str()).await,
+            None => graph.start_txn().await,
+        }
+    }
+}
+
+// Properties every batch MERGEs nodes on, each given a uniqueness constraint so a MERGE
+// is an index lookup instead of a scan of every node with the label
+const CONSTRAINTS: [(&str, &str); 3] = [("Participant", "phone"), ("Conversation", "id"), ("Message", "id")];
+
+// Create the uniqueness constraints before the first batch. Schema changes can't share a
+// transaction with data writes, so each one is created on its own; `IF NOT EXISTS` makes
+// that a no-op on a graph that already has it, from an earlier run or created by hand
+// under another name. Existing ones are looked up first so the log tells them apart.
+async fn ensure_constraints(graph: &Graph, target: &GraphTarget) -> Result<()> {
+    for (name, property) in CONSTRAINTS {
+        let label = target.label(name);
+        let existing = Query::new(
+            "SHOW CONSTRAINTS YIELD labelsOrTypes, properties, type
+             WHERE labelsOrTypes = [$label] AND properties = [$property]
+                 AND (type CONTAINS 'UNIQUENESS' OR type CONTAINS 'KEY')
+             RETURN count(*) AS count".to_string()
+        )
+            .param("label", label.as_str())
+            .param("property", property);
+        let existing = target.count(graph, existing).await.with_context(||
+            format!("Failed to look up the constraints on :{}({})", label, property)
+        )?;
+        if existing > 0 {
+            info!("Constraint on :{}({}) already present", label, property);
+            continue;
+        }
+        let create = Query::new(
+            format!(
+                "CREATE CONSTRAINT `{label}_{property}_unique` IF NOT EXISTS
+                 FOR (n:{label}) REQUIRE n.{property} IS UNIQUE"
+            )
+        );
+        target.run(graph, create).await.with_context(||
+            format!("Failed to create a uniqueness constraint on :{}({})", label, property)
+        )?;
+        info!("Created uniqueness constraint on :{}({})", label, property);
+    }
+    Ok(())
+}
+
+// The configured database, for the writer and `--neo4j-check` alike
+async fn connect() -> Result<Graph> {
+    Graph::new(NEO4J_URI, NEO4J_USER, NEO4J_PASSWORD).await.context("Failed to connect to Neo4j")
+}
+
+// `--neo4j-check`: connect and create a node in a transaction that is rolled back, so
+// bad credentials or an unreachable or read-only database show up before an import
+// spends hours parsing. Each step's latency is printed.
+pub fn check(target: &GraphTarget) -> Result<()> {
+    let rt = tokio::runtime::Runtime::new().context("Failed to start Tokio runtime")?;
+    rt.block_on(async {
+        match &target.database {
+            Some(database) => println!("Checking Neo4j database {:?} at {} as {}", database, NEO4J_URI, NEO4J_USER),
+            None => println!("Checking Neo4j at {} as {}", NEO4J_URI, NEO4J_USER),
+        }
+        // The pool only opens a connection once it is first used
+        let start = Instant::now();
+        let graph = connect().await?;
+        let mut txn = target.start_txn(&graph).await.with_context(||
+            format!("Failed to connect to Neo4j at {} (after {})", NEO4J_URI, millis(start))
+        )?;
+        println!("  connected in {}", millis(start));
+
+        let start = Instant::now();
+        let write = Query::new(
+            format!("CREATE (:{} {{checked_at: datetime()}})", target.label("ImporterHealthCheck"))
+        );
+        txn.run(write).await.with_context(|| format!("Failed to write to Neo4j (after {})", millis(start)))?;
+        txn.rollback().await.context("Failed to roll back the Neo4j transaction")?;
+        println!("  wrote and rolled back in {}", millis(start));
+        println!("Neo4j is reachable and writable");
+        Ok(())
+    })
+}
+
+fn millis(start: Instant) -> String {
+    format!("{:.1} ms", start.elapsed().as_secs_f64() * 1000.0)
+}
+
+// Token bucket for `--neo4j-max-rate`: tokens (messages) accrue at `rate` per second up
+// to one second's worth, and a batch takes as many as it has messages. A batch needing
+// more than the bucket holds waits until the rest would have accrued, so the average
+// rate holds however batches are sized.
+pub struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64) -> Self {
+        RateLimiter { rate, tokens: rate, last: Instant::now() }
+    }
+
+    // Take `count` tokens, returning how long to wait before using them. The shortfall is
+    // left as a negative balance that the wait itself refills.
+    fn acquire(&mut self, count: usize) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate) - count as f64;
+        if self.tokens < 0.0 {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+async fn throttle(limiter: Option<&mut RateLimiter>, batch: &[Thread]) {
+    let Some(limiter) = limiter else {
+        return;
+    };
+    let messages = batch
+        .iter()
+        .map(|t| t.messages.len())
+        .sum();
+    let wait = limiter.acquire(messages);
+    if !wait.is_zero() {
+        info!("Waiting {:.1}s to stay under --neo4j-max-rate", wait.as_secs_f64());
+        tokio::time::sleep(wait).await;
+    }
+}
+
+fn record_checkpoint(checkpoint: Option<&mut Checkpoint>, batch: &[Thread]) -> Result<()> {
+    match checkpoint {
+        Some(checkpoint) => checkpoint.record(batch.iter().map(|t| t.source_path.as_str())),
+        None => Ok(()),
+    }
+}
+
+// Write a batch in a single transaction so a checkpoint only ever follows fully
+// committed data
+async fn send_batch_to_neo4j(
+    graph: &Graph,
+    target: &GraphTarget,
+    batch: &[Thread],
+    conversed_with: bool
+) -> Result<()> {
+    let participant = target.label("Participant");
+    let conversation = target.label("Conversation");
+    let message = target.label("Message");
+    let mut txn = target.start_txn(graph).await.context("Failed to start Neo4j transaction")?;
+    // Read before anything is merged, so it tells what this batch adds
+    let stored = if conversed_with { Some(read_stored(&mut txn, target, batch).await?) } else { None };
     // Create participants
     info!("Creating participants");
+    // Empty names arrive as null, so they never blank out a name another batch (or an
+    // earlier run) already stored for the same phone, see `merged_name`
     let participant_query = Query::new(
-        "UNWIND $participants AS participant
-         MERGE (p:Participant {phone: participant.phone})
-         SET p.name = participant.name".to_string()
+        format!(
+            "UNWIND $participants AS participant
+             MERGE (p:{participant} {{phone: participant.phone}})
+             SET p.name = coalesce(participant.name, p.name)"
+        )
     ).param("participants", participant_to_params(batch));
 
-    graph.run(participant_query).await?;
+    txn.run(participant_query).await?;
+    // Create conversations keyed by the stable thread id, so files and runs that carry
+    // the same conversation converge on one node
+    info!("Creating conversations");
+    let conversation_query = Query::new(
+        format!(
+            "UNWIND $threads AS thread
+             MERGE (c:{conversation} {{id: thread.id}})
+             WITH c, thread
+             UNWIND thread.phones AS phone
+             MATCH (p:{participant} {{phone: phone}})
+             MERGE (p)-[:PARTICIPATED_IN]->(c)"
+        )
+    ).param("threads", thread_to_params(batch));
+
+    txn.run(conversation_query).await?;
     info!("Creating messages and relationships");
     // Create messages and relationships
     let message_query = Query::new(
-        "UNWIND $messages AS message
-         MATCH (from:Participant {phone: message.from_phone})
-         WITH message, from
-         UNWIND message.to_phones AS to_phone
-         MATCH (to:Participant {phone: to_phone})
-         CREATE (m:Message {content: message.content, timestamp: message.timestamp})
-         CREATE (from)-[:SENT]->(m)
-         CREATE (m)-[:TO]->(to)".to_string()
+        format!(
+            "UNWIND $messages AS message
+             MATCH (from:{participant} {{phone: message.from_phone}})
+             MATCH (c:{conversation} {{id: message.thread_id}})
+             MERGE (m:{message} {{id: message.id}})
+             ON CREATE SET m.content = message.content, m.timestamp = message.timestamp,
+                 m.direction = message.direction
+             SET m.kind = message.kind
+             MERGE (from)-[:SENT]->(m)
+             MERGE (m)-[:IN]->(c)
+             WITH m, message
+             UNWIND message.to_phones AS to_phone
+             MATCH (to:{participant} {{phone: to_phone}})
+             MERGE (m)-[:TO]->(to)"
+        )
     ).param("messages", message_to_params(batch));
 
-    graph.run(message_query).await?;
+    txn.run(message_query).await?;
+    if let Some(stored) = stored {
+        // One relationship per pair, pointing from the lower phone to the higher so every
+        // batch finds the same one. Each batch adds what it brings, see `conversed_deltas`,
+        // in the transaction that writes it.
+        info!("Updating CONVERSED_WITH relationships");
+        let pair_query = Query::new(
+            format!(
+                "UNWIND $pairs AS pair
+                 MATCH (a:{participant} {{phone: pair.low}})
+                 MATCH (b:{participant} {{phone: pair.high}})
+                 MERGE (a)-[r:CONVERSED_WITH]->(b)
+                 ON CREATE SET r.thread_count = pair.threads, r.message_count = pair.messages
+                 ON MATCH SET r.thread_count = coalesce(r.thread_count, 0) + pair.threads,
+                     r.message_count = coalesce(r.message_count, 0) + pair.messages"
+            )
+        ).param("pairs", pairs_to_params(conversed_deltas(batch, stored)));
+        txn.run(pair_query).await?;
+    }
+    txn.commit().await.context("Failed to commit Neo4j transaction")?;
 
     Ok(())
 }
 
+// What the graph holds of a batch before it is written: the ids of its messages already
+// stored and, for each of its conversations, the phones already linked to it
+#[derive(Debug, Default)]
+struct Stored {
+    messages: HashSet<String>,
+    members: HashMap<String, HashSet<String>>,
+}
+
+async fn read_stored(txn: &mut Txn, target: &GraphTarget, batch: &[Thread]) -> Result<Stored> {
+    let message_ids: Vec<&str> = batch
+        .iter()
+        .flat_map(|thread| &thread.messages)
+        .map(|message| message.id.as_str())
+        .collect();
+    let thread_ids: Vec<&str> = batch
+        .iter()
+        .map(|thread| thread.thread_id.as_str())
+        .collect();
+    let mut stored = Stored::default();
+
+    let messages = Query::new(
+        format!("UNWIND $ids AS id MATCH (m:{} {{id: id}}) RETURN m.id AS id", target.label("Message"))
+    ).param("ids", message_ids);
+    let mut rows = txn.execute(messages).await.context("Failed to look up stored messages")?;
+    while let Some(row) = rows.next(txn.handle()).await? {
+        stored.messages.insert(row.get("id")?);
+    }
+
+    let members = Query::new(
+        format!(
+            "UNWIND $ids AS id
+             MATCH (p:{})-[:PARTICIPATED_IN]->(c:{} {{id: id}})
+             RETURN c.id AS id, collect(p.phone) AS phones",
+            target.label("Participant"),
+            target.label("Conversation")
+        )
+    ).param("ids", thread_ids);
+    let mut rows = txn.execute(members).await.context("Failed to look up stored conversations")?;
+    while let Some(row) = rows.next(txn.handle()).await? {
+        let phones: Vec<String> = row.get("phones")?;
+        stored.members.insert(row.get("id")?, phones.into_iter().collect());
+    }
+    Ok(stored)
+}
+
+// `--conversed-with`: how many threads and regular messages a batch adds to each pair
+// of participants, as their phones in order, given what the graph already `stored`. A
+// thread counts for a pair unless both were linked to its conversation before, and a
+// message for its sender and each recipient unless it is already in the graph, so a
+// replayed batch (a second run, or a resume after a crash between the commit and the
+// checkpoint) adds nothing. Participants without a phone are left out.
+fn conversed_deltas(batch: &[Thread], mut stored: Stored) -> BTreeMap<(&str, &str), (i64, i64)> {
+    let mut deltas: BTreeMap<(&str, &str), (i64, i64)> = BTreeMap::new();
+    for thread in batch {
+        let mut phones: Vec<&str> = thread.participants
+            .iter()
+            .map(|p| p.phone.as_str())
+            .filter(|phone| !phone.is_empty())
+            .collect();
+        phones.sort_unstable();
+        phones.dedup();
+        let members = stored.members.entry(thread.thread_id.clone()).or_default();
+        for (i, low) in phones.iter().enumerate() {
+            for high in &phones[i + 1..] {
+                if !(members.contains(*low) && members.contains(*high)) {
+                    deltas.entry((*low, *high)).or_default().0 += 1;
+                }
+            }
+        }
+        members.extend(phones.iter().map(|phone| phone.to_string()));
+
+        for message in &thread.messages {
+            if message.kind != MessageKind::Regular || !stored.messages.insert(message.id.clone()) {
+                continue;
+            }
+            let from = message.from.phone.as_str();
+            for to in &message.to {
+                let to = to.phone.as_str();
+                if from.is_empty() || to.is_empty() || from == to {
+                    continue;
+                }
+                deltas.entry((from.min(to), from.max(to))).or_default().1 += 1;
+            }
+        }
+    }
+    deltas.retain(|_, (threads, messages)| *threads > 0 || *messages > 0);
+    deltas
+}
+
+fn pairs_to_params(deltas: BTreeMap<(&str, &str), (i64, i64)>) -> BoltType {
+    let mut pairs = BoltList::new();
+    for ((low, high), (threads, messages)) in deltas {
+        let mut map = BoltMap::new();
+        map.put(BoltString::new("low"), BoltType::String(BoltString::new(low)));
+        map.put(BoltString::new("high"), BoltType::String(BoltString::new(high)));
+        map.put(BoltString::new("threads"), BoltType::Integer(BoltInteger::new(threads)));
+        map.put(BoltString::new("messages"), BoltType::Integer(BoltInteger::new(messages)));
+        pairs.push(BoltType::Map(map));
+    }
+    BoltType::List(pairs)
+}
+
+// One entry per phone in the batch, with the name `merged_name` settles on for it
 fn participant_to_params(batch: &[Thread]) -> BoltType {
+    let mut names: BTreeMap<&str, Option<&str>> = BTreeMap::new();
+    for p in batch.iter().flat_map(|thread| &thread.participants) {
+        let name = names.entry(&p.phone).or_default();
+        *name = merged_name(*name, &p.name);
+    }
     let mut participants = BoltList::new();
+    for (phone, name) in names {
+        let mut map = BoltMap::new();
+        map.put(BoltString::new("phone"), BoltType::String(BoltString::new(phone)));
+        let name = match name {
+            Some(name) => BoltType::String(BoltString::new(name)),
+            None => BoltType::Null(BoltNull),
+        };
+        map.put(BoltString::new("name"), name);
+        participants.push(BoltType::Map(map));
+    }
+    BoltType::List(participants)
+}
+
+// The name a participant has once `incoming` arrives for it on top of `stored`: a blank
+// name is no name and keeps the one already known. The participant query applies this
+// to the name in the graph with `coalesce`, as a `None` is sent as null.
+fn merged_name<'a>(stored: Option<&'a str>, incoming: &'a str) -> Option<&'a str> {
+    match incoming.trim() {
+        "" => stored,
+        name => Some(name),
+    }
+}
+
+fn thread_to_params(batch: &[Thread]) -> BoltType {
+    let mut threads = BoltList::new();
     for thread in batch {
+        let mut map = BoltMap::new();
+        map.put(BoltString::new("id"), BoltType::String(BoltString::new(&thread.thread_id)));
+        let mut phones = BoltList::new();
         for p in &thread.participants {
-            let mut map = BoltMap::new();
-            map.put(BoltString::new("phone"), BoltType::String(BoltString::new(&p.phone)));
-            map.put(BoltString::new("name"), BoltType::String(BoltString::new(&p.name)));
-            participants.push(BoltType::Map(map));
+            phones.push(BoltType::String(BoltString::new(&p.phone)));
         }
+        map.put(BoltString::new("phones"), BoltType::List(phones));
+        threads.push(BoltType::Map(map));
     }
-    BoltType::List(participants)
+    BoltType::List(threads)
 }
 
 fn message_to_params(batch: &[Thread]) -> BoltType {
@@ -90,6 +617,11 @@ fn message_to_params(batch: &[Thread]) -> BoltType {
     for thread in batch {
         for m in &thread.messages {
             let mut map = BoltMap::new();
+            map.put(BoltString::new("id"), BoltType::String(BoltString::new(&m.id)));
+            map.put(
+                BoltString::new("thread_id"),
+                BoltType::String(BoltString::new(&thread.thread_id))
+            );
             map.put(
                 BoltString::new("from_phone"),
                 BoltType::String(BoltString::new(&m.from.phone))
@@ -99,13 +631,174 @@ fn message_to_params(batch: &[Thread]) -> BoltType {
                 to_phones.push(BoltType::String(BoltString::new(&to.phone)));
             }
             map.put(BoltString::new("to_phones"), BoltType::List(to_phones));
-            map.put(BoltString::new("content"), BoltType::String(BoltString::new(&m.content)));
+            let content = match m.body.text() {
+                Some(content) => BoltType::String(BoltString::new(content)),
+                None => BoltType::Null(BoltNull),
+            };
+            map.put(BoltString::new("content"), content);
             map.put(
-                BoltString::new("timestamp"),
-                BoltType::String(BoltString::new(&m.timestamp.to_rfc3339()))
+                BoltString::new("direction"),
+                BoltType::String(BoltString::new(m.direction.as_str()))
             );
+            map.put(BoltString::new("kind"), BoltType::String(BoltString::new(m.kind.as_str())));
+            let timestamp = match timestamp::value(&m.timestamp) {
+                TimestampValue::Text(text) => BoltType::String(BoltString::new(&text)),
+                TimestampValue::Integer(value) => BoltType::Integer(BoltInteger::new(value)),
+            };
+            map.put(BoltString::new("timestamp"), timestamp);
             messages.push(BoltType::Map(map));
         }
     }
     BoltType::List(messages)
 }
+
+#[cfg(test)]
+mod tests {
+    use neo4rs::{ BoltNull, BoltString, BoltType };
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::time::{ Duration, Instant };
+    use tokio::sync::mpsc;
+    use crate::writer::ThreadWriter;
+    use crate::Thread;
+    use super::{
+        conversed_deltas,
+        merged_name,
+        participant_to_params,
+        FileBatches,
+        GraphTarget,
+        Neo4jWriter,
+        Stored,
+    };
+
+    fn fixture_thread() -> Thread {
+        let cli = <crate::Cli as clap::Parser>::parse_from(["google_voice_importer", "test"]);
+        let options = crate::ParseOptions::from_cli(&cli).unwrap();
+        let path = Path::new("test/+12028529407 - Text - 2021-08-05T17_05_39Z.html");
+        crate::parse_threads(path, &options).unwrap().remove(0)
+    }
+
+    // Write `count` copies of `thread` through a writer sending `send_batch` at a time to
+    // a stand-in for `neo4j_writer` that counts what it receives. Returns
+    // the threads it received, in how many sends, and how long writing and finishing took.
+    fn deliver(thread: &Thread, count: usize, send_batch: usize) -> (usize, usize, Duration) {
+        let mut writer = Box::new(Neo4jWriter::new(None, 100, send_batch, None, GraphTarget::default(), false));
+        let (tx, mut rx) = mpsc::channel::<Vec<Thread>>((100usize).div_ceil(send_batch));
+        let received = Arc::new(AtomicUsize::new(0));
+        let sends = Arc::new(AtomicUsize::new(0));
+        let (threads, batches) = (received.clone(), sends.clone());
+        writer.tx = Some(tx);
+        writer.handle = Some(
+            std::thread::spawn(move || {
+                while let Some(batch) = rx.blocking_recv() {
+                    batches.fetch_add(1, Ordering::SeqCst);
+                    threads.fetch_add(batch.len(), Ordering::SeqCst);
+                }
+                Ok(())
+            })
+        );
+        let start = Instant::now();
+        for _ in 0..count {
+            writer.write_thread(thread.clone()).unwrap();
+        }
+        writer.finish().unwrap();
+        let elapsed = start.elapsed();
+        (received.load(Ordering::SeqCst), sends.load(Ordering::SeqCst), elapsed)
+    }
+
+    #[test]
+    fn finish_sends_the_last_partial_batch() {
+        let (received, sends, _) = deliver(&fixture_thread(), 70, 32);
+        assert_eq!(received, 70);
+        assert_eq!(sends, 3);
+    }
+
+    // Handing 200,000 single-message threads to the writer thread one at a time and in
+    // micro-batches; see `bench.rs` for how to run the ignored benchmarks
+    #[test]
+    #[ignore]
+    fn bench_send_batches() {
+        let thread = fixture_thread();
+        let count = 200_000;
+        for send_batch in [1, 8, 32, 128] {
+            let (received, sends, elapsed) = deliver(&thread, count, send_batch);
+            assert_eq!(received, count);
+            println!("--neo4j-send-batch {:>3}: {:?} for {} sends", send_batch, elapsed, sends);
+        }
+    }
+
+    #[test]
+    fn a_file_is_never_split_across_batches() {
+        let thread = |source: &str| Thread { source_path: source.to_string(), ..fixture_thread() };
+        let mut batches = FileBatches::new(2);
+        let mut sent = Vec::new();
+        for source in ["a.html", "b.html", "b.html", "b.html", "c.html"] {
+            sent.extend(batches.push(thread(source)));
+        }
+        sent.push(batches.finish());
+        let sources: Vec<Vec<&str>> = sent
+            .iter()
+            .map(|batch| batch.iter().map(|t| t.source_path.as_str()).collect())
+            .collect();
+        assert_eq!(sources, [vec!["a.html", "b.html", "b.html", "b.html"], vec!["c.html"]]);
+    }
+
+    #[test]
+    fn replayed_batch_adds_nothing_to_conversed_with() {
+        let cli = <crate::Cli as clap::Parser>::parse_from(["google_voice_importer", "test"]);
+        let options = crate::ParseOptions::from_cli(&cli).unwrap();
+        let path = Path::new("test/Farah Aziz - Text - 2024-07-21T16_40_12Z.html");
+        let thread = crate::parse_threads(path, &options).unwrap().remove(0);
+        // The same file twice in one batch counts once
+        let batch = [thread.clone(), thread];
+        let pair = ("+13125550147", "+16502074150");
+
+        let first = conversed_deltas(&batch, Stored::default());
+        assert_eq!(first.into_iter().collect::<Vec<_>>(), [(pair, (1, 4))]);
+
+        // What the graph holds once the batch has committed
+        let mut stored = Stored::default();
+        stored.messages.extend(batch[0].messages.iter().map(|m| m.id.clone()));
+        stored.members.insert(batch[0].thread_id.clone(), [pair.0, pair.1].map(str::to_string).into());
+        assert!(conversed_deltas(&batch, stored).is_empty());
+    }
+
+    #[test]
+    fn blank_name_keeps_the_stored_one() {
+        assert_eq!(merged_name(Some("Ann Lee"), ""), Some("Ann Lee"));
+        assert_eq!(merged_name(Some("Ann Lee"), "  "), Some("Ann Lee"));
+        assert_eq!(merged_name(None, ""), None);
+        assert_eq!(merged_name(Some("Ann"), " Ann Lee "), Some("Ann Lee"));
+    }
+
+    #[test]
+    fn unnamed_participants_are_sent_without_a_name() {
+        let mut unnamed = fixture_thread();
+        unnamed.participants[0].name = " ".to_string();
+        let mut named = fixture_thread();
+        named.participants[0].name = "Ann Lee".to_string();
+        let phone = unnamed.participants[0].phone.clone();
+        let sent_name = |batch: &[Thread]| {
+            let BoltType::List(participants) = participant_to_params(batch) else {
+                panic!("participants are a list");
+            };
+            let name = participants
+                .iter()
+                .find_map(|participant| {
+                    let BoltType::Map(map) = participant else {
+                        return None;
+                    };
+                    let sent = map.value.get(&BoltString::new("phone"))?;
+                    (sent == &BoltType::String(BoltString::new(&phone))).then(|| map.value.get(&BoltString::new("name")))
+                })
+                .flatten()
+                .cloned()
+                .expect("the participant is sent with a name field");
+            name
+        };
+        assert_eq!(sent_name(std::slice::from_ref(&unnamed)), BoltType::Null(BoltNull));
+        // A blank name elsewhere in the batch doesn't hide a known one
+        assert_eq!(sent_name(&[named, unnamed]), BoltType::String(BoltString::new("Ann Lee")));
+    }
+}
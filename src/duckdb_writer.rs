@@ -0,0 +1,205 @@
+use anyhow::{ Context, Result };
+use duckdb::arrow::array::builder::{ ListBuilder, StringBuilder, TimestampMicrosecondBuilder, UInt64Builder };
+use duckdb::arrow::array::ArrayRef;
+use duckdb::arrow::datatypes::{ DataType, Field, Schema, SchemaRef, TimeUnit };
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::{ params, Connection };
+use std::path::Path;
+use std::sync::Arc;
+use crate::roster::Roster;
+use crate::Thread;
+use log::info;
+
+// Messages buffered before they are appended to the `messages` table as one batch
+const BATCH_SIZE: usize = 10_000;
+
+// Tables are replaced on every run, leaving any other tables in the database alone.
+// `messages` has the columns of the Parquet output and `participants` those of
+// `--participants-only`.
+const CREATE_TABLES: &str = "
+    CREATE OR REPLACE TABLE messages (
+        from_phone VARCHAR NOT NULL,
+        from_name VARCHAR NOT NULL,
+        to_phones VARCHAR[] NOT NULL,
+        direction VARCHAR NOT NULL,
+        timestamp TIMESTAMP NOT NULL,
+        content VARCHAR,
+        thread_id VARCHAR NOT NULL,
+        source_path VARCHAR NOT NULL,
+        labels VARCHAR[] NOT NULL,
+        thread_index UBIGINT,
+        message_index UBIGINT
+    );
+    CREATE OR REPLACE TABLE participants (
+        name VARCHAR NOT NULL,
+        phone VARCHAR NOT NULL,
+        message_count BIGINT NOT NULL,
+        threads BIGINT NOT NULL
+    );";
+
+// `--duckdb`: a DuckDB database with a row per message and per distinct participant,
+// filled through the appender. Messages are appended in Arrow batches of `BATCH_SIZE`;
+// participants are merged by phone digits across the run and appended at the end.
+pub struct DuckdbWriter {
+    conn: Connection,
+    schema: SchemaRef,
+    rows: RowBuilders,
+    buffered: usize,
+    total: usize,
+    roster: Roster,
+}
+
+struct RowBuilders {
+    from_phone: StringBuilder,
+    from_name: StringBuilder,
+    to_phones: ListBuilder<StringBuilder>,
+    direction: StringBuilder,
+    timestamp: TimestampMicrosecondBuilder,
+    content: StringBuilder,
+    thread_id: StringBuilder,
+    source_path: StringBuilder,
+    labels: ListBuilder<StringBuilder>,
+    thread_index: UInt64Builder,
+    message_index: UInt64Builder,
+}
+
+impl RowBuilders {
+    fn new() -> Self {
+        RowBuilders {
+            from_phone: StringBuilder::new(),
+            from_name: StringBuilder::new(),
+            to_phones: ListBuilder::new(StringBuilder::new()),
+            direction: StringBuilder::new(),
+            timestamp: TimestampMicrosecondBuilder::new(),
+            content: StringBuilder::new(),
+            thread_id: StringBuilder::new(),
+            source_path: StringBuilder::new(),
+            labels: ListBuilder::new(StringBuilder::new()),
+            thread_index: UInt64Builder::new(),
+            message_index: UInt64Builder::new(),
+        }
+    }
+
+    // Drain the builders into columns, leaving them empty for the next batch
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.from_phone.finish()),
+            Arc::new(self.from_name.finish()),
+            Arc::new(self.to_phones.finish()),
+            Arc::new(self.direction.finish()),
+            Arc::new(self.timestamp.finish()),
+            Arc::new(self.content.finish()),
+            Arc::new(self.thread_id.finish()),
+            Arc::new(self.source_path.finish()),
+            Arc::new(self.labels.finish()),
+            Arc::new(self.thread_index.finish()),
+            Arc::new(self.message_index.finish())
+        ]
+    }
+}
+
+// Arrow types the appender maps onto the `messages` columns; a timestamp without a
+// zone is a DuckDB `TIMESTAMP` (in UTC here)
+fn schema() -> SchemaRef {
+    let list_of_strings = DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)));
+    Arc::new(
+        Schema::new(
+            vec![
+                Field::new("from_phone", DataType::Utf8, false),
+                Field::new("from_name", DataType::Utf8, false),
+                Field::new("to_phones", list_of_strings.clone(), false),
+                Field::new("direction", DataType::Utf8, false),
+                Field::new("timestamp", DataType::Timestamp(TimeUnit::Microsecond, None), false),
+                Field::new("content", DataType::Utf8, true),
+                Field::new("thread_id", DataType::Utf8, false),
+                Field::new("source_path", DataType::Utf8, false),
+                Field::new("labels", list_of_strings, false),
+                Field::new("thread_index", DataType::UInt64, true),
+                Field::new("message_index", DataType::UInt64, true)
+            ]
+        )
+    )
+}
+
+impl DuckdbWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path).with_context(||
+            format!("Failed to open DuckDB database: {:?}", path)
+        )?;
+        conn.execute_batch(CREATE_TABLES).context("Failed to create DuckDB tables")?;
+        info!("Writing DuckDB output to {:?}", path);
+        Ok(DuckdbWriter {
+            conn,
+            schema: schema(),
+            rows: RowBuilders::new(),
+            buffered: 0,
+            total: 0,
+            roster: Roster::default(),
+        })
+    }
+
+    // Append every message of `thread`; rows carry the thread's id and source path
+    pub fn write_thread(&mut self, thread: &Thread) -> Result<()> {
+        self.roster.add(thread);
+        for m in &thread.messages {
+            let rows = &mut self.rows;
+            rows.from_phone.append_value(&m.from.phone);
+            rows.from_name.append_value(&m.from.name);
+            for to in &m.to {
+                rows.to_phones.values().append_value(&to.phone);
+            }
+            rows.to_phones.append(true);
+            rows.direction.append_value(m.direction.as_str());
+            rows.timestamp.append_value(m.timestamp.timestamp_micros());
+            rows.content.append_option(m.body.text());
+            rows.thread_id.append_value(&thread.thread_id);
+            rows.source_path.append_value(&thread.source_path);
+            for label in &thread.labels {
+                rows.labels.values().append_value(label);
+            }
+            rows.labels.append(true);
+            rows.thread_index.append_option(thread.thread_index.map(|index| index as u64));
+            rows.message_index.append_option(m.message_index.map(|index| index as u64));
+
+            self.buffered += 1;
+            if self.buffered >= BATCH_SIZE {
+                self.flush_batch()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_batch(&mut self) -> Result<()> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+        let batch = RecordBatch::try_new(self.schema.clone(), self.rows.finish()).context(
+            "Failed to build DuckDB record batch"
+        )?;
+        let mut appender = self.conn.appender("messages").context("Failed to append to DuckDB")?;
+        appender.append_record_batch(batch).context("Failed to append messages to DuckDB")?;
+        appender.flush().context("Failed to append messages to DuckDB")?;
+        self.total += self.buffered;
+        self.buffered = 0;
+        Ok(())
+    }
+
+    // Append the remaining messages and the participants
+    pub fn finish(mut self) -> Result<()> {
+        self.flush_batch()?;
+        let participants = self.roster.into_sorted();
+        let mut appender = self.conn.appender("participants").context("Failed to append to DuckDB")?;
+        for entry in &participants {
+            appender
+                .append_row(params![entry.name, entry.phone, entry.message_count as i64, entry.threads as i64])
+                .context("Failed to append participants to DuckDB")?;
+        }
+        appender.flush().context("Failed to append participants to DuckDB")?;
+        info!(
+            "DuckDB output complete. Total messages written: {}, participants: {}",
+            self.total,
+            participants.len()
+        );
+        Ok(())
+    }
+}
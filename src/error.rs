@@ -0,0 +1,36 @@
+use scraper::Selector;
+use std::io;
+
+// Why a file couldn't be parsed, so callers can tell failure modes apart instead of
+// matching on message strings. The CLI wraps these in `anyhow` like any other error.
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("Failed to read file")]
+    Io(#[from] io::Error),
+    /// Only with `--strict-encoding`; otherwise invalid bytes are replaced
+    #[error("File is not valid UTF-8 (invalid byte at offset {offset})")]
+    Encoding { offset: usize },
+    #[error("Invalid selector {selector:?}: {message}")]
+    Selector { selector: String, message: String },
+    /// A `.message` element without the `.sender` it is attributed by
+    #[error("Message {message_index} has no sender")]
+    MissingSender { message_index: usize },
+    /// Neither a conversation container nor any `.message` element, so most likely not
+    /// a Google Voice export at all
+    #[error("No conversation found in the document")]
+    EmptyDocument,
+    /// A `.json` input that isn't JSON at all
+    #[error("Invalid JSON")]
+    Json(#[from] serde_json::Error),
+    /// A JSON value of a `.json` input that isn't a thread as the `json` format writes them
+    #[error("Thread {index} doesn't match the thread schema:\n  {report}")]
+    ThreadSchema { index: usize, report: String },
+}
+
+// Compile a CSS selector, naming it in the error
+pub fn compile(selector: &str) -> Result<Selector, ParseError> {
+    Selector::parse(selector).map_err(|e| ParseError::Selector {
+        selector: selector.to_string(),
+        message: e.to_string(),
+    })
+}
@@ -0,0 +1,399 @@
+use anyhow::{ Context, Result };
+use chrono::DateTime;
+use scraper::{ ElementRef, Html };
+use std::path::Path;
+use crate::position::MessageSource;
+use crate::selectors;
+use crate::{
+    content_text,
+    conversation_self_phone,
+    unknown_self_phone,
+    error,
+    file_counterpart,
+    in_reply,
+    is_own_number,
+    known_phones,
+    message_kind,
+    parse_document,
+    parse_labels,
+    read_document,
+    sender_is_self,
+    sender_phone,
+    sender_tels,
+    split_conversations,
+    Direction,
+    MessageKind,
+    ParseOptions,
+    ATTACHMENT_SELECTOR,
+    CONTENT_SELECTOR,
+    CONVERSATION_SELECTOR,
+    DATETIME_FORMAT,
+    DATETIME_SELECTOR,
+    MESSAGE_SELECTOR,
+    PARTICIPANTS_SELECTOR,
+    REPLY_SELECTOR,
+    SELF_SENDER_CLASSES,
+    SENDER_SELECTOR,
+    TAGS_SELECTOR,
+    within,
+};
+
+// Every selector the parser uses, in the order the counts are listed
+const SELECTORS: [&str; 9] = [
+    MESSAGE_SELECTOR,
+    CONVERSATION_SELECTOR,
+    PARTICIPANTS_SELECTOR,
+    DATETIME_SELECTOR,
+    SENDER_SELECTOR,
+    CONTENT_SELECTOR,
+    REPLY_SELECTOR,
+    ATTACHMENT_SELECTOR,
+    TAGS_SELECTOR,
+];
+
+// Levels below `<body>` whose elements `--dump-selectors` lists
+const SHAPE_DEPTH: usize = 3;
+
+// Distinct element shapes `--dump-selectors` lists at most
+const SHAPE_SAMPLE: usize = 25;
+
+// `--explain`: a readable trace of how one file is parsed, for working out why an
+// export comes out wrong. Each message's timestamp, sender, self detection and direction
+// are explained by re-running the decisions the parser makes, and the trace ends with
+// what the parser itself made of the file, so the two can be compared.
+pub fn explain(path: &Path, options: &ParseOptions) -> Result<()> {
+    let (document, sources) = read_document(path, options).with_context(||
+        format!("Failed to read {:?}", path)
+    )?;
+    println!("Explaining {:?}", path);
+
+    print_selector_counts(&document)?;
+    let labels = parse_labels(&document);
+    if labels.is_empty() {
+        println!("Labels: none");
+    } else {
+        println!("Labels: {}", labels.join(", "));
+    }
+
+    // Sources are only matched to elements when there is one per element, as in parsing
+    let selectors = selectors::get();
+    let elements: Vec<_> = document
+        .select(&selectors.message)
+        .map(|el| el.id())
+        .collect();
+    let source_of = |element: &ElementRef| -> Option<&MessageSource> {
+        if sources.len() != elements.len() {
+            return None;
+        }
+        elements
+            .iter()
+            .position(|&id| id == element.id())
+            .map(|i| &sources[i])
+    };
+
+    let counterpart = file_counterpart(&document, path);
+    let conversations = split_conversations(&document);
+    for (index, conversation) in conversations.iter().enumerate() {
+        print!("\nConversation {} of {}: {} messages", index + 1, conversations.len(), conversation.messages.len());
+        match conversation.roster {
+            Some(roster) => {
+                let members = roster.select(&selectors.sender).count();
+                println!(", `{}` header listing {} members", PARTICIPANTS_SELECTOR, members);
+            }
+            None => println!(", no `{}` header", PARTICIPANTS_SELECTOR),
+        }
+
+        let known_phones = known_phones(conversation, &selectors.sender);
+        let known_self_phone = conversation_self_phone(conversation, &known_phones, options);
+        let self_phone = known_self_phone.clone().unwrap_or_else(|| unknown_self_phone(path));
+        let senders: Vec<Sender> = conversation.messages
+            .iter()
+            .map(|message| Sender::resolve(message, &known_phones, &self_phone, path, options))
+            .collect::<Result<_>>()?;
+        // Your number comes from your first message, or `--me-phone` without one
+        let me_phone = senders
+            .iter()
+            .find(|s| s.is_self)
+            .map(|s| s.phone.clone())
+            .or_else(|| options.me_phone.clone())
+            .unwrap_or_else(|| self_phone.clone());
+        let every_sender_is_you = !senders.is_empty() && senders.iter().all(|s| s.phone == me_phone);
+        let named_for_you = counterpart
+            .as_deref()
+            .is_some_and(|c| is_own_number(c, known_self_phone.as_deref().unwrap_or(""), options));
+        let is_self_thread = every_sender_is_you && named_for_you;
+        if is_self_thread {
+            println!("  Every sender is you and the file is named for your number, so this is a thread with yourself");
+        } else if every_sender_is_you {
+            println!(
+                "  Every sender is you, but the file is with {}, so the messages are to them",
+                counterpart.as_deref().unwrap_or("an unnamed contact")
+            );
+        }
+
+        for (message_index, (message, sender)) in conversation.messages.iter().zip(&senders).enumerate() {
+            let position = source_of(message)
+                .map(|s| format!(" ({})", s.position))
+                .unwrap_or_default();
+            println!("  Message {}{}", message_index + 1, position);
+            println!("    timestamp: {}", explain_timestamp(message, options)?);
+            println!("    sender: {}; phone {}", sender.name_description(), sender.phone_description);
+            println!("    self: {}", sender.self_description);
+            let direction = if is_self_thread || sender.is_self {
+                Direction::Outgoing
+            } else {
+                Direction::Incoming
+            };
+            let reason = if is_self_thread {
+                "thread with yourself"
+            } else if sender.is_self {
+                "sent by you"
+            } else {
+                "sent by a contact"
+            };
+            println!("    direction: {} ({})", direction.as_str(), reason);
+            println!("    {}", explain_body(message, sender.name.as_deref().unwrap_or(""), options)?);
+        }
+    }
+
+    println!("\nResult:");
+    match parse_document(&document, &sources, path, options) {
+        Ok(threads) => {
+            for thread in &threads {
+                let outgoing = thread.messages
+                    .iter()
+                    .filter(|m| m.direction == Direction::Outgoing)
+                    .count();
+                println!(
+                    "  Thread {}: {} messages ({} outgoing, {} incoming)",
+                    thread.thread_id,
+                    thread.messages.len(),
+                    outgoing,
+                    thread.messages.len() - outgoing
+                );
+                for participant in &thread.participants {
+                    println!("    participant: {:?} {}", participant.name, participant.phone);
+                }
+                for warning in &thread.warnings {
+                    println!("    warning: {}", warning);
+                }
+            }
+        }
+        Err(e) => println!("  failed to parse: {:#}", e),
+    }
+    Ok(())
+}
+
+// `--dump-selectors`: what a file contains as far as the parser's selectors are
+// concerned, for exports whose markup drifted from what the parser expects and parse
+// to nothing. The selector counts show which ones stopped matching, and the tags and
+// classes of the elements near the top of the document show what is there instead.
+pub fn dump_selectors(path: &Path, options: &ParseOptions) -> Result<()> {
+    let (document, sources) = read_document(path, options).with_context(||
+        format!("Failed to read {:?}", path)
+    )?;
+    println!("Dumping selectors for {:?}", path);
+    print_selector_counts(&document)?;
+
+    println!("\nElements up to {} levels below <body> (count, tag and classes):", SHAPE_DEPTH);
+    let shapes = element_shapes(&document)?;
+    for (depth, shape, count) in shapes.iter().take(SHAPE_SAMPLE) {
+        println!("  {:>5}  {}{}", count, "  ".repeat(depth - 1), shape);
+    }
+    if shapes.len() > SHAPE_SAMPLE {
+        println!("  ... and {} more", shapes.len() - SHAPE_SAMPLE);
+    }
+
+    let messages: usize = match parse_document(&document, &sources, path, options) {
+        Ok(threads) => threads.iter().map(|t| t.messages.len()).sum(),
+        Err(e) => {
+            println!("\nThe file fails to parse: {:#}", e);
+            return Ok(());
+        }
+    };
+    println!("\nMessages parsed: {}", messages);
+    if messages == 0 {
+        println!(
+            "No `{}` elements yielded a message; if the export uses other markup, the elements \
+             above show which tags and classes hold its messages",
+            MESSAGE_SELECTOR
+        );
+    }
+    Ok(())
+}
+
+fn print_selector_counts(document: &Html) -> Result<()> {
+    println!("\nSelectors (matches in the document):");
+    for selector in SELECTORS {
+        let matches = document.select(&error::compile(selector)?).count();
+        println!("  {:>5}  {}", matches, selector);
+    }
+    Ok(())
+}
+
+// The elements within `SHAPE_DEPTH` levels of `<body>` as `tag.class.class`, counted per
+// path from `<body>` and listed depth-first in order of first appearance, so the list
+// reads like an outline of the document
+fn element_shapes(document: &Html) -> Result<Vec<(usize, String, usize)>> {
+    let root = document
+        .select(&error::compile("body")?)
+        .next()
+        .unwrap_or_else(|| document.root_element());
+    // Shapes from the top down to the element, and how many elements have that path
+    let mut shapes: Vec<(Vec<String>, usize)> = Vec::new();
+    let mut stack: Vec<(ElementRef, Vec<String>)> = root
+        .children()
+        .filter_map(ElementRef::wrap)
+        .rev()
+        .map(|el| (el, Vec::new()))
+        .collect();
+    while let Some((element, mut path)) = stack.pop() {
+        let mut shape = element.value().name().to_string();
+        for class in element.value().classes() {
+            shape.push('.');
+            shape.push_str(class);
+        }
+        path.push(shape);
+        match shapes.iter_mut().find(|(known, _)| *known == path) {
+            Some((_, count)) => *count += 1,
+            None => shapes.push((path.clone(), 1)),
+        }
+        if path.len() < SHAPE_DEPTH {
+            let children: Vec<_> = element.children().filter_map(ElementRef::wrap).collect();
+            stack.extend(children.into_iter().rev().map(|el| (el, path.clone())));
+        }
+    }
+    Ok(
+        shapes
+            .into_iter()
+            .map(|(mut path, count)| {
+                let depth = path.len();
+                (depth, path.pop().unwrap_or_default(), count)
+            })
+            .collect()
+    )
+}
+
+// How a message's sender was read, mirroring `parse_conversation`
+struct Sender {
+    name: Option<String>,
+    phone: String,
+    phone_description: String,
+    self_description: String,
+    is_self: bool,
+}
+
+impl Sender {
+    fn resolve(
+        message: &ElementRef,
+        known_phones: &[String],
+        self_phone: &str,
+        path: &Path,
+        options: &ParseOptions
+    ) -> Result<Self> {
+        let selectors = selectors::get();
+        let Some(sender) = message.select(&selectors.sender).next() else {
+            anyhow::bail!("A message has no `{}` element, so the file fails to parse", SENDER_SELECTOR);
+        };
+        let name_element = sender.select(&selectors.name).next();
+        let name = name_element.and_then(|el| el.text().next()).map(str::to_string);
+        let name_str = name.as_deref().unwrap_or("");
+
+        let phone = sender_phone(&sender, name_str, known_phones, self_phone, path, options);
+        let tels = sender_tels(&sender);
+        let by_markup = sender_is_self(&sender);
+        let is_me_sender = options.is_me_sender(&sender, name_str);
+        let phone_description = if is_me_sender && !tels.is_empty() && !tels.contains(&phone) {
+            format!("{:?} from --me-phone, in place of the `a.tel` link ({})", phone, tels.join(", "))
+        } else if tels.len() > 1 {
+            let known = known_phones.contains(&phone);
+            format!(
+                "{:?} of {} `a.tel` links ({}), as {}",
+                phone,
+                tels.len(),
+                tels.join(", "),
+                if known { "someone in the conversation has that number alone" } else { "the first" }
+            )
+        } else if !tels.is_empty() {
+            format!("{:?} from the `a.tel` link", phone)
+        } else if is_me_sender && phone == unknown_self_phone(path) {
+            format!("{:?}, a placeholder as none of your messages has an `a.tel` link and there is no --me-phone", phone)
+        } else if is_me_sender && options.me_phone.as_deref() == Some(phone.as_str()) {
+            format!("{:?} from --me-phone (no `a.tel` link)", phone)
+        } else if is_me_sender {
+            format!("{:?} from the `a.tel` link of another of your messages (none here)", phone)
+        } else {
+            format!("{:?}, a placeholder as there is no `a.tel` link", phone)
+        };
+
+        let class = sender
+            .value()
+            .classes()
+            .find(|c| SELF_SENDER_CLASSES.contains(c));
+        let mut self_description = match (class, by_markup) {
+            (Some(class), _) => format!("yes, the sender has class `{}`", class),
+            (None, Some(true)) => "yes, the name is in `abbr.fn`".to_string(),
+            (None, Some(false)) => "no, the name is in `span.fn`".to_string(),
+            (None, None) if name_str == "Me" => "yes, by the name \"Me\"".to_string(),
+            (None, None) if is_me_sender => "yes, the name matches --me-name".to_string(),
+            (None, None) => "no, nothing marks the sender and the name isn't yours".to_string(),
+        };
+        let is_self = is_me_sender || options.is_me_phone(&phone);
+        if is_self && !is_me_sender {
+            self_description = format!("yes, the phone matches --me-phone (markup: {})", self_description);
+        }
+        Ok(Sender { name, phone, phone_description, self_description, is_self })
+    }
+
+    fn name_description(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{:?} from `span.fn, abbr.fn`", name),
+            None => "no name (no `span.fn` or `abbr.fn`)".to_string(),
+        }
+    }
+}
+
+// Which timestamp was read, or why the message falls back to the Unix epoch
+fn explain_timestamp(message: &ElementRef, options: &ParseOptions) -> Result<String> {
+    let Some(dt) = message.select(&selectors::get().datetime).next() else {
+        if options.drop_undated {
+            return Ok(format!("no `{}` element, dropped (--drop-undated)", DATETIME_SELECTOR));
+        }
+        return Ok(format!("no `{}` element, falling back to the Unix epoch", DATETIME_SELECTOR));
+    };
+    let Some(title) = dt.value().attr("title") else {
+        return Ok(format!("`{}` has no title attribute, falling back to the Unix epoch", DATETIME_SELECTOR));
+    };
+    Ok(match DateTime::parse_from_str(title, DATETIME_FORMAT) {
+        Ok(parsed) => format!("{:?} matched {}, read as {}", title, DATETIME_FORMAT, parsed.to_utc()),
+        Err(e) =>
+            format!("{:?} doesn't match {} ({}), falling back to the Unix epoch", title, DATETIME_FORMAT, e),
+    })
+}
+
+// Content, quoted reply, attachments and kind of a message
+fn explain_body(message: &ElementRef, sender: &str, options: &ParseOptions) -> Result<String> {
+    let selectors = selectors::get();
+    let reply_selector = &selectors.reply;
+    let content = message
+        .select(&selectors.content)
+        .find(|el| !in_reply(el, reply_selector))
+        .map(|el| content_text(&el));
+    let content_description = match &content {
+        Some(text) => format!("`{}` with {} characters", CONTENT_SELECTOR, text.chars().count()),
+        None => format!("no `{}`", CONTENT_SELECTOR),
+    };
+    let reply = if message.select(reply_selector).next().is_some() { "a quoted reply" } else { "no reply" };
+    let attachments = message
+        .select(&selectors.attachment)
+        .filter(|el| !in_reply(el, reply_selector) && !within(el, &selectors.sender))
+        .count();
+    let kind = match message_kind(message, sender, content.as_deref(), &options.notices) {
+        MessageKind::Regular => "regular",
+        MessageKind::Reaction => "reaction",
+        MessageKind::System if options.notices.is_notice(sender, content.as_deref()) =>
+            "system (matches a notice pattern)",
+        MessageKind::System => "system",
+    };
+    Ok(format!("content: {}, {}, {} attachments; kind {}", content_description, reply, attachments, kind))
+}
@@ -0,0 +1,31 @@
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+
+// Area code table compiled into the binary, see data/nanp_area_codes.csv
+const AREA_CODES: &str = include_str!("../data/nanp_area_codes.csv");
+
+lazy_static! {
+    static ref REGIONS: HashMap<&'static str, &'static str> = AREA_CODES.lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with("area_code"))
+        .filter_map(|line| line.split_once(','))
+        .collect();
+}
+
+// Best-effort region (state, province or territory) for a North American number.
+// Accepts `+1NXXNXXXXXX`, `1NXXNXXXXXX` or bare 10-digit numbers with any punctuation;
+// anything else is treated as non-NANP and yields `None`.
+pub fn region_for_phone(phone: &str) -> Option<&'static str> {
+    let digits: String = phone
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect();
+    let international = phone.trim_start().starts_with('+');
+    let national = match digits.len() {
+        11 if digits.starts_with('1') => &digits[1..],
+        10 if !international => &digits[..],
+        _ => {
+            return None;
+        }
+    };
+    REGIONS.get(&national[..3]).copied()
+}
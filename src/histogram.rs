@@ -0,0 +1,135 @@
+use anyhow::{ Context, Result };
+use chrono::{ Datelike, Timelike };
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{ self, BufWriter, Write };
+use std::path::Path;
+use crate::Thread;
+
+const WEEKDAYS: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+// Width of the longest bar in the text rendering
+const BAR_WIDTH: usize = 40;
+
+// Messages per day of week and hour of day in the `--tz-output` zone, aggregated over
+// every thread written in the run. Written as JSON for a `.json` path and as text bar
+// charts otherwise (`-` prints the text to stdout).
+pub struct Histogram {
+    out: Box<dyn Write>,
+    json: bool,
+    tz: Tz,
+    /// Message counts indexed by weekday (Monday first), then local hour
+    counts: [[usize; 24]; 7],
+}
+
+#[derive(Serialize)]
+struct HistogramJson<'a> {
+    time_zone: &'a str,
+    total: usize,
+    by_hour: [usize; 24],
+    by_weekday: Vec<WeekdayBuckets>,
+}
+
+#[derive(Serialize)]
+struct WeekdayBuckets {
+    day: &'static str,
+    total: usize,
+    hours: [usize; 24],
+}
+
+impl Histogram {
+    pub fn create(path: &Path, tz: Tz) -> Result<Self> {
+        let out: Box<dyn Write> = if path.as_os_str() == "-" {
+            Box::new(io::stdout())
+        } else {
+            let file = File::create(path).with_context(||
+                format!("Failed to create histogram file: {:?}", path)
+            )?;
+            Box::new(BufWriter::new(file))
+        };
+        let json = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        Ok(Histogram {
+            out,
+            json,
+            tz,
+            counts: [[0; 24]; 7],
+        })
+    }
+
+    pub fn record(&mut self, thread: &Thread) {
+        for message in &thread.messages {
+            let local = message.timestamp.with_timezone(&self.tz);
+            self.counts[local.weekday().num_days_from_monday() as usize][local.hour() as usize] += 1;
+        }
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        let rendered = if self.json { self.render_json()? } else { self.render_text() };
+        self.out.write_all(rendered.as_bytes())?;
+        self.out.flush().context("Failed to flush histogram")
+    }
+
+    fn by_hour(&self) -> [usize; 24] {
+        let mut by_hour = [0; 24];
+        for day in &self.counts {
+            for (hour, count) in day.iter().enumerate() {
+                by_hour[hour] += count;
+            }
+        }
+        by_hour
+    }
+
+    fn render_json(&self) -> Result<String> {
+        let by_weekday: Vec<WeekdayBuckets> = WEEKDAYS.iter()
+            .zip(&self.counts)
+            .map(|(&day, hours)| WeekdayBuckets {
+                day,
+                total: hours.iter().sum(),
+                hours: *hours,
+            })
+            .collect();
+        let histogram = HistogramJson {
+            time_zone: self.tz.name(),
+            total: by_weekday
+                .iter()
+                .map(|d| d.total)
+                .sum(),
+            by_hour: self.by_hour(),
+            by_weekday,
+        };
+        Ok(serde_json::to_string_pretty(&histogram)? + "\n")
+    }
+
+    fn render_text(&self) -> String {
+        let by_weekday: Vec<usize> = self.counts
+            .iter()
+            .map(|hours| hours.iter().sum())
+            .collect();
+        let mut text = format!("Messages by day of week ({}):\n", self.tz.name());
+        for (day, &count) in WEEKDAYS.iter().zip(&by_weekday) {
+            let line = format!("  {:<9} {:>7} {}", day, count, bar(count, &by_weekday));
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        let by_hour = self.by_hour();
+        text.push_str(&format!("\nMessages by hour of day ({}):\n", self.tz.name()));
+        for (hour, &count) in by_hour.iter().enumerate() {
+            let line = format!("  {:02}:00 {:>7} {}", hour, count, bar(count, &by_hour));
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+}
+
+// Bar scaled so the largest bucket spans `BAR_WIDTH`; empty buckets get no bar
+fn bar(count: usize, buckets: &[usize]) -> String {
+    let max = buckets.iter().copied().max().unwrap_or(0);
+    if max == 0 {
+        return String::new();
+    }
+    "#".repeat((count * BAR_WIDTH).div_ceil(max))
+}
@@ -0,0 +1,349 @@
+use anyhow::{ Context, Result };
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::Duration;
+use chrono_tz::Tz;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use crate::media::resolve_media;
+use crate::writer::ThreadWriter;
+use crate::{ Attachment, AttachmentKind, Direction, Message, Participant, Thread };
+use log::{ info, warn };
+
+const PAGE_STYLE: &str = "
+body { font-family: Arial, Helvetica, sans-serif; font-size: 14px; background: #f4f4f6; margin: 0; }
+main { max-width: 760px; margin: 0 auto; padding: 16px; }
+header { margin-bottom: 16px; }
+header h1 { font-size: 20px; margin: 0 0 4px; }
+.muted { color: #777; font-size: 12px; }
+.message { display: flex; flex-direction: column; margin: 6px 0; }
+.message .bubble { max-width: 70%; padding: 8px 12px; border-radius: 16px; white-space: normal; word-wrap: break-word; }
+.message.them { align-items: flex-start; }
+.message.them .bubble { background: #e5e5ea; color: #000; }
+.message.me { align-items: flex-end; }
+.message.me .bubble { background: #1a73e8; color: #fff; }
+.message .meta { font-size: 11px; color: #777; margin: 2px 8px; }
+.message .meta .avatar { width: 20px; height: 20px; border-radius: 50%; object-fit: cover; vertical-align: middle; margin-right: 4px; }
+.attachment { font-style: italic; opacity: 0.8; }
+.message .bubble + .bubble { margin-top: 3px; }
+.message .bubble img { display: block; max-width: 100%; border-radius: 10px; margin: 4px 0; }
+.message.me .bubble a { color: #fff; }
+table { border-collapse: collapse; width: 100%; background: #fff; }
+th, td { text-align: left; padding: 6px 8px; border-bottom: 1px solid #ddd; }
+";
+
+// Writes one standalone chat-log page per thread into an output directory, plus an
+// `index.html` linking to every page once the run finishes. Times are shown in `tz`.
+// Images are shown inline and other attachments linked; with `embed_media` images up
+// to that many bytes are embedded in the page itself. With `collapse`, a run of
+// messages from one sender, each within that long of the one before, is shown under a
+// single name and time.
+pub struct HtmlWriter {
+    dir: PathBuf,
+    tz: Tz,
+    embed_media: Option<u64>,
+    collapse: Option<Duration>,
+    pages: Vec<IndexEntry>,
+    used_names: HashSet<String>,
+}
+
+struct IndexEntry {
+    file_name: String,
+    title: String,
+    message_count: usize,
+    first: String,
+    last: String,
+}
+
+impl HtmlWriter {
+    pub fn create(dir: &Path, tz: Tz, embed_media: Option<u64>, collapse: Option<Duration>) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(||
+            format!("Failed to create HTML output directory: {:?}", dir)
+        )?;
+        info!("Writing HTML output to {:?}", dir);
+        Ok(HtmlWriter {
+            dir: dir.to_path_buf(),
+            tz,
+            embed_media,
+            collapse,
+            pages: Vec::new(),
+            used_names: HashSet::new(),
+        })
+    }
+
+    // Derive a unique, filesystem-safe page name from the thread's source file name
+    fn page_name(&mut self, source_path: &str) -> String {
+        let stem = Path::new(source_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "thread".to_string());
+        let stem: String = stem
+            .chars()
+            .map(|c| if c.is_alphanumeric() || "-_+.".contains(c) { c } else { '_' })
+            .collect();
+
+        let mut name = format!("{}.html", stem);
+        let mut n = 2;
+        while name == "index.html" || !self.used_names.insert(name.clone()) {
+            name = format!("{}-{}.html", stem, n);
+            n += 1;
+        }
+        name
+    }
+}
+
+impl ThreadWriter for HtmlWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        let file_name = self.page_name(&thread.source_path);
+        let title = thread_title(&thread);
+        let path = self.dir.join(&file_name);
+        fs::write(&path, render_thread(&thread, &title, self.tz, self.embed_media, self.collapse)).with_context(||
+            format!("Failed to write HTML page: {:?}", path)
+        )?;
+
+        let format_time = |m: Option<&Message>| {
+            m.map(|m| m.timestamp.with_timezone(&self.tz).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default()
+        };
+        self.pages.push(IndexEntry {
+            file_name,
+            title,
+            message_count: thread.message_count,
+            first: format_time(thread.messages.iter().min_by_key(|m| m.timestamp)),
+            last: format_time(thread.messages.iter().max_by_key(|m| m.timestamp)),
+        });
+        Ok(())
+    }
+
+    // Write the index page listing every thread written so far
+    fn finish(self: Box<Self>) -> Result<()> {
+        let mut rows = String::new();
+        for page in &self.pages {
+            let _ = writeln!(
+                rows,
+                "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(&page.file_name),
+                escape_html(&page.title),
+                page.message_count,
+                page.first,
+                page.last
+            );
+        }
+        let body = format!(
+            "<header><h1>Conversations</h1><div class=\"muted\">{} threads</div></header>\n\
+             <table><tr><th>Conversation</th><th>Messages</th><th>First ({1})</th><th>Last ({1})</th></tr>\n\
+             {2}</table>",
+            self.pages.len(),
+            self.tz.name(),
+            rows
+        );
+        let path = self.dir.join("index.html");
+        fs::write(&path, page("Conversations", &body)).with_context(||
+            format!("Failed to write HTML index: {:?}", path)
+        )?;
+        info!("HTML output complete. Total pages written: {}", self.pages.len());
+        Ok(())
+    }
+}
+
+fn thread_title(thread: &Thread) -> String {
+    let names: Vec<&str> = thread.participants
+        .iter()
+        .filter(|p| p.name != "Me")
+        .map(|p| if p.name.is_empty() { p.phone.as_str() } else { p.name.as_str() })
+        .collect();
+    if names.is_empty() {
+        "Me".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+fn render_thread(
+    thread: &Thread,
+    title: &str,
+    tz: Tz,
+    embed_media: Option<u64>,
+    collapse: Option<Duration>
+) -> String {
+    let mut body = format!(
+        "<header><h1>{}</h1><div class=\"muted\">{} messages",
+        escape_html(title),
+        thread.message_count
+    );
+    if !thread.labels.is_empty() {
+        let _ = write!(body, " &middot; {}", escape_html(&thread.labels.join(", ")));
+    }
+    body.push_str(" &middot; <a href=\"index.html\">all conversations</a></div></header>\n");
+
+    let mut messages = thread.messages.iter().peekable();
+    while let Some(first) = messages.next() {
+        let side = if first.direction == Direction::Outgoing { "me" } else { "them" };
+        let mut bubbles = vec![render_bubble(first, &thread.source_path, embed_media)];
+        if let Some(window) = collapse {
+            let mut previous = first;
+            while let Some(next) = messages.next_if(|m| continues_run(previous, m, window)) {
+                bubbles.push(render_bubble(next, &thread.source_path, embed_media));
+                previous = next;
+            }
+        }
+        let _ = writeln!(
+            body,
+            "<div class=\"message {}\"><div class=\"meta\">{}{} &middot; {}</div>{}</div>",
+            side,
+            render_avatar(&first.from, &thread.source_path, embed_media),
+            escape_html(&first.from.name),
+            first.timestamp.with_timezone(&tz).format("%Y-%m-%d %H:%M:%S %Z"),
+            bubbles.concat()
+        );
+    }
+    page(title, &body)
+}
+
+fn render_bubble(m: &Message, source_path: &str, embed_media: Option<u64>) -> String {
+    let mut text = m.body
+        .text()
+        .map(|content| escape_html(content).replace('\n', "<br>"))
+        .unwrap_or_default();
+    for attachment in m.body.media() {
+        text.push_str(&render_attachment(attachment, source_path, embed_media));
+    }
+    format!("<div class=\"bubble\">{}</div>", text)
+}
+
+// The sender's photo in front of their name, found and embedded like an image
+// attachment; a photo that can't be found is left out
+fn render_avatar(participant: &Participant, source_path: &str, embed_media: Option<u64>) -> String {
+    let Some(path) = participant.avatar
+        .as_deref()
+        .and_then(|src| resolve_media(Path::new(source_path), src)) else {
+        return String::new();
+    };
+    let src = embed_media
+        .and_then(|max_size| embedded_image(&path, max_size))
+        .unwrap_or_else(|| file_url(&path));
+    format!("<img class=\"avatar\" src=\"{}\" alt=\"\">", escape_html(&src))
+}
+
+// Whether `next` goes under the same header as `previous` with `--collapse-consecutive`
+fn continues_run(previous: &Message, next: &Message, window: Duration) -> bool {
+    next.direction == previous.direction &&
+        next.from.name == previous.from.name &&
+        next.from.phone == previous.from.phone &&
+        next.timestamp >= previous.timestamp &&
+        next.timestamp - previous.timestamp <= window
+}
+
+// An image becomes an `<img>`, anything else a link to the file. Media is referenced
+// where `--extract-media` copied it, or else where it was found next to the source file.
+fn render_attachment(attachment: &Attachment, source_path: &str, embed_media: Option<u64>) -> String {
+    let path = match &attachment.extracted_path {
+        Some(extracted) => Some(PathBuf::from(extracted)),
+        None => resolve_media(Path::new(source_path), &attachment.src),
+    };
+    let Some(path) = path else {
+        warn!("Media file {:?} referenced by {:?} not found", attachment.src, source_path);
+        return format!("<span class=\"attachment\">[missing {}]</span>", kind_label(attachment.kind));
+    };
+    let url = file_url(&path);
+    if attachment.kind != AttachmentKind::Image {
+        return format!(
+            "<a class=\"attachment\" href=\"{}\" download>[{}: {}]</a>",
+            escape_html(&url),
+            kind_label(attachment.kind),
+            escape_html(&path.file_name().unwrap_or_default().to_string_lossy())
+        );
+    }
+    let src = embed_media
+        .and_then(|max_size| embedded_image(&path, max_size))
+        .unwrap_or(url);
+    format!("<img src=\"{}\" alt=\"image\" loading=\"lazy\">", escape_html(&src))
+}
+
+// Base64 data URL of an image no larger than `max_size` bytes. Larger or unreadable files
+// are linked instead, with a warning.
+fn embedded_image(path: &Path, max_size: u64) -> Option<String> {
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) => {
+            warn!("Can't embed {:?}: {}", path, e);
+            return None;
+        }
+    };
+    if size > max_size {
+        warn!("Not embedding {:?}: {} bytes exceeds --embed-max-size of {} bytes", path, size, max_size);
+        return None;
+    }
+    match fs::read(path) {
+        Ok(bytes) => Some(format!("data:{};base64,{}", image_mime_type(path), BASE64.encode(bytes))),
+        Err(e) => {
+            warn!("Can't embed {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+fn image_mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "heic" => "image/heic",
+        _ => "image/jpeg",
+    }
+}
+
+pub fn kind_label(kind: AttachmentKind) -> &'static str {
+    match kind {
+        AttachmentKind::Image => "image",
+        AttachmentKind::Video => "video",
+        AttachmentKind::Audio => "audio",
+        AttachmentKind::Contact => "contact",
+    }
+}
+
+// `file://` URL of a media file, absolute so pages work wherever the output directory is
+fn file_url(path: &Path) -> String {
+    let absolute = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut url = String::from("file://");
+    for byte in absolute.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            let _ = write!(url, "%{:02X}", byte);
+        }
+    }
+    url
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style></head>\n<body><main>\n{}\n</main></body></html>\n",
+        escape_html(title),
+        PAGE_STYLE,
+        body
+    )
+}
+
+// Escape text for safe inclusion in HTML element content and attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
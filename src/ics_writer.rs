@@ -0,0 +1,207 @@
+use anyhow::{ Context, Result };
+use chrono::{ DateTime, NaiveDate, Utc };
+use chrono_tz::Tz;
+use std::io::Write;
+use crate::html_writer::kind_label;
+use crate::takeout_writer::other_participants;
+use crate::writer::ThreadWriter;
+use crate::{ IcsEvents, Message, Thread };
+use log::info;
+
+// Longest content line iCalendar allows, in octets; longer lines are folded
+const MAX_LINE_OCTETS: usize = 75;
+
+// iCalendar (RFC 5545) export of when conversations happened, for a year-in-review in a
+// calendar app: a VEVENT per conversation-day spanning its first to last message, or one
+// per message. Days and event times are in the `--tz-output` zone. The zone is referenced
+// by its IANA name without a VTIMEZONE definition, which calendar apps resolve themselves.
+pub struct IcsWriter {
+    out: Box<dyn Write>,
+    tz: Tz,
+    events: IcsEvents,
+    written: usize,
+}
+
+impl IcsWriter {
+    pub fn create(mut out: Box<dyn Write>, tz: Tz, events: IcsEvents) -> Result<Self> {
+        write_line(&mut out, "BEGIN:VCALENDAR")?;
+        write_line(&mut out, "VERSION:2.0")?;
+        let product = format!("PRODID:-//google_voice_importer//{}//EN", env!("CARGO_PKG_VERSION"));
+        write_line(&mut out, &product)?;
+        write_line(&mut out, "CALSCALE:GREGORIAN")?;
+        write_line(&mut out, &format!("X-WR-TIMEZONE:{}", tz.name()))?;
+        Ok(IcsWriter { out, tz, events, written: 0 })
+    }
+
+    fn write_event(&mut self, event: &Event) -> Result<()> {
+        let start = self.date_time(event.start);
+        let end = self.date_time(event.end);
+        write_line(&mut self.out, "BEGIN:VEVENT")?;
+        write_line(&mut self.out, &format!("UID:{}@google_voice_importer", event.uid))?;
+        write_line(&mut self.out, &format!("DTSTAMP:{}", event.start.format("%Y%m%dT%H%M%SZ")))?;
+        write_line(&mut self.out, &format!("DTSTART{}", start))?;
+        // Without DTEND a single-message event ends when it starts
+        if event.end > event.start {
+            write_line(&mut self.out, &format!("DTEND{}", end))?;
+        }
+        write_line(&mut self.out, &format!("SUMMARY:{}", escape_text(&event.summary)))?;
+        write_line(&mut self.out, &format!("DESCRIPTION:{}", escape_text(&event.description)))?;
+        write_line(&mut self.out, "END:VEVENT")?;
+        self.written += 1;
+        Ok(())
+    }
+
+    // `:` plus a UTC time, or `;TZID=<zone>:` plus the local time in the display zone
+    fn date_time(&self, timestamp: DateTime<Utc>) -> String {
+        if self.tz == Tz::UTC {
+            format!(":{}", timestamp.format("%Y%m%dT%H%M%SZ"))
+        } else {
+            format!(
+                ";TZID={}:{}",
+                self.tz.name(),
+                timestamp.with_timezone(&self.tz).format("%Y%m%dT%H%M%S")
+            )
+        }
+    }
+
+    // Message lines for a description: `HH:MM Sender: text`
+    fn transcript(&self, messages: &[&Message]) -> String {
+        messages
+            .iter()
+            .map(|m| {
+                format!(
+                    "{} {}: {}",
+                    m.timestamp.with_timezone(&self.tz).format("%H:%M"),
+                    display_name(&m.from.name, &m.from.phone),
+                    message_text(m)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+struct Event {
+    uid: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    summary: String,
+    description: String,
+}
+
+impl ThreadWriter for IcsWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        let others = other_participants(&thread)
+            .iter()
+            .map(|p| display_name(&p.name, &p.phone))
+            .collect::<Vec<_>>();
+        let summary = if others.is_empty() {
+            "Texts to yourself".to_string()
+        } else {
+            format!("Texts with {}", others.join(", "))
+        };
+
+        match self.events {
+            IcsEvents::Message => {
+                for message in &thread.messages {
+                    let event = Event {
+                        uid: message.id.clone(),
+                        start: message.timestamp,
+                        end: message.timestamp,
+                        summary: summary.clone(),
+                        description: message_text(message),
+                    };
+                    self.write_event(&event)?;
+                }
+            }
+            IcsEvents::Day => {
+                // Messages aren't necessarily in order, so days are grouped explicitly
+                let mut days: Vec<(NaiveDate, Vec<&Message>)> = Vec::new();
+                let mut messages: Vec<&Message> = thread.messages.iter().collect();
+                messages.sort_by_key(|m| m.timestamp);
+                for message in messages {
+                    let day = message.timestamp.with_timezone(&self.tz).date_naive();
+                    match days.last_mut() {
+                        Some((last, messages)) if *last == day => messages.push(message),
+                        _ => days.push((day, vec![message])),
+                    }
+                }
+                for (day, messages) in days {
+                    let event = Event {
+                        uid: format!("{}-{}", thread.thread_id, day.format("%Y%m%d")),
+                        start: messages[0].timestamp,
+                        end: messages[messages.len() - 1].timestamp,
+                        summary: summary.clone(),
+                        description: self.transcript(&messages),
+                    };
+                    self.write_event(&event)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        write_line(&mut self.out, "END:VCALENDAR")?;
+        self.out.flush().context("Failed to flush output")?;
+        info!("iCalendar output complete. Total events written: {}", self.written);
+        Ok(())
+    }
+}
+
+fn display_name<'a>(name: &'a str, phone: &'a str) -> &'a str {
+    if name.is_empty() { phone } else { name }
+}
+
+// The message text, with attachments named after it
+fn message_text(message: &Message) -> String {
+    let media = message.body
+        .media()
+        .iter()
+        .map(|a| format!("[{}]", kind_label(a.kind)));
+    message.body
+        .text()
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+        .into_iter()
+        .chain(media)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Escape a TEXT value: backslashes, semicolons, commas and line breaks
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Write a content line with CRLF, folded into continuation lines (starting with a space)
+// so none is longer than 75 octets, without splitting a UTF-8 character
+fn write_line(out: &mut dyn Write, line: &str) -> Result<()> {
+    let mut rest = line;
+    let mut limit = MAX_LINE_OCTETS;
+    loop {
+        if rest.len() <= limit {
+            write!(out, "{}\r\n", rest)?;
+            return Ok(());
+        }
+        let mut split = limit;
+        while !rest.is_char_boundary(split) {
+            split -= 1;
+        }
+        write!(out, "{}\r\n ", &rest[..split])?;
+        rest = &rest[split..];
+        // The leading space of a continuation line counts towards its length
+        limit = MAX_LINE_OCTETS - 1;
+    }
+}
@@ -0,0 +1,58 @@
+use serde_json::{ Deserializer, Value };
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use crate::error::ParseError;
+use crate::{ schema, Thread };
+
+const JSON_FILE_TYPE: &str = "json";
+
+// Bytes read from a JSON file to tell whether it holds threads
+const SNIFF_LENGTH: usize = 256;
+
+pub fn is_json_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(JSON_FILE_TYPE))
+}
+
+// Whether a file found in a directory starts as the `json` format writes threads: an
+// object, alone or first of an array, whose first key is `thread_id`. Only the start is
+// read, so the other JSON of a directory is passed over cheaply.
+pub fn looks_like_threads(path: &Path) -> bool {
+    let mut start = Vec::with_capacity(SNIFF_LENGTH);
+    let read = fs::File::open(path).and_then(|file| file.take(SNIFF_LENGTH as u64).read_to_end(&mut start));
+    if read.is_err() {
+        // Unreadable files are still taken, so the failure is reported
+        return true;
+    }
+    let start = String::from_utf8_lossy(&start);
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+    let start = start.strip_prefix('[').map(str::trim_start).unwrap_or(start);
+    start
+        .strip_prefix('{')
+        .is_some_and(|object| object.trim_start().starts_with("\"thread_id\""))
+}
+
+// The threads of a file the `json` format wrote, pretty or `--compact`: JSON objects one
+// after another, or an array of them. Each is checked against the thread schema before
+// it is deserialized, so a file that isn't the importer's output (or was written with
+// `--json-case camel`) fails with every mismatch located rather than serde's first one.
+pub fn read_threads(path: &Path) -> Result<Vec<Thread>, ParseError> {
+    let content = fs::read_to_string(path)?;
+    let mut threads = Vec::new();
+    for value in Deserializer::from_str(&content).into_iter::<Value>() {
+        let values = match value? {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            if let Some(report) = schema::check_thread(&value) {
+                return Err(ParseError::ThreadSchema { index: threads.len(), report });
+            }
+            threads.push(serde_json::from_value(value)?);
+        }
+    }
+    if threads.is_empty() {
+        return Err(ParseError::EmptyDocument);
+    }
+    Ok(threads)
+}
@@ -0,0 +1,89 @@
+use anyhow::{ Context, Result };
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// Keyword categories from `--flag-keywords`, for tagging messages in moderation
+// datasets. Terms match case-insensitively on word boundaries, so `ass` flags
+// "you ass!" but not "class"; a term may span several words.
+#[derive(Debug, Clone, Default)]
+pub struct KeywordList {
+    /// Lowercased terms by category, categories in name order
+    categories: BTreeMap<String, Vec<String>>,
+}
+
+impl KeywordList {
+    // Load a `.toml` file of `category = ["term", ...]` arrays, or a CSV file with one
+    // `category,term` pair per line (an optional `category,term` header and `#`
+    // comments allowed)
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(||
+            format!("Failed to read keyword list: {:?}", path)
+        )?;
+        let is_toml = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("toml"));
+        let categories: BTreeMap<String, Vec<String>> = if is_toml {
+            toml::from_str(&content)
+                .with_context(|| format!("Invalid keyword list: {:?}", path))?
+        } else {
+            parse_csv(&content).with_context(|| format!("Invalid keyword list: {:?}", path))?
+        };
+
+        let categories = categories
+            .into_iter()
+            .map(|(category, terms)| {
+                let terms = terms
+                    .iter()
+                    .map(|term| term.trim().to_lowercase())
+                    .filter(|term| !term.is_empty())
+                    .collect();
+                (category, terms)
+            })
+            .collect();
+        Ok(KeywordList { categories })
+    }
+
+    pub fn categories(&self) -> impl Iterator<Item = &str> {
+        self.categories.keys().map(String::as_str)
+    }
+
+    // Categories with at least one term in `text`, in name order
+    pub fn flags(&self, text: &str) -> Vec<String> {
+        let text = text.to_lowercase();
+        self.categories
+            .iter()
+            .filter(|(_, terms)| terms.iter().any(|term| contains_word(&text, term)))
+            .map(|(category, _)| category.clone())
+            .collect()
+    }
+}
+
+fn parse_csv(content: &str) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut categories: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((category, term)) = line.split_once(',') else {
+            anyhow::bail!("line {}: expected `category,term`", index + 1);
+        };
+        let (category, term) = (category.trim(), term.trim().trim_matches('"'));
+        if index == 0 && category.eq_ignore_ascii_case("category") && term.eq_ignore_ascii_case("term") {
+            continue;
+        }
+        categories.entry(category.to_string()).or_default().push(term.to_string());
+    }
+    Ok(categories)
+}
+
+// Whether `term` occurs in `text` with no letter or digit directly before or after it
+fn contains_word(text: &str, term: &str) -> bool {
+    text.match_indices(term).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + term.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
@@ -1,31 +1,138 @@
 use anyhow::{ Context, Result };
 use chrono::{ DateTime, Utc };
+use chrono_tz::Tz;
 use clap::Parser;
-use cypher_writer::neo4j_writer;
 use indicatif::{ ProgressBar, ProgressStyle };
-use scraper::{ Html, Selector };
-use serde::Serialize;
-use std::collections::HashSet;
+use scraper::{ ElementRef, Html, Selector };
+use serde::{ Deserialize, Serialize };
+use std::borrow::Cow;
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::cell::RefCell;
 use std::fs;
+use std::hash::{ Hash, Hasher };
+use std::io::{ self, Read };
+use std::num::NonZeroUsize;
 use std::path::{ Path, PathBuf };
+use std::process::ExitCode;
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::{ mpsc, Arc };
 use std::time::Instant;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
 use walkdir::WalkDir;
 use glob::glob;
-use tokio::sync::mpsc;
+mod append;
+mod archive_writer;
+#[cfg(test)]
+mod bench;
+mod cache;
+mod checkpoint;
+mod classify;
+mod checksum;
+mod chunk_writer;
+mod csv_writer;
 mod cypher_writer;
-use std::thread;
-use log::{ info, warn, error };
+#[cfg(feature = "duckdb")]
+mod duckdb_writer;
+mod error;
+mod explain;
+mod geo;
+mod histogram;
+mod hll;
+mod html_writer;
+mod ics_writer;
+mod json_input;
+mod keywords;
+mod manifest;
+mod markdown_writer;
+mod matrix;
+mod media;
+mod merge;
+mod mirror;
+mod notices;
+mod parquet_writer;
+mod per_contact;
+mod position;
+mod protobuf_writer;
+mod progress;
+mod redact;
+mod regions;
+mod rename;
+mod roster;
+mod sample_writer;
+mod schema;
+mod selectors;
+mod signal_writer;
+mod split_writer;
+mod takeout_writer;
+mod tally;
+mod terms;
+#[cfg(test)]
+mod tests;
+mod timeline;
+mod transcript;
+mod timestamp;
+mod tui;
+mod voicemail;
+mod watch;
+mod writer;
+use histogram::Histogram;
+use checksum::Checksums;
+use manifest::Manifest;
+use matrix::AdjacencyMatrix;
+use per_contact::ContactFiles;
+use position::{ MessageSource, SourcePosition };
+use progress::ProgressLog;
+use writer::{ create_multi_writer, create_writer, ThreadWriter };
+use checkpoint::Checkpoint;
+use error::ParseError;
+use log::{ info, error, warn };
 // Constants for HTML selectors and date format
 
 const MESSAGE_SELECTOR: &str = ".message";
 const DATETIME_SELECTOR: &str = ".dt";
 const SENDER_SELECTOR: &str = ".sender";
+// A sender's display name and number; your own name is an `abbr`, see `sender_is_self`
+const NAME_SELECTOR: &str = "span.fn, abbr.fn";
+const SELF_NAME_SELECTOR: &str = ".fn";
+const TEL_SELECTOR: &str = "a.tel";
 const CONTENT_SELECTOR: &str = "q";
 const TAGS_SELECTOR: &str = ".tags";
+const TITLE_SELECTOR: &str = "title";
+const CONVERSATION_SELECTOR: &str = ".hChatLog";
+const PARTICIPANTS_SELECTOR: &str = ".participants";
+const ATTACHMENT_SELECTOR: &str =
+    "img[src], audio[src], video[src], a.image[href], a.video[href], a.audio[href], a.vcard[href]";
+// Quoted earlier message inside a reply: `<blockquote class="reply">` holding the quoted
+// sender's name in a `<cite>` and the quoted text in a `<q>`
+const REPLY_SELECTOR: &str = "blockquote.reply";
+const REPLY_SENDER_SELECTOR: &str = "cite";
+// Contact photo some exports put in the `.sender` markup
+const AVATAR_SELECTOR: &str = "img[src]";
+// A voicemail export: the recording's `.haudio` element, with the caller's contact card,
+// the time it was left and Google's transcription
+const VOICEMAIL_SELECTOR: &str = ".haudio";
+const CALLER_SELECTOR: &str = ".contributor";
+const PUBLISHED_SELECTOR: &str = "abbr.published";
+const TRANSCRIPTION_SELECTOR: &str = ".description .full-text";
+// Classes marking a `.message` as a status notice (read receipt, typing indicator,
+// membership change) or as a reaction rather than a message of its own
+const SYSTEM_MESSAGE_CLASSES: [&str; 2] = ["system", "status"];
+const REACTION_MESSAGE_CLASS: &str = "reaction";
+// Verbs of the text fallback iMessage/RCS tapbacks arrive as over SMS, e.g.
+// `Liked “See you at 8”`; `Reacted 👍 to “...”` is recognized separately
+const TAPBACK_VERBS: [&str; 6] = ["Liked", "Loved", "Disliked", "Laughed at", "Emphasized", "Questioned"];
+// Classes that explicitly mark a sender element as the account owner
+const SELF_SENDER_CLASSES: [&str; 2] = ["self", "me"];
 const TEXT_LABEL: &str = "- Text -";
+const VOICEMAIL_LABEL: &str = "- Voicemail -";
 const GROUP_CONVERSATION_LABEL: &str = "Group Conversation -";
 const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
 const FILE_TYPE: &str = "html";
+const UNKNOWN_PHONE: &str = "Unknown";
+// `input` value that reads HTML from stdin, and the source path recorded for it
+const STDIN_INPUT: &str = "-";
+const STDIN_SOURCE: &str = "<stdin>";
 
 // Knowledge gragh constants
 const NEO4J_URI: &str = "bolt://localhost:7687";
@@ -33,381 +140,4312 @@ const NEO4J_USER: &str = "neo4j";
 const NEO4J_PASSWORD: &str = "password";
 const THREAD_BATCH_SIZE: usize = 100;
 
-// Struct to represent a participant in the conversation
-#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
+// Files between `--progress-to-file` lines unless `--progress-interval` says otherwise
+const DEFAULT_PROGRESS_INTERVAL: usize = 100;
+
+// Messages per Parquet row group
+const PARQUET_ROW_GROUP_SIZE: usize = 10_000;
+
+// Capacity the per-thread read buffer may keep between files; anything larger (from the
+// occasional huge concatenated export) is released after that file is parsed
+const READ_BUFFER_RETAIN: usize = 16 * 1024 * 1024;
+
+// Set by the first Ctrl-C; the run stops taking new files and shuts down cleanly
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // Reused across files so each parse doesn't allocate a fresh buffer for the raw HTML
+    static READ_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// Struct to represent a participant in the conversation.
+// The rollup fields only describe the thread the participant was parsed from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Participant {
     name: String,
+    /// Name as written in the export, kept when `--clean-names` changed it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_name: Option<String>,
+    phone: String,
+    /// Number of messages this participant sent in the thread
+    message_count: usize,
+    /// Timestamp of the participant's first message in the thread
+    #[serde(serialize_with = "timestamp::serialize_option")]
+    first_seen: Option<DateTime<Utc>>,
+    /// Timestamp of the participant's last message in the thread
+    #[serde(serialize_with = "timestamp::serialize_option")]
+    last_seen: Option<DateTime<Utc>>,
+    /// Approximate region from the area code, filled in with `--geo`
+    region: Option<String>,
+    /// Person, business or automated sender, with `--classify-participants`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    participant_type: Option<classify::ParticipantType>,
+    /// Other numbers on the sender's contact card when it lists several, see `choose_tel`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    alternate_phones: Vec<String>,
+    /// Contact photo from the sender markup, as referenced in the HTML (relative to the
+    /// HTML file, like attachments)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    avatar: Option<String>,
+    /// Names and phones combined into this participant by `--merge-similar-participants`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    merged_from: Vec<merge::ParticipantAlias>,
+}
+
+impl Participant {
+    fn new(name: &str, phone: &str) -> Self {
+        Participant {
+            name: name.to_string(),
+            raw_name: None,
+            phone: phone.to_string(),
+            message_count: 0,
+            first_seen: None,
+            last_seen: None,
+            region: None,
+            participant_type: None,
+            alternate_phones: Vec::new(),
+            avatar: None,
+            merged_from: Vec::new(),
+        }
+    }
+}
+
+// Participants are identified by name and phone only, so the same contact
+// compares equal across threads regardless of its per-thread rollups
+impl PartialEq for Participant {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.phone == other.phone
+    }
+}
+
+impl Eq for Participant {}
+
+impl Hash for Participant {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.phone.hash(state);
+    }
+}
+
+// Struct to represent a single message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Message {
+    /// Stable identifier, see `message_id`
+    id: String,
+    from: Participant,
+    to: Vec<Participant>,
+    /// Whether the account owner sent the message
+    direction: Direction,
+    /// Regular message, reaction to an earlier message, or status notice
+    kind: MessageKind,
+    #[serde(serialize_with = "timestamp::serialize")]
+    timestamp: DateTime<Utc>,
+    /// Text and/or media of the message, serialized inline as `type`, `content` and
+    /// `attachments`
+    #[serde(flatten)]
+    body: MessageBody,
+    /// Id of the earlier message this one quotes (or, for a reaction, reacts to), when
+    /// the export includes it
+    reply_to: Option<String>,
+    /// ISO 639-3 code of the content's language with `--detect-language`, when the
+    /// detector is confident enough (short messages usually aren't)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    /// `--flag-keywords` categories with a term in the content
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    flags: Vec<String>,
+    /// The content was cut at `--max-content-length`; left out when it wasn't
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    /// Where the source `.message` element starts in its file, kept with `--include-raw`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_position: Option<SourcePosition>,
+    /// Outer HTML of the source `.message` element, kept with `--include-raw`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_html: Option<String>,
+    /// Position among the thread's messages as written, from 0, with `--include-indices`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_index: Option<usize>,
+}
+
+// What a message carries. Attachment-only MMS is `Media` rather than text that happens
+// to be empty; a message without a `<q>` and without media is `Text("")`.
+#[derive(Debug, Clone)]
+enum MessageBody {
+    Text(String),
+    Media(Vec<Attachment>),
+    Mixed {
+        text: String,
+        media: Vec<Attachment>,
+    },
+}
+
+impl MessageBody {
+    fn new(text: Option<String>, media: Vec<Attachment>) -> Self {
+        match (text, media.is_empty()) {
+            (text, true) => MessageBody::Text(text.unwrap_or_default()),
+            (Some(text), false) if !text.is_empty() => MessageBody::Mixed { text, media },
+            (_, false) => MessageBody::Media(media),
+        }
+    }
+
+    fn text(&self) -> Option<&str> {
+        match self {
+            MessageBody::Text(text) | MessageBody::Mixed { text, .. } => Some(text),
+            MessageBody::Media(_) => None,
+        }
+    }
+
+    fn text_mut(&mut self) -> Option<&mut String> {
+        match self {
+            MessageBody::Text(text) | MessageBody::Mixed { text, .. } => Some(text),
+            MessageBody::Media(_) => None,
+        }
+    }
+
+    fn media(&self) -> &[Attachment] {
+        match self {
+            MessageBody::Media(media) | MessageBody::Mixed { media, .. } => media,
+            MessageBody::Text(_) => &[],
+        }
+    }
+
+    fn media_mut(&mut self) -> &mut [Attachment] {
+        match self {
+            MessageBody::Media(media) | MessageBody::Mixed { media, .. } => media,
+            MessageBody::Text(_) => &mut [],
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            MessageBody::Text(_) => "text",
+            MessageBody::Media(_) => "media",
+            MessageBody::Mixed { .. } => "mixed",
+        }
+    }
+}
+
+// Serialized as a `type` discriminator next to the `content` (null for media-only)
+// and `attachments` fields earlier versions wrote, so existing consumers keep working
+impl Serialize for MessageBody {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut body = serializer.serialize_struct("MessageBody", 3)?;
+        body.serialize_field("type", self.kind())?;
+        body.serialize_field("content", &self.text())?;
+        body.serialize_field("attachments", self.media())?;
+        body.end()
+    }
+}
+
+// Read back from the same three fields, with `type` deciding the variant
+impl<'de> Deserialize<'de> for MessageBody {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields {
+            #[serde(rename = "type")]
+            kind: String,
+            content: Option<String>,
+            #[serde(default)]
+            attachments: Vec<Attachment>,
+        }
+        let Fields { kind, content, attachments } = Fields::deserialize(deserializer)?;
+        Ok(match kind.as_str() {
+            "media" => MessageBody::Media(attachments),
+            "mixed" => MessageBody::Mixed { text: content.unwrap_or_default(), media: attachments },
+            _ => MessageBody::Text(content.unwrap_or_default()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MessageKind {
+    Regular,
+    /// Tapback-style reaction to an earlier message; not counted in `message_count`
+    Reaction,
+    /// Read receipt, typing indicator or similar notice; not counted in `message_count`
+    System,
+}
+
+impl MessageKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            MessageKind::Regular => "regular",
+            MessageKind::Reaction => "reaction",
+            MessageKind::System => "system",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Direction {
+    /// Sent by the account owner (in group threads too)
+    Outgoing,
+    /// Sent by anyone else
+    Incoming,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Outgoing => "outgoing",
+            Direction::Incoming => "incoming",
+        }
+    }
+}
+
+// Struct to represent a media file attached to a message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Attachment {
+    kind: AttachmentKind,
+    /// Path as referenced in the HTML, relative to the HTML file
+    src: String,
+    /// Where the file was copied to with `--extract-media`
+    extracted_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum AttachmentKind {
+    Image,
+    Video,
+    Audio,
+    Contact,
+}
+
+// Struct to represent a thread of messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Thread {
+    /// Stable identifier, see `thread_id`
+    thread_id: String,
+    /// Path of the file the thread was parsed from
+    source_path: String,
+    messages: Vec<Message>,
+    participants: Vec<Participant>,
+    labels: Vec<String>,
+    message_count: usize,
+    /// Note-to-self thread: every participant is the account owner, and each message is
+    /// addressed to the owner
+    is_self_thread: bool,
+    /// Whether your own phone number was found, in the export or from `--me-phone`. When
+    /// it wasn't, your participant has the file's `me:<hash>` placeholder phone.
+    self_phone_known: bool,
+    /// Each participant's role and share of the messages in this thread
+    participant_stats: Vec<ThreadParticipantStats>,
+    /// Position of this session within its source file when split with `--session-gap`
+    session_index: Option<usize>,
+    /// Position among the threads written in the run, from 0, with `--include-indices`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_index: Option<usize>,
+    /// Inconsistencies found while parsing the thread
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<ParseWarning>,
+}
+
+// How a participant took part in a thread
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ThreadParticipantStats {
     phone: String,
+    role: ParticipantRole,
+    /// Percentage (0-100) of the thread's messages sent by this participant
+    message_share: f64,
+    /// Median seconds between someone else's message and this participant's next one,
+    /// see `reply_latencies`; `None` without any such reply
+    median_reply_secs: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ParticipantRole {
+    /// Sent the earliest message of the thread
+    Initiator,
+    /// Sent one of several messages tied for earliest, so the initiator is ambiguous
+    CoInitiator,
+    /// Sent messages, but not the first
+    Responder,
+    /// Sent no messages in the thread
+    Silent,
+}
+
+// Problems detected in a file that don't prevent it from being parsed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum ParseWarning {
+    /// A message's sender number is shown under another name elsewhere in the
+    /// conversation; senders are resolved by number, so the message is attributed to the
+    /// participant of that name
+    SenderNameConflict {
+        message_index: usize,
+        phone: String,
+        name: String,
+        /// Name of the participant the message is attributed to
+        attributed_to: String,
+        /// Where the message starts in the file, when its element could be located
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source_position: Option<SourcePosition>,
+    },
+    /// A sender the markup marks as someone other than you has your number
+    SenderHasSelfPhone {
+        message_index: usize,
+        phone: String,
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source_position: Option<SourcePosition>,
+    },
+    /// A message quotes text that doesn't match any earlier message of the thread
+    UnresolvedReply {
+        message_index: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source_position: Option<SourcePosition>,
+    },
+    /// With `--raw-content`, a message's `<q>` couldn't be located in the raw HTML, so its
+    /// content is the decoded text instead
+    RawContentUnavailable {
+        message_index: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source_position: Option<SourcePosition>,
+    },
+    /// A message has no `.dt` element at all, so its time is unknown; it is dated to the
+    /// Unix epoch, or dropped with `--drop-undated`
+    MissingTimestamp {
+        message_index: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source_position: Option<SourcePosition>,
+    },
+    /// A message's `.dt` element has no title or one that isn't a timestamp, so it is
+    /// dated to the Unix epoch
+    InvalidTimestamp {
+        message_index: usize,
+        /// The title, when there is one
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source_position: Option<SourcePosition>,
+    },
+    /// Fewer messages were parsed than there are `.message` elements, so some were lost
+    /// (e.g. `.message` elements outside any conversation container)
+    MessageCountMismatch {
+        elements: usize,
+        parsed: usize,
+    },
+    /// The conversation repeats messages (same sender, time, content and attachments), as
+    /// export glitches sometimes do; all but the first copy of each were dropped
+    RepeatedMessages {
+        dropped: usize,
+    },
+    /// A voicemail without a usable transcription: none at all, or a placeholder such as
+    /// "(Unable to transcribe)"; `--skip-untranscribed` drops such voicemails
+    UntranscribedVoicemail {
+        /// The placeholder, when there is one
+        #[serde(skip_serializing_if = "Option::is_none")]
+        transcription: Option<String>,
+    },
+}
+
+// ` (line N, byte M)` after a message number when its position is known
+fn located(position: &Option<SourcePosition>) -> String {
+    position.map(|p| format!(" ({})", p)).unwrap_or_default()
+}
+
+impl ParseWarning {
+    fn is_message_count_mismatch(&self) -> bool {
+        matches!(self, ParseWarning::MessageCountMismatch { .. })
+    }
+}
+
+// Messages dropped as repeats across the threads of one file
+fn repeated_in_file(threads: &[Thread]) -> usize {
+    threads
+        .iter()
+        .flat_map(|t| &t.warnings)
+        .map(|warning| match warning {
+            ParseWarning::RepeatedMessages { dropped } => *dropped,
+            _ => 0,
+        })
+        .sum()
+}
+
+impl std::fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseWarning::SenderNameConflict { message_index, phone, name, attributed_to, source_position } =>
+                write!(
+                    f,
+                    "message {}{}: sender {:?} ({}) shares a number with {:?} and is attributed to them",
+                    message_index,
+                    located(source_position),
+                    name,
+                    phone,
+                    attributed_to
+                ),
+            ParseWarning::SenderHasSelfPhone { message_index, phone, name, source_position } =>
+                write!(
+                    f,
+                    "message {}{}: sender {:?} isn't marked as you but has your number ({})",
+                    message_index,
+                    located(source_position),
+                    name,
+                    phone
+                ),
+            ParseWarning::UnresolvedReply { message_index, source_position } =>
+                write!(
+                    f,
+                    "message {}{}: quoted message not found in the thread",
+                    message_index,
+                    located(source_position)
+                ),
+            ParseWarning::RawContentUnavailable { message_index, source_position } =>
+                write!(
+                    f,
+                    "message {}{}: content not found in the raw HTML, decoded text kept",
+                    message_index,
+                    located(source_position)
+                ),
+            ParseWarning::MissingTimestamp { message_index, source_position } =>
+                write!(f, "message {}{}: no timestamp element", message_index, located(source_position)),
+            ParseWarning::InvalidTimestamp { message_index, title: Some(title), source_position } =>
+                write!(
+                    f,
+                    "message {}{}: timestamp {:?} couldn't be parsed",
+                    message_index,
+                    located(source_position),
+                    title
+                ),
+            ParseWarning::InvalidTimestamp { message_index, title: None, source_position } =>
+                write!(f, "message {}{}: timestamp element has no title", message_index, located(source_position)),
+            ParseWarning::MessageCountMismatch { elements, parsed } =>
+                write!(f, "{} .message elements but only {} messages parsed", elements, parsed),
+            ParseWarning::RepeatedMessages { dropped } =>
+                write!(f, "{} repeated messages dropped", dropped),
+            ParseWarning::UntranscribedVoicemail { transcription: Some(transcription) } =>
+                write!(f, "voicemail transcription {:?} is a placeholder", transcription),
+            ParseWarning::UntranscribedVoicemail { transcription: None } =>
+                write!(f, "voicemail has no transcription"),
+        }
+    }
+}
+
+// Failure classes that map to distinct process exit codes, attached to errors as context
+#[derive(Debug, Clone, Copy)]
+enum Failure {
+    /// No files matched the input
+    NoInput,
+    /// The run finished but this many files couldn't be parsed
+    ParseFailures(usize),
+    /// Writing output failed, including Neo4j connection and query errors
+    Output,
+    /// Stopped early by Ctrl-C after flushing what was already parsed
+    Interrupted,
+    /// Aborted because parse failures exceeded `--max-failures` or `--max-failure-rate`
+    ThresholdExceeded,
+    /// This many threads differ from the `--verify-checksums` manifest
+    ChecksumMismatch(usize),
+}
+
+impl Failure {
+    fn exit_code(self) -> u8 {
+        match self {
+            Failure::NoInput => 2,
+            Failure::ParseFailures(_) => 3,
+            Failure::Output => 4,
+            Failure::ThresholdExceeded => 5,
+            Failure::ChecksumMismatch(_) => 6,
+            Failure::Interrupted => 130,
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::NoInput => write!(f, "No input files"),
+            Failure::ParseFailures(count) => write!(f, "{} files failed to parse", count),
+            Failure::Output => write!(f, "Failed to write output"),
+            Failure::Interrupted => write!(f, "Interrupted"),
+            Failure::ThresholdExceeded => write!(f, "Too many files failed to parse"),
+            Failure::ChecksumMismatch(count) => write!(f, "{} threads differ from the checksum manifest", count),
+        }
+    }
+}
+
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  success
+  1  other errors (invalid arguments, unreadable input, ...)
+  2  no input files matched, or a directory yielded no conversations
+  3  some files failed to parse (the rest were processed)
+  4  writing output failed, including Neo4j connection errors
+  5  aborted after exceeding --max-failures or --max-failure-rate
+  6  threads differ from the --verify-checksums manifest
+  130  interrupted by Ctrl-C (output written so far is flushed)";
+
+// Command-line interface struct
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None, after_help = EXIT_CODES_HELP)]
+struct Cli {
+    /// Input file or directory, or `-` to read a single HTML file from stdin; `.json` files
+    /// are read back as threads the `json` format wrote
+    #[clap(required_unless_present_any = ["explain", "dump_selectors", "neo4j_check"])]
+    input: Option<PathBuf>,
+
+    /// Output format; when left at `default` and `--output` is given, the format is
+    /// inferred from the output file extension
+    #[clap(value_enum, default_value_t = OutputFormat::Default)]
+    format: OutputFormat,
+
+    /// Write thread output to this file instead of stdout
+    #[clap(long)]
+    output: Option<PathBuf>,
+
+    /// Write several formats from one parse, e.g. `--emit json,parquet`, each to its own
+    /// file or subdirectory of the `--output` directory; replaces the format argument
+    #[clap(
+        long,
+        value_enum,
+        value_name = "FORMATS",
+        value_delimiter = ',',
+        requires = "output",
+        conflicts_with_all = ["chunk_output", "sample"]
+    )]
+    emit: Vec<OutputFormat>,
+
+    /// Write JSON output split into numbered files `<PREFIX>-0001.json`, `<PREFIX>-0002.json`,
+    /// ..., each a standalone array of at most `--chunk-size` threads
+    #[clap(long, value_name = "PREFIX", conflicts_with = "output")]
+    chunk_output: Option<PathBuf>,
+
+    /// Emit only this many messages picked uniformly at random from the whole run, as a
+    /// JSON array with each message's thread id and source path
+    #[clap(long, value_name = "N", conflicts_with = "chunk_output")]
+    sample: Option<NonZeroUsize>,
+
+    /// Seed for `--sample`, to draw the same sample again; random when omitted
+    #[clap(long, requires = "sample")]
+    seed: Option<u64>,
+
+    /// Write every message into the NDJSON file of the month or year it was sent in
+    /// (`2023-07.ndjson`, `2023.ndjson`) in the `--output` directory, by `--tz-output` time
+    #[clap(
+        long,
+        value_enum,
+        value_name = "month|year",
+        requires = "output",
+        conflicts_with_all = ["emit", "chunk_output", "sample", "state"]
+    )]
+    split_by: Option<SplitPeriod>,
+
+    /// Threads per file with `--chunk-output`
+    #[clap(long, value_name = "N", default_value = "1000", requires = "chunk_output")]
+    chunk_size: NonZeroUsize,
+
+    /// Also write an index with one row per thread (source, participants, message count,
+    /// first/last timestamp, labels, group flag) here: CSV for `.csv`, otherwise JSON
+    #[clap(long, value_name = "PATH")]
+    manifest: Option<PathBuf>,
+
+    /// Also write a content checksum per thread (a hash of its messages in order) here, as
+    /// a TSV manifest to check later runs against with `--verify-checksums`
+    #[clap(long, value_name = "PATH")]
+    checksums: Option<PathBuf>,
+
+    /// Compare each thread's content checksum with this `--checksums` manifest and report
+    /// the threads that changed, are new, or are missing; exits with 6 if any differ
+    #[clap(long, value_name = "PATH")]
+    verify_checksums: Option<PathBuf>,
+
+    /// Also write message counts per day of week and hour of day (in the `--tz-output`
+    /// zone) here: JSON for `.json`, otherwise text bar charts; `-` prints them to stdout
+    #[clap(long, value_name = "PATH")]
+    histogram: Option<PathBuf>,
+
+    /// Also write a CSV adjacency matrix of message counts between participants here:
+    /// one row per sender and one column per recipient, over the whole run
+    #[clap(long, value_name = "PATH")]
+    matrix: Option<PathBuf>,
+
+    /// Print the N most frequent words in message content across the run, leaving out
+    /// common stopwords and numbers
+    #[clap(long, value_name = "N")]
+    top_terms: Option<NonZeroUsize>,
+
+    /// Shortest word counted by `--top-terms`, in characters
+    #[clap(long, value_name = "CHARS", default_value_t = 3, requires = "top_terms")]
+    min_term_length: usize,
+
+    /// With `--top-terms`, also print the top terms of each sender
+    #[clap(long, requires = "top_terms")]
+    top_terms_by_participant: bool,
+
+    /// Print how many contacts, and messages from them, fall into each region inferred
+    /// from North American area codes
+    #[clap(long)]
+    region_summary: bool,
+
+    /// Print every contact with the messages they sent and received and the names they
+    /// appeared under, most messages first
+    #[clap(long)]
+    participant_summary: bool,
+
+    /// Also write one JSON file per contact into this directory, with every message
+    /// exchanged with them across the run in time order
+    #[clap(long, value_name = "DIR")]
+    per_contact: Option<PathBuf>,
+
+    /// Whether `--per-contact` writes group messages into every member's file (`all`) or
+    /// leaves group conversations out (`none`)
+    #[clap(long, value_enum, value_name = "all|none", default_value = "all", requires = "per_contact")]
+    group_fan_out: GroupFanOut,
+
+    /// Also write the messages of each source file as NDJSON into this directory, at the
+    /// file's path relative to the input directory
+    #[clap(long, value_name = "DIR")]
+    mirror_output: Option<PathBuf>,
+
+    /// Also write every message of the run into one file in time order, each with its
+    /// thread's id, source and participants: CSV for a `.csv` path, NDJSON for `.ndjson` or `.jsonl`
+    #[clap(long, value_name = "PATH")]
+    timeline: Option<PathBuf>,
+
+    /// Also write the messages and participants into `messages` and `participants`
+    /// tables of this DuckDB database (needs a build with the `duckdb` feature)
+    #[clap(long, value_name = "PATH")]
+    duckdb: Option<PathBuf>,
+
+    /// Estimate the "unique participants" statistic with a HyperLogLog (about 0.8% error,
+    /// 16 KiB) instead of keeping every participant of the run in memory
+    #[clap(long)]
+    approx_participants: bool,
+
+    /// Write progress of each directory as JSON lines (processed/total files, elapsed
+    /// seconds, ETA) to this file, for monitoring headless runs
+    #[clap(long, value_name = "PATH")]
+    progress_to_file: Option<PathBuf>,
+
+    /// Files between progress lines; on its own, writes the lines to stderr
+    #[clap(long, value_name = "N")]
+    progress_interval: Option<NonZeroUsize>,
+
+    /// Check every thread against the bundled JSON Schema (`schema/thread.schema.json`)
+    /// before writing it, and fail the run on the first one that doesn't match
+    #[clap(long)]
+    validate_output: bool,
+
+    /// Write JSON on a single line per thread instead of pretty-printing it
+    #[clap(long)]
+    compact: bool,
+
+    /// Field naming of JSON and NDJSON output: `snake` (e.g. `message_count`) or `camel`
+    /// (`messageCount`)
+    #[clap(long, value_enum, default_value = "snake")]
+    json_case: JsonCase,
+
+    /// Time zone (IANA name, e.g. `America/New_York`) for timestamps in human-readable
+    /// output such as HTML pages, iCalendar events and the TUI; machine-readable formats
+    /// stay in UTC
+    #[clap(long, value_name = "IANA_ZONE", value_parser = parse_timezone, default_value = "UTC")]
+    tz_output: Tz,
+
+    /// How timestamps are written in JSON, YAML, the manifest and Neo4j: full-precision
+    /// RFC 3339, RFC 3339 in whole seconds, or epoch milliseconds or seconds
+    #[clap(long, value_enum, value_name = "FORMAT", default_value = "rfc3339")]
+    timestamp_format: timestamp::TimestampFormat,
+
+    /// Only parse the input and report parse warnings for each file
+    #[clap(long)]
+    validate: bool,
+
+    /// Only print a one-line tally of files, messages and unique participants
+    #[clap(long, conflicts_with = "validate")]
+    count: bool,
+
+    /// Browse the input interactively in a terminal UI; files are parsed as they are opened
+    #[clap(long, conflicts_with_all = ["validate", "count"])]
+    tui: bool,
+
+    /// Only write the deduplicated list of participants with their message totals, to
+    /// `--output` (CSV for `.csv`, otherwise JSON) or stdout
+    #[clap(long, conflicts_with_all = ["validate", "count", "tui", "redact"])]
+    participants_only: bool,
+
+    /// Use the single "Unknown" placeholder for every unresolved phone number instead of a
+    /// per-file placeholder. Unknown contacts from different files then share one phone.
+    #[clap(long)]
+    collapse_unknown_phones: bool,
+
+    /// Give every sender whose number can't be resolved this phone instead of a per-file
+    /// placeholder, e.g. a sentinel number your schema accepts (implies
+    /// `--collapse-unknown-phones`, which uses "Unknown")
+    #[clap(long, value_name = "STR")]
+    unknown_phone_placeholder: Option<String>,
+
+    /// Name senders whose markup has no name with this instead of leaving the name empty
+    #[clap(long, value_name = "STR")]
+    unknown_name_placeholder: Option<String>,
+
+    /// Record committed source files here during a Neo4j import
+    #[clap(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Resume a Neo4j import, skipping files already listed in the checkpoint
+    #[clap(long, requires = "checkpoint")]
+    resume: bool,
+
+    /// Keep a Neo4j import under this many messages written per second on average, to
+    /// go easy on a shared database; unlimited by default
+    #[clap(long, value_name = "MSGS_PER_SEC", value_parser = parse_rate)]
+    neo4j_max_rate: Option<f64>,
+
+    /// Write the Neo4j import to this database of a multi-database server instead of the
+    /// server's default one
+    #[clap(long, value_name = "NAME")]
+    neo4j_database: Option<String>,
+
+    /// Put this in front of the `Participant`, `Conversation` and `Message` node labels
+    /// (e.g. `GV` for `GVParticipant`), to keep the import apart from other graph data
+    #[clap(long, value_name = "STR", value_parser = parse_label_prefix, default_value = "")]
+    node_label_prefix: String,
+
+    /// Also keep a `CONVERSED_WITH` relationship between every two participants who share
+    /// a thread, counting their threads and the messages between them
+    #[clap(long)]
+    conversed_with: bool,
+
+    /// Attach the approximate region (state/province) of North American numbers to
+    /// each participant, using a bundled area code table
+    #[clap(long)]
+    geo: bool,
+
+    /// Split each thread into sessions wherever consecutive messages are further apart
+    /// than this duration (e.g. `90m`, `24h`, `7d`); each session becomes its own thread
+    #[clap(long, value_parser = parse_duration)]
+    session_gap: Option<chrono::Duration>,
+
+    /// Copy every message attachment into this directory, recording the new path
+    #[clap(long, value_name = "DIR")]
+    extract_media: Option<PathBuf>,
+
+    /// With the html format, embed images in the pages as base64 data instead of linking
+    /// to the files, so the pages are self-contained
+    #[clap(long)]
+    embed_media: bool,
+
+    /// Largest image `--embed-media` embeds (e.g. `500K`, `5M`); bigger ones are linked
+    #[clap(long, value_name = "SIZE", value_parser = parse_size, default_value = "5M", requires = "embed_media")]
+    embed_max_size: u64,
+
+    /// With the html format, show a run of messages from the same sender under one name
+    /// and time instead of repeating them on every message
+    #[clap(long)]
+    collapse_consecutive: bool,
+
+    /// Longest gap between two messages of a `--collapse-consecutive` run (e.g. `90s`, `5m`)
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        default_value = "5m",
+        requires = "collapse_consecutive"
+    )]
+    collapse_window: chrono::Duration,
+
+    /// With the `ics` format, write an event per conversation-day (`day`) or per message
+    #[clap(long, value_enum, value_name = "day|message", default_value = "day")]
+    ics_events: IcsEvents,
+
+    /// Phone number belonging to you; repeat for multi-account exports. A sender with any
+    /// of these numbers is treated as yourself, in addition to senders named "Me"
+    #[clap(long = "me-phone", value_name = "PHONE")]
+    me_phones: Vec<String>,
+
+    /// Your name as it should appear in the output instead of "Me"; a sender with this
+    /// name is also treated as yourself
+    #[clap(long, value_name = "NAME")]
+    me_name: Option<String>,
+
+    /// Abort the run once more than this many files have failed to parse
+    #[clap(long, value_name = "N")]
+    max_failures: Option<usize>,
+
+    /// Abort the run once more than this percentage of the files attempted so far have
+    /// failed to parse; checked after at least 20 files, and again at the end of the run
+    #[clap(long, value_name = "PCT", value_parser = parse_percentage)]
+    max_failure_rate: Option<f64>,
+
+    /// Parse files even when their content is identical to a file already seen in this run
+    #[clap(long)]
+    allow_duplicate_files: bool,
+
+    /// Only keep threads that have this label (case-insensitive); repeat to accept several.
+    /// Threads without labels are dropped while a filter is active.
+    #[clap(long = "label", value_name = "NAME")]
+    labels: Vec<String>,
+
+    /// Keep threads rejected by `--label` in the output with no messages, to see which
+    /// conversations a filter removed
+    #[clap(long)]
+    keep_empty: bool,
+
+    /// Drop threads with fewer than this many messages, counted after the other filters
+    /// and after splitting with `--session-gap`
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    min_messages: usize,
+
+    /// Only keep group threads: those with more than two participants (you included) or
+    /// from a `Group Conversation` file
+    #[clap(long, conflicts_with = "only_direct")]
+    only_group: bool,
+
+    /// Only keep one-on-one threads (and notes to yourself): those that aren't group
+    /// threads by the `--only-group` rule
+    #[clap(long)]
+    only_direct: bool,
+
+    /// Only keep threads with this contact, given as a name (case-insensitive) or a phone
+    /// number (compared by digits); repeat to accept several. With a single contact the
+    /// default format prints the conversation with them instead of statistics
+    #[clap(long = "participant", value_name = "NAME|PHONE")]
+    participants: Vec<String>,
+
+    /// Sort each thread's messages by timestamp (ties keep document order).
+    /// Without this flag messages are emitted in original document order.
+    #[clap(long)]
+    sort_messages: bool,
+
+    /// Attach the original markup of each message as `raw_html`, for debugging the parser
+    #[clap(long, conflicts_with = "redact")]
+    include_raw: bool,
+
+    /// Number the threads in the order they are written (`thread_index`, from 0 across
+    /// the run) and each thread's messages (`message_index`, from 0), so the output can
+    /// be put back in order without relying on timestamps
+    #[clap(long)]
+    include_indices: bool,
+
+    /// Fail on files that aren't valid UTF-8 instead of replacing the invalid bytes with
+    /// U+FFFD and logging a warning
+    #[clap(long)]
+    strict_encoding: bool,
+
+    /// Trim message content, collapse whitespace runs (including newlines and tabs) to a
+    /// single space and normalize it to Unicode NFC. Without it content is kept as written.
+    #[clap(long)]
+    normalize_content: bool,
+
+    /// Store the inner HTML of each message's `<q>` exactly as written (entities, tags and
+    /// whitespace included) as its content, for archival. Consumers must decode it.
+    #[clap(long, conflicts_with = "normalize_content")]
+    raw_content: bool,
+
+    /// Trim participant names and collapse runs of whitespace inside them, keeping the
+    /// original as `raw_name`, so the same contact gets the same name everywhere
+    #[clap(long)]
+    clean_names: bool,
+
+    /// With `--clean-names`, also drop trailing parenthetical annotations such as
+    /// `(Mobile)` from names
+    #[clap(long, requires = "clean_names")]
+    strip_name_annotations: bool,
+
+    /// Number of threads parsing files of a directory concurrently. With more than one,
+    /// threads are written in the order files finish parsing rather than file order.
+    #[clap(long, value_name = "N", default_value = "1")]
+    jobs: NonZeroUsize,
+
+    /// Order in which the files of a directory are processed: by path (reproducible
+    /// output), by modification time, or as the filesystem lists them (fastest)
+    #[clap(long, value_name = "ORDER", value_enum, default_value_t = FileOrder::Name)]
+    sort_files: FileOrder,
+
+    /// How many parsed files may wait for the writer before parsing pauses; also the
+    /// number of threads queued for the Neo4j writer
+    #[clap(long, value_name = "N", default_value = "100")]
+    channel_capacity: NonZeroUsize,
+
+    /// Threads handed to the Neo4j writer at a time, so runs of many small files don't
+    /// pay a channel handoff per thread
+    #[clap(long, value_name = "N", default_value = "32")]
+    neo4j_send_batch: NonZeroUsize,
+
+    /// Keep parsed threads in this directory, keyed by file content and parse options, so
+    /// later runs only parse files that changed
+    #[clap(long, value_name = "DIR")]
+    cache: Option<PathBuf>,
+
+    /// Override display names from a TOML (`"key" = "Name"`) or CSV (`key,Name`) file,
+    /// where each key is a phone number or a name as parsed
+    #[clap(long, value_name = "FILE")]
+    rename_map: Option<PathBuf>,
+
+    /// Tag messages with the keyword categories their content matches, from a `.toml`
+    /// file of `category = ["term", ...]` or a CSV file of `category,term` lines
+    #[clap(long, value_name = "FILE")]
+    flag_keywords: Option<PathBuf>,
+
+    /// Classify every participant as a person, business or automated sender (short
+    /// codes), by heuristic rules; counts per type are added to the statistics
+    #[clap(long)]
+    classify_participants: bool,
+
+    /// TOML file replacing some or all of the `--classify-participants` rules: business
+    /// and automated name patterns, lists of names and numbers, the short code length
+    #[clap(long, value_name = "FILE", requires = "classify_participants")]
+    participant_rules: Option<PathBuf>,
+
+    /// Tag each message with the language of its content (ISO 639-3, e.g. `eng`, `spa`)
+    /// when it can be detected reliably
+    #[clap(long)]
+    detect_language: bool,
+
+    /// Drop reactions and status notices (read receipts, typing indicators) instead of
+    /// emitting them as messages with a `reaction` or `system` kind
+    #[clap(long)]
+    skip_system: bool,
+
+    /// TOML file of `senders` and `content` regexes that recognize Google Voice and
+    /// carrier notices, replacing the built-in English patterns
+    #[clap(long, value_name = "FILE")]
+    system_patterns: Option<PathBuf>,
+
+    /// Also read voicemail files (`... - Voicemail - ...`), each as a thread of one
+    /// incoming message holding the transcription and the recording
+    #[clap(long)]
+    voicemails: bool,
+
+    /// Drop voicemails whose transcription is missing or a placeholder such as
+    /// "(Unable to transcribe)", and report how many were dropped
+    #[clap(long, requires = "voicemails")]
+    skip_untranscribed: bool,
+
+    /// A transcription that stands for none, in addition to the built-in English ones,
+    /// e.g. the placeholder of another language; may be repeated
+    #[clap(long = "untranscribed-placeholder", value_name = "TEXT", requires = "voicemails")]
+    untranscribed_placeholders: Vec<String>,
+
+    /// Drop messages that have no timestamp element at all instead of dating them to the
+    /// Unix epoch (messages whose timestamp can't be parsed are kept either way)
+    #[clap(long)]
+    drop_undated: bool,
+
+    /// Keep messages that a file repeats within one conversation (same sender, time,
+    /// content and attachments) instead of dropping all but the first
+    #[clap(long)]
+    keep_intra_duplicates: bool,
+
+    /// In group threads, address every message to all participants but its sender,
+    /// rather than only to you when someone else sends it
+    #[clap(long)]
+    group_full_recipients: bool,
+
+    /// Cut message content after this many characters (grapheme clusters), ending it
+    /// with `…` and marking the message `truncated`
+    #[clap(long, value_name = "N")]
+    max_content_length: Option<NonZeroUsize>,
+
+    /// Anonymize output for sharing: message text is masked (keeping its length), phone
+    /// numbers are hashed and display names replaced
+    #[clap(long)]
+    redact: bool,
+
+    /// Combine contacts whose names are alike (e.g. `Mom` and `Mom ❤️` on two numbers)
+    /// into one participant, listing the originals in `merged_from`. Heuristic: the
+    /// clusters formed are printed so they can be checked.
+    #[clap(long, conflicts_with_all = ["count", "tui", "validate"])]
+    merge_similar_participants: bool,
+
+    /// Name similarity (Jaro-Winkler, 0 to 1) from which `--merge-similar-participants`
+    /// treats two contacts as the same person
+    #[clap(
+        long,
+        value_name = "SIMILARITY",
+        default_value = "0.9",
+        requires = "merge_similar_participants",
+        value_parser = parse_similarity
+    )]
+    merge_threshold: f64,
+
+    /// With `--redact`, keep display names and source file names
+    #[clap(long, requires = "redact")]
+    redact_keep_names: bool,
+
+    /// Succeed even when an input directory contains no matching conversations
+    #[clap(long)]
+    allow_empty: bool,
+
+    /// Replace existing output files and write into non-empty output directories;
+    /// without it a run refuses to start when any of them exists
+    #[clap(long)]
+    force: bool,
+
+    /// Keep a record of the threads written to the `--output` JSON, NDJSON or CSV file
+    /// here, so an interrupted export can be finished later with `--append`
+    #[clap(long, value_name = "FILE", requires = "output")]
+    state: Option<PathBuf>,
+
+    /// Resume the export to `--output` that `--state` recorded: threads it lists are
+    /// skipped and the rest are appended
+    #[clap(long, requires = "state")]
+    append: bool,
+
+    /// After the initial pass, keep watching the input directories and process new or
+    /// changed conversation files as they appear, until Ctrl-C
+    #[clap(long, conflicts_with_all = ["count", "tui", "validate", "participants_only"])]
+    watch: bool,
+
+    /// Print a trace of how this file is parsed instead of converting anything: the
+    /// elements each selector matched, how senders resolved to participants and whether
+    /// they were taken to be you, which timestamps parsed and why messages got their
+    /// direction
+    #[clap(long, value_name = "FILE")]
+    explain: Option<PathBuf>,
+
+    /// Print how many elements each selector matches in this file, and the tags and
+    /// classes of the elements at the top of the document, instead of converting anything
+    #[clap(long, value_name = "FILE", conflicts_with = "explain")]
+    dump_selectors: Option<PathBuf>,
+
+    /// Check that the Neo4j database is reachable and writable (a write that is rolled
+    /// back) and exit, reporting how long each step took
+    #[clap(long, conflicts_with_all = ["explain", "dump_selectors"])]
+    neo4j_check: bool,
+}
+
+// Tracks file content hashes across a run so byte-identical copies of an export
+// (e.g. the same backup under two paths) are skipped before parsing
+#[derive(Debug, Default)]
+struct FileDeduper {
+    enabled: bool,
+    seen: HashSet<blake3::Hash>,
+    duplicates: usize,
+}
+
+impl FileDeduper {
+    fn new(enabled: bool) -> Self {
+        FileDeduper {
+            enabled,
+            ..Default::default()
+        }
+    }
+
+    // Returns true if a file with the same content was already seen in this run
+    fn is_duplicate(&mut self, path: &Path) -> Result<bool> {
+        if !self.enabled {
+            return Ok(false);
+        }
+        let content = fs::read(path).with_context(|| format!("Failed to read file: {:?}", path))?;
+        if self.seen.insert(blake3::hash(&content)) {
+            Ok(false)
+        } else {
+            info!("Skipping duplicate file: {:?}", path);
+            self.duplicates += 1;
+            Ok(true)
+        }
+    }
+}
+
+// Keeps only threads carrying one of the `--label` names, of the `--only-group` or
+// `--only-direct` type and with at least `--min-messages` messages, counting how many
+// each check removed
+#[derive(Debug, Default)]
+struct ThreadFilter {
+    /// Accepted labels, lowercased
+    labels: Vec<String>,
+    /// Emit threads without a wanted label, emptied of their messages, instead of
+    /// dropping them
+    keep_empty: bool,
+    /// Keep only group threads (`Some(true)`) or only one-on-one ones (`Some(false)`)
+    group: Option<bool>,
+    /// Contacts of which a thread has to include one, see `is_participant`
+    participants: Vec<String>,
+    min_messages: usize,
+    /// `--skip-untranscribed`
+    skip_untranscribed: bool,
+    /// Threads carrying a wanted label
+    matched: usize,
+    /// Matching threads dropped for including none of `participants`
+    without_participant: usize,
+    /// Matching threads that are group threads and that aren't, with a type filter
+    groups: usize,
+    directs: usize,
+    /// Matching threads dropped for having fewer than `min_messages` messages
+    too_small: usize,
+    /// Voicemails dropped for having no usable transcription
+    untranscribed: usize,
+    total: usize,
+}
+
+// A group thread has more than two participants (you included), or comes from a `Group
+// Conversation` file even if the other members can't be told apart
+fn is_group_thread(thread: &Thread) -> bool {
+    is_group_conversation(&thread.participants, &thread.source_path)
+}
+
+fn is_group_conversation(participants: &[Participant], source_path: &str) -> bool {
+    participants.len() > 2 || source_path.contains(GROUP_CONVERSATION_LABEL)
+}
+
+// A voicemail whose transcription is missing or a placeholder, see `parse_voicemail`
+fn is_untranscribed_voicemail(thread: &Thread) -> bool {
+    thread.warnings
+        .iter()
+        .any(|warning| matches!(warning, ParseWarning::UntranscribedVoicemail { .. }))
+}
+
+// Whether `--participant` `query` names this participant: the same phone digits (as
+// contacts are merged in `--participants-only`) or the same name ignoring case
+fn is_participant(query: &str, participant: &Participant) -> bool {
+    let digits = phone_digits(query);
+    (!digits.is_empty() && per_contact::contact_key(participant) == digits) ||
+        participant.name.trim().to_lowercase() == query.trim().to_lowercase()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterVerdict {
+    Keep,
+    WrongLabel,
+    WrongParticipant,
+    WrongType,
+    TooSmall,
+    Untranscribed,
+}
+
+impl ThreadFilter {
+    fn new(
+        labels: &[String],
+        keep_empty: bool,
+        group: Option<bool>,
+        participants: &[String],
+        min_messages: usize,
+        skip_untranscribed: bool
+    ) -> Self {
+        ThreadFilter {
+            labels: labels
+                .iter()
+                .map(|label| label.to_lowercase())
+                .collect(),
+            keep_empty,
+            group,
+            participants: participants.to_vec(),
+            min_messages,
+            skip_untranscribed,
+            ..Default::default()
+        }
+    }
+
+    // Pass a kept thread through. A thread without a wanted label is dropped, or
+    // emptied of its messages with `--keep-empty`; one without a wanted participant, of
+    // the wrong type, below `--min-messages` or an untranscribed voicemail with
+    // `--skip-untranscribed` is always dropped. The flag tells whether
+    // the thread matched.
+    fn filter(&mut self, thread: Thread) -> Option<(Thread, bool)> {
+        match self.check(&thread) {
+            FilterVerdict::Keep => Some((thread, true)),
+            FilterVerdict::WrongLabel if self.keep_empty => Some((empty_thread(thread), false)),
+            FilterVerdict::WrongLabel |
+            FilterVerdict::WrongParticipant |
+            FilterVerdict::WrongType |
+            FilterVerdict::TooSmall |
+            FilterVerdict::Untranscribed => None,
+        }
+    }
+
+    fn keep(&mut self, thread: &Thread) -> bool {
+        self.check(thread) == FilterVerdict::Keep
+    }
+
+    fn check(&mut self, thread: &Thread) -> FilterVerdict {
+        self.total += 1;
+        let labelled =
+            self.labels.is_empty() ||
+            thread.labels.iter().any(|label| self.labels.contains(&label.to_lowercase()));
+        if !labelled {
+            return FilterVerdict::WrongLabel;
+        }
+        self.matched += 1;
+        let with_participant =
+            self.participants.is_empty() ||
+            takeout_writer::other_participants(thread)
+                .into_iter()
+                .any(|p| self.participants.iter().any(|query| is_participant(query, p)));
+        if !with_participant {
+            self.without_participant += 1;
+            return FilterVerdict::WrongParticipant;
+        }
+        if let Some(group) = self.group {
+            let is_group = is_group_thread(thread);
+            if is_group {
+                self.groups += 1;
+            } else {
+                self.directs += 1;
+            }
+            if is_group != group {
+                return FilterVerdict::WrongType;
+            }
+        }
+        if thread.message_count < self.min_messages {
+            self.too_small += 1;
+            return FilterVerdict::TooSmall;
+        }
+        if self.skip_untranscribed && is_untranscribed_voicemail(thread) {
+            self.untranscribed += 1;
+            return FilterVerdict::Untranscribed;
+        }
+        FilterVerdict::Keep
+    }
+
+    fn dropped_by_type(&self) -> usize {
+        match self.group {
+            Some(true) => self.directs,
+            Some(false) => self.groups,
+            None => 0,
+        }
+    }
+
+    fn report(&self) {
+        if !self.labels.is_empty() {
+            eprintln!("Label filter matched {} of {} threads", self.matched, self.total);
+        }
+        if !self.participants.is_empty() {
+            eprintln!(
+                "Participant filter matched {} of {} threads",
+                self.matched - self.without_participant,
+                self.matched
+            );
+        }
+        if let Some(group) = self.group {
+            eprintln!(
+                "Thread types: {} group, {} one-on-one; kept the {} threads",
+                self.groups,
+                self.directs,
+                if group { "group" } else { "one-on-one" }
+            );
+        }
+        if self.min_messages > 0 {
+            eprintln!(
+                "Dropped {} of {} threads with fewer than {} messages",
+                self.too_small,
+                self.matched - self.without_participant - self.dropped_by_type(),
+                self.min_messages
+            );
+        }
+        if self.skip_untranscribed {
+            eprintln!("Skipped {} untranscribed voicemails", self.untranscribed);
+        }
+    }
+}
+
+// Files attempted before `--max-failure-rate` is checked mid-run, so a single early
+// failure doesn't abort the whole import
+const MIN_FILES_FOR_FAILURE_RATE: usize = 20;
+
+// Counts parse failures across a run and aborts it once a configured limit is exceeded.
+// Below the limits failures are only logged, and the run reports them at the end.
+#[derive(Debug, Default)]
+struct FailureTracker {
+    max_failures: Option<usize>,
+    /// Percentage of attempted files
+    max_rate: Option<f64>,
+    attempted: usize,
+    failed: usize,
+}
+
+impl FailureTracker {
+    fn new(max_failures: Option<usize>, max_rate: Option<f64>) -> Self {
+        FailureTracker {
+            max_failures,
+            max_rate,
+            ..Default::default()
+        }
+    }
+
+    fn record_success(&mut self) {
+        self.attempted += 1;
+    }
+
+    fn record_failure(&mut self, path: &Path, error: &anyhow::Error) -> Result<()> {
+        error!("Failed to parse file {:?}: {:#}", path, error);
+        self.attempted += 1;
+        self.failed += 1;
+        self.check(false)
+    }
+
+    // Error out when a limit is exceeded; the rate is only judged on a reasonable sample
+    // until the run is over
+    fn check(&self, finished: bool) -> Result<()> {
+        if let Some(max) = self.max_failures {
+            if self.failed > max {
+                return Err(
+                    anyhow::anyhow!("{} files failed to parse, more than the limit of {}", self.failed, max)
+                        .context(Failure::ThresholdExceeded)
+                );
+            }
+        }
+        if let Some(max_rate) = self.max_rate {
+            let rate = ((self.failed as f64) * 100.0) / (self.attempted.max(1) as f64);
+            if (finished || self.attempted >= MIN_FILES_FOR_FAILURE_RATE) && rate > max_rate {
+                return Err(
+                    anyhow::anyhow!(
+                        "{} of {} files ({:.1}%) failed to parse, more than the limit of {}%",
+                        self.failed,
+                        self.attempted,
+                        rate,
+                        max_rate
+                    ).context(Failure::ThresholdExceeded)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // The run's result: over a limit, or some (tolerated) failures, or success
+    fn finish(&self) -> Result<()> {
+        self.check(true)?;
+        parse_failures(self.failed)
+    }
+}
+
+// Options that control how an individual file is parsed
+#[derive(Debug, Clone, Default)]
+struct ParseOptions {
+    sort_messages: bool,
+    /// The one phone of every unresolved sender, with `--collapse-unknown-phones` or
+    /// `--unknown-phone-placeholder`
+    unknown_phone: Option<String>,
+    unknown_name: Option<String>,
+    geo: bool,
+    session_gap: Option<chrono::Duration>,
+    extract_media: Option<PathBuf>,
+    /// Self phone numbers, reduced to digits for comparison
+    me_phones: Vec<String>,
+    /// First `--me-phone` as given, used when the export has no number for you
+    me_phone: Option<String>,
+    me_name: Option<String>,
+    redact: bool,
+    redact_keep_names: bool,
+    include_raw: bool,
+    strict_encoding: bool,
+    normalize_content: bool,
+    raw_content: bool,
+    skip_system: bool,
+    notices: notices::NoticePatterns,
+    voicemails: bool,
+    untranscribed: voicemail::Placeholders,
+    drop_undated: bool,
+    keep_intra_duplicates: bool,
+    group_full_recipients: bool,
+    max_content_length: Option<usize>,
+    detect_language: bool,
+    clean_names: bool,
+    strip_name_annotations: bool,
+    rename_map: Option<rename::RenameMap>,
+    keywords: Option<keywords::KeywordList>,
+    classifier: Option<classify::ParticipantClassifier>,
+    /// Clusters from `--merge-similar-participants`, applied after parsing
+    merger: Option<Arc<merge::ParticipantMerger>>,
+    /// `--cache` of parsed files; parse options added above must be reflected in
+    /// `cache_fingerprint`
+    cache: Option<Arc<cache::ThreadCache>>,
+    /// Files of a directory parsed concurrently
+    jobs: usize,
+    /// `--sort-files`
+    file_order: FileOrder,
+    /// What the run writes, which a directory walk never reads back as input
+    run_outputs: RunOutputs,
+    /// Parsed files buffered ahead of the writer
+    channel_capacity: usize,
+}
+
+// Settings for the Neo4j writer shared by every directory and file in a run
+#[derive(Debug, Default)]
+struct Neo4jOptions {
+    checkpoint: Option<PathBuf>,
+    /// Threads queued for the writer before senders block
+    channel_capacity: usize,
+    /// Threads sent to the writer at a time
+    send_batch: usize,
+    /// Source paths already committed by a previous run, skipped when resuming
+    committed: HashSet<String>,
+    /// `--neo4j-max-rate` in messages per second
+    max_rate: Option<f64>,
+    /// `--neo4j-database` and `--node-label-prefix`
+    target: cypher_writer::GraphTarget,
+    /// `--conversed-with`
+    conversed_with: bool,
+}
+
+impl Neo4jOptions {
+    fn from_cli(cli: &Cli) -> Result<Self> {
+        let committed = match &cli.checkpoint {
+            Some(path) if cli.resume => Checkpoint::load(path)?,
+            Some(path) => {
+                Checkpoint::reset(path)?;
+                HashSet::new()
+            }
+            None => HashSet::new(),
+        };
+        Ok(Neo4jOptions {
+            checkpoint: cli.checkpoint.clone(),
+            channel_capacity: cli.channel_capacity.get(),
+            send_batch: cli.neo4j_send_batch.get(),
+            committed,
+            max_rate: cli.neo4j_max_rate,
+            target: graph_target(cli),
+            conversed_with: cli.conversed_with,
+        })
+    }
+}
+
+fn graph_target(cli: &Cli) -> cypher_writer::GraphTarget {
+    cypher_writer::GraphTarget {
+        database: cli.neo4j_database.clone(),
+        label_prefix: cli.node_label_prefix.clone(),
+    }
+}
+
+impl ParseOptions {
+    fn from_cli(cli: &Cli) -> Result<Self> {
+        // Before any file is parsed, so a bad selector fails the run rather than each file
+        selectors::compile()?;
+        Ok(ParseOptions {
+            sort_messages: cli.sort_messages,
+            unknown_phone: cli.unknown_phone_placeholder
+                .clone()
+                .or_else(|| cli.collapse_unknown_phones.then(|| UNKNOWN_PHONE.to_string())),
+            unknown_name: cli.unknown_name_placeholder.clone(),
+            geo: cli.geo,
+            session_gap: cli.session_gap,
+            extract_media: cli.extract_media.clone(),
+            me_phones: cli.me_phones
+                .iter()
+                .map(|phone| phone_digits(phone))
+                .collect(),
+            me_phone: cli.me_phones.first().cloned(),
+            me_name: cli.me_name.clone(),
+            redact: cli.redact,
+            redact_keep_names: cli.redact_keep_names,
+            include_raw: cli.include_raw,
+            strict_encoding: cli.strict_encoding,
+            normalize_content: cli.normalize_content,
+            raw_content: cli.raw_content,
+            skip_system: cli.skip_system,
+            notices: notices::NoticePatterns::load(cli.system_patterns.as_deref())?,
+            voicemails: cli.voicemails,
+            untranscribed: voicemail::Placeholders::new(&cli.untranscribed_placeholders),
+            drop_undated: cli.drop_undated,
+            keep_intra_duplicates: cli.keep_intra_duplicates,
+            group_full_recipients: cli.group_full_recipients,
+            max_content_length: cli.max_content_length.map(NonZeroUsize::get),
+            detect_language: cli.detect_language,
+            clean_names: cli.clean_names,
+            strip_name_annotations: cli.strip_name_annotations,
+            rename_map: cli.rename_map.as_deref().map(rename::RenameMap::load).transpose()?,
+            keywords: cli.flag_keywords.as_deref().map(keywords::KeywordList::load).transpose()?,
+            classifier: if cli.classify_participants {
+                Some(classify::ParticipantClassifier::load(cli.participant_rules.as_deref())?)
+            } else {
+                None
+            },
+            merger: None,
+            cache: match &cli.cache {
+                Some(dir) => Some(Arc::new(cache::ThreadCache::open(dir, cache_fingerprint(cli)?)?)),
+                None => None,
+            },
+            jobs: cli.jobs.get(),
+            file_order: cli.sort_files,
+            run_outputs: RunOutputs::from_cli(cli),
+            channel_capacity: cli.channel_capacity.get(),
+        })
+    }
+
+    // Whether `phone` is one of the configured self numbers
+    fn is_me_phone(&self, phone: &str) -> bool {
+        !self.me_phones.is_empty() && self.me_phones.contains(&phone_digits(phone))
+    }
+
+    // Whether a sender is the account owner: by markup where it says, otherwise by name
+    fn is_me_sender(&self, sender_element: &ElementRef, name: &str) -> bool {
+        sender_is_self(sender_element).unwrap_or(
+            name == "Me" || self.me_name.as_deref() == Some(name)
+        )
+    }
+}
+
+// Everything other than a file's path and content that changes what `parse_file` makes
+// of it, so cache entries are only reused for an identical parse. The mapping, keyword,
+// rule and pattern files are included by content.
+fn cache_fingerprint(cli: &Cli) -> Result<String> {
+    let mut fingerprint = format!(
+        "{} {:?} {:?}",
+        env!("CARGO_PKG_VERSION"),
+        (&cli.me_phones, &cli.me_name, cli.clean_names, cli.strip_name_annotations, cli.classify_participants),
+        (
+            cli.sort_messages,
+            (cli.collapse_unknown_phones, &cli.unknown_phone_placeholder, &cli.unknown_name_placeholder),
+            cli.geo,
+            cli.include_raw,
+            cli.strict_encoding,
+            cli.normalize_content,
+            cli.raw_content,
+            (cli.skip_system, cli.voicemails, &cli.untranscribed_placeholders),
+            (cli.drop_undated, cli.keep_intra_duplicates),
+            cli.group_full_recipients,
+            cli.detect_language,
+            cli.max_content_length,
+        )
+    );
+    let files = [
+        ("rename-map", &cli.rename_map),
+        ("flag-keywords", &cli.flag_keywords),
+        ("participant-rules", &cli.participant_rules),
+        ("system-patterns", &cli.system_patterns),
+    ];
+    for (flag, file) in files {
+        let Some(file) = file else {
+            continue;
+        };
+        let content = fs::read(file).with_context(|| format!("Failed to read {:?}", file))?;
+        fingerprint.push_str(&format!(" {}={}", flag, blake3::hash(&content).to_hex()));
+    }
+    Ok(fingerprint)
+}
+
+// Reduce a phone number to its digits so `+1 (650) 207-4150` matches `+16502074150`
+fn phone_digits(phone: &str) -> String {
+    phone
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect()
+}
+
+// Enum to represent different output formats
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+enum OutputFormat {
+    Debug,
+    Json,
+    Default,
+    Cypher,
+    /// Columnar export of every message into a single file; requires `--output`
+    Parquet,
+    /// Static chat-log pages, one per thread plus an index; `--output` names the directory
+    Html,
+    /// A single YAML document holding a sequence of threads
+    Yaml,
+    /// One JSON document wrapping every thread with run metadata and statistics
+    Archive,
+    /// Flat message-backup JSON (sender, recipients, timestamp_ms, body, thread_id)
+    /// accepted by Signal-style importers
+    SignalJson,
+    /// Google Voice Takeout HTML again, one file per thread in the `--output` directory;
+    /// e.g. with `--redact`, for making test fixtures
+    Takeout,
+    /// iCalendar events of when conversations took place, see `--ics-events`
+    Ics,
+    /// Length-delimited protobuf `Thread` messages, see `schema/google_voice.proto`
+    Protobuf,
+    /// One JSON thread per line, whatever `--compact` says
+    Ndjson,
+    /// One row per message with its thread's context, in the columns of a `.csv` `--timeline`
+    Csv,
+    /// A readable chat log, a section per thread with a heading per day
+    Markdown,
+}
+
+// What each event of the `ics` format stands for, see `--ics-events`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum IcsEvents {
+    /// One event per conversation and day, from its first to its last message
+    #[default]
+    Day,
+    /// One event per message
+    Message,
+}
+
+// The period each file of `--split-by` covers
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum SplitPeriod {
+    Month,
+    Year,
+}
+
+// How `--sort-files` orders the files found in a directory
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum FileOrder {
+    /// By file name within each directory, walking subdirectories in name order too
+    #[default]
+    Name,
+    /// Oldest modification time first, ties by path
+    Mtime,
+    /// As the filesystem returns them, without sorting
+    None,
+}
+
+// Where `--per-contact` puts the messages of group conversations
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum GroupFanOut {
+    /// Into the file of every member other than yourself
+    #[default]
+    All,
+    /// Nowhere; only one-on-one conversations are written
+    None,
+}
+
+// Key naming for JSON thread output, see `--json-case`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum JsonCase {
+    #[default]
+    Snake,
+    Camel,
+}
+
+impl OutputFormat {
+    // Infer the output format from an output file's extension
+    fn from_extension(path: &Path) -> Option<OutputFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(OutputFormat::Json),
+            "ndjson" | "jsonl" => Some(OutputFormat::Ndjson),
+            "csv" => Some(OutputFormat::Csv),
+            "md" | "markdown" => Some(OutputFormat::Markdown),
+            "parquet" => Some(OutputFormat::Parquet),
+            "yaml" | "yml" => Some(OutputFormat::Yaml),
+            "pb" | "binpb" => Some(OutputFormat::Protobuf),
+            "ics" => Some(OutputFormat::Ics),
+            _ => None,
+        }
+    }
+}
+
+// How parsed threads are written out
+#[derive(Debug, Clone, Copy)]
+struct OutputOptions {
+    format: OutputFormat,
+    compact_json: bool,
+    json_case: JsonCase,
+    /// Estimate the unique-participant statistic instead of keeping every participant
+    approx_participants: bool,
+    /// Size cap for images embedded in HTML pages with `--embed-media`
+    embed_media: Option<u64>,
+    /// Window of `--collapse-consecutive` in HTML pages
+    collapse: Option<chrono::Duration>,
+    ics_events: IcsEvents,
+    /// Display zone for human-readable formats
+    tz: Tz,
+    /// `--sample` size and seed; replaces the format's own writer
+    sample: Option<Sampling>,
+    /// `--split-by` period; replaces the format's own writer
+    split_by: Option<SplitPeriod>,
+    /// The default format prints a `--participant` transcript instead of statistics
+    transcript: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sampling {
+    size: usize,
+    seed: u64,
+}
+
+// Distinct participants (by name and phone) seen in a run: kept exactly, or estimated
+// in fixed memory with `--approx-participants`
+enum ParticipantCounter {
+    Exact(HashSet<Participant>),
+    Approximate(hll::HyperLogLog),
+}
+
+impl ParticipantCounter {
+    fn add(&mut self, participants: &[Participant]) {
+        match self {
+            ParticipantCounter::Exact(seen) => seen.extend(participants.iter().cloned()),
+            ParticipantCounter::Approximate(hll) => {
+                for participant in participants {
+                    // Same identity as `Participant`'s `Eq`: name and phone
+                    hll.insert(format!("{}\0{}", participant.name, participant.phone).as_bytes());
+                }
+            }
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            ParticipantCounter::Exact(seen) => seen.len(),
+            ParticipantCounter::Approximate(hll) => hll.estimate(),
+        }
+    }
+}
+
+// Struct to hold statistics about the processing run
+#[derive(Debug)]
+struct RunStatistics {
+    duration: std::time::Duration,
+    files_processed: usize,
+    messages_extracted: usize,
+    unique_participants: usize,
+    /// `unique_participants` is a `--approx-participants` estimate
+    participants_estimated: bool,
+    avg_messages_per_file: f64,
+    misattributed_senders: usize,
+    /// Files whose parsed message count didn't match their `.message` elements
+    message_count_mismatches: usize,
+    /// Messages a file repeated within a conversation, dropped unless
+    /// `--keep-intra-duplicates`
+    repeated_messages: usize,
+    /// Messages without a `.dt` element, dropped with `--drop-undated`
+    missing_timestamps: usize,
+    dropped_undated: bool,
+    /// Messages whose `.dt` element couldn't be parsed
+    invalid_timestamps: usize,
+    duplicate_files: usize,
+    /// Messages flagged per `--flag-keywords` category, when a list is given
+    keyword_matches: Option<BTreeMap<String, usize>>,
+    /// Files loaded from and missing from the `--cache`, when one is used
+    cache_counts: Option<(usize, usize)>,
+    /// Distinct participants (by name and phone) of each type, with
+    /// `--classify-participants`
+    participant_types: Option<BTreeMap<classify::ParticipantType, usize>>,
+    /// Messages cut at `--max-content-length`, when it is given
+    truncated_messages: Option<usize>,
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            let failure = e.downcast_ref::<Failure>().copied();
+            ExitCode::from(failure.map_or(1, Failure::exit_code))
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    info!("Starting...");
+    let cli = Cli::parse();
+    install_interrupt_handler()?;
+    timestamp::set_format(cli.timestamp_format);
+    if let Some(file) = &cli.explain {
+        return explain::explain(file, &ParseOptions::from_cli(&cli)?);
+    }
+    if let Some(file) = &cli.dump_selectors {
+        return explain::dump_selectors(file, &ParseOptions::from_cli(&cli)?);
+    }
+    if cli.neo4j_check {
+        return cypher_writer::check(&graph_target(&cli)).context(Failure::Output);
+    }
+    let input = cli.input.as_deref().context("No input given")?;
+
+    // An explicit format wins; otherwise fall back to the output file extension. With
+    // `--emit` the output is a directory and the run itself reports like the default format,
+    // as it does for `--split-by`, whose format is its own, and `--participants-only`, which
+    // writes the roster there. An extension that names no format is an error rather than a
+    // run that writes nothing.
+    let format = match (cli.format, &cli.output) {
+        _ if !cli.emit.is_empty() => OutputFormat::Default,
+        _ if cli.split_by.is_some() || cli.participants_only => cli.format,
+        (OutputFormat::Default, Some(output)) =>
+            OutputFormat::from_extension(output).with_context(||
+                format!(
+                    "Can't tell the output format from {:?}; give the format argument, or use an extension such as .json, .ndjson, .csv or .md",
+                    output
+                )
+            )?,
+        (format, _) => format,
+    };
+
+    // Expand the input path, including any glob patterns, unless reading from stdin
+    let from_stdin = input.as_os_str() == STDIN_INPUT;
+    let expanded_paths: Vec<PathBuf> = if from_stdin {
+        Vec::new()
+    } else {
+        glob(&input.to_string_lossy())
+            .with_context(|| format!("Failed to read glob pattern: {:?}", input))?
+            .filter_map(Result::ok)
+            .collect()
+    };
+
+    if !from_stdin && expanded_paths.is_empty() {
+        error!("No matching paths found for: {:?}", input);
+        return Err(
+            anyhow::anyhow!("No matching paths found for: {:?}", input).context(Failure::NoInput)
+        );
+    }
+
+    let mut options = ParseOptions::from_cli(&cli)?;
+
+    if
+        from_stdin &&
+        (cli.validate ||
+            cli.count ||
+            cli.tui ||
+            cli.participants_only ||
+            cli.watch ||
+            cli.merge_similar_participants)
+    {
+        anyhow::bail!(
+            "--validate, --count, --tui, --participants-only, --watch and --merge-similar-participants need file input and can't read from stdin"
+        );
+    }
+    let watched: Vec<PathBuf> = expanded_paths
+        .iter()
+        .filter(|path| path.is_dir())
+        .cloned()
+        .collect();
+    if cli.watch && watched.is_empty() {
+        anyhow::bail!("--watch needs an input directory to watch");
+    }
+    if cfg!(not(feature = "duckdb")) && cli.duckdb.is_some() {
+        anyhow::bail!("--duckdb needs a build with the `duckdb` feature (cargo build --features duckdb)");
+    }
+    if cli.validate {
+        return validate_paths(&expanded_paths, &options);
+    }
+    if cli.tui {
+        return tui::run(expand_files(&expanded_paths, &options), &options, cli.tz_output);
+    }
+    if !cli.force {
+        check_overwrite(&cli)?;
+    }
+    if cli.merge_similar_participants {
+        let merger = merge_participants(&expanded_paths, &options, cli.merge_threshold)?;
+        options.merger = Some(Arc::new(merger));
+    }
+    let mut deduper = FileDeduper::new(!cli.allow_duplicate_files);
+    let group_filter = match (cli.only_group, cli.only_direct) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+    let mut thread_filter = ThreadFilter::new(
+        &cli.labels,
+        cli.keep_empty,
+        group_filter,
+        &cli.participants,
+        cli.min_messages,
+        cli.skip_untranscribed
+    );
+    if cli.count {
+        let result = count_paths(&expanded_paths, &options, &mut deduper, &mut thread_filter);
+        thread_filter.report();
+        return result;
+    }
+    if cli.participants_only {
+        let result = roster_paths(
+            &expanded_paths,
+            &options,
+            &mut deduper,
+            &mut thread_filter,
+            cli.output.as_deref()
+        );
+        thread_filter.report();
+        return result;
+    }
+
+    // A single `--participant` turns the statistics into the conversation with them, as
+    // long as the default format writes nothing else to stdout
+    let transcript =
+        format == OutputFormat::Default &&
+        cli.participants.len() == 1 &&
+        cli.output.is_none() &&
+        cli.emit.is_empty() &&
+        cli.chunk_output.is_none() &&
+        cli.sample.is_none();
+    let output = OutputOptions {
+        format,
+        compact_json: cli.compact,
+        json_case: cli.json_case,
+        approx_participants: cli.approx_participants,
+        embed_media: cli.embed_media.then_some(cli.embed_max_size),
+        collapse: cli.collapse_consecutive.then_some(cli.collapse_window),
+        ics_events: cli.ics_events,
+        tz: cli.tz_output,
+        sample: cli.sample.map(|size| {
+            let seed = cli.seed.unwrap_or_else(|| {
+                // Reported so an interesting sample can be drawn again with `--seed`
+                let seed = Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+                eprintln!("Sample seed: {}", seed);
+                seed
+            });
+            Sampling { size: size.get(), seed }
+        }),
+        split_by: cli.split_by,
+        transcript,
+    };
+    let neo4j = if format == OutputFormat::Cypher {
+        Neo4jOptions::from_cli(&cli)?
+    } else {
+        Neo4jOptions::default()
+    };
+
+    let writer = match cli.output.as_deref() {
+        _ if transcript => {
+            let writer = transcript::TranscriptWriter::new(&cli.participants[0], cli.tz_output);
+            Ok(Box::new(writer) as Box<dyn ThreadWriter>)
+        }
+        Some(path) if cli.state.is_some() => {
+            let single_file =
+                matches!(format, OutputFormat::Json | OutputFormat::Ndjson | OutputFormat::Csv) &&
+                cli.emit.is_empty() &&
+                cli.chunk_output.is_none() &&
+                cli.sample.is_none();
+            if !single_file {
+                anyhow::bail!("--state and --append resume a single file of json, ndjson or csv output only");
+            }
+            let state = cli.state.as_deref().context("No state file given")?;
+            append::AppendWriter
+                ::open(path, state, cli.append, output)
+                .map(|writer| Box::new(writer) as Box<dyn ThreadWriter>)
+        }
+        Some(dir) if !cli.emit.is_empty() => {
+            if cli.format != OutputFormat::Default {
+                anyhow::bail!("Give either a format argument or --emit, not both");
+            }
+            create_multi_writer(&cli.emit, &output, dir, input)
+        }
+        output_path =>
+            create_writer(
+                &output,
+                output_path,
+                input,
+                cli.chunk_output.as_deref().map(|prefix| (prefix, cli.chunk_size.get())),
+                &neo4j
+            ),
+    };
+    let mut writer = writer.context(Failure::Output)?;
+    writer.begin().context(Failure::Output)?;
+    let mut sinks = Sinks {
+        writer,
+        manifest: cli.manifest.as_deref().map(Manifest::create).transpose().context(Failure::Output)?,
+        checksums: if cli.checksums.is_some() || cli.verify_checksums.is_some() {
+            Some(
+                Checksums::create(cli.checksums.as_deref(), cli.verify_checksums.as_deref()).context(
+                    Failure::Output
+                )?
+            )
+        } else {
+            None
+        },
+        histogram: cli.histogram
+            .as_deref()
+            .map(|path| Histogram::create(path, cli.tz_output))
+            .transpose()
+            .context(Failure::Output)?,
+        matrix: cli.matrix.as_deref().map(AdjacencyMatrix::create).transpose().context(Failure::Output)?,
+        per_contact: cli.per_contact
+            .as_deref()
+            .map(|dir| ContactFiles::create(dir, cli.group_fan_out, output))
+            .transpose()
+            .context(Failure::Output)?,
+        mirror: cli.mirror_output
+            .as_deref()
+            .map(|dir| mirror::MirrorFiles::create(dir, &expanded_paths, output))
+            .transpose()
+            .context(Failure::Output)?,
+        timeline: cli.timeline
+            .as_deref()
+            .map(|path| timeline::Timeline::create(path, output))
+            .transpose()
+            .context(Failure::Output)?,
+        terms: cli.top_terms.map(|top| {
+            terms::TermCounter::new(top.get(), cli.min_term_length, cli.top_terms_by_participant)
+        }),
+        regions: cli.region_summary.then(regions::RegionSummary::default),
+        tally: cli.participant_summary.then(tally::ParticipantTally::default),
+        #[cfg(feature = "duckdb")]
+        duckdb: cli.duckdb
+            .as_deref()
+            .map(duckdb_writer::DuckdbWriter::create)
+            .transpose()
+            .context(Failure::Output)?,
+        validator: cli.validate_output.then(schema::OutputValidator::new).transpose()?,
+        progress: if cli.progress_to_file.is_some() || cli.progress_interval.is_some() {
+            let interval = cli.progress_interval.map_or(DEFAULT_PROGRESS_INTERVAL, NonZeroUsize::get);
+            Some(ProgressLog::create(cli.progress_to_file.as_deref(), interval).context(Failure::Output)?)
+        } else {
+            None
+        },
+        next_thread_index: cli.include_indices.then_some(0),
+        thread_filter,
+    };
+
+    let mut failures = FailureTracker::new(cli.max_failures, cli.max_failure_rate);
+    let mut empty_directories = Vec::new();
+    let mut processed = if from_stdin { process_stdin(&output, &options, &mut sinks) } else { Ok(()) };
+    for path in expanded_paths {
+        if interrupted() || processed.is_err() {
+            break;
+        }
+        processed = if path.is_dir() {
+            process_directory(
+                &path,
+                &output,
+                &options,
+                &neo4j,
+                &mut deduper,
+                &mut failures,
+                &mut sinks
+            ).map(|found| {
+                if !found {
+                    empty_directories.push(path.clone());
+                }
+            })
+        } else if path.is_file() {
+            process_file(&path, &output, &options, &mut deduper, &mut failures, &mut sinks)
+        } else {
+            println!("Skipping non-file, non-directory: {:?}", path);
+            Ok(())
+        };
+    }
+    // Ctrl-C is how watching ends, so it doesn't count as an interrupted run then
+    let watching = cli.watch && processed.is_ok() && !interrupted();
+    if watching {
+        processed = watch_directories(
+            &watched,
+            &output,
+            &options,
+            &neo4j,
+            &mut deduper,
+            &mut failures,
+            &mut sinks
+        );
+    }
+    // Finish the outputs even when the run was aborted, so what was written stays usable
+    let finished = sinks.finish();
+    processed?;
+    let changed_threads = finished?;
+
+    if interrupted() && !watching {
+        return Err(anyhow::Error::msg(Failure::Interrupted));
+    }
+    if !empty_directories.is_empty() && !cli.allow_empty {
+        return Err(
+            anyhow::anyhow!(
+                "No matching conversations in {:?} (pass --allow-empty if that is expected)",
+                empty_directories
+            ).context(Failure::NoInput)
+        );
+    }
+    failures.finish()?;
+    if changed_threads > 0 {
+        return Err(anyhow::Error::msg(Failure::ChecksumMismatch(changed_threads)));
+    }
+    Ok(())
+}
+
+// On the first Ctrl-C stop feeding new files so in-flight output (including the current
+// Neo4j batch and its checkpoint) is flushed; a second Ctrl-C exits immediately
+fn install_interrupt_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            eprintln!("\nInterrupted again, exiting immediately");
+            std::process::exit(130);
+        }
+        eprintln!("\nInterrupted, finishing current work (press Ctrl-C again to force exit)");
+    }).context("Failed to install Ctrl-C handler")
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// Turn the number of files that failed to parse into the run's result
+fn parse_failures(failed_files: usize) -> Result<()> {
+    if failed_files > 0 {
+        return Err(anyhow::Error::msg(Failure::ParseFailures(failed_files)));
+    }
+    Ok(())
+}
+
+// Everything a run writes threads to: the format's writer and the optional
+// `--manifest`, `--checksums`, `--histogram`, `--matrix`, `--per-contact`,
+// `--mirror-output`, `--timeline`, `--top-terms`, `--region-summary` and `--participant-summary`,
+// behind the `--label` filter deciding which threads get written
+struct Sinks {
+    writer: Box<dyn ThreadWriter>,
+    manifest: Option<Manifest>,
+    checksums: Option<Checksums>,
+    histogram: Option<Histogram>,
+    matrix: Option<AdjacencyMatrix>,
+    per_contact: Option<ContactFiles>,
+    mirror: Option<mirror::MirrorFiles>,
+    timeline: Option<timeline::Timeline>,
+    terms: Option<terms::TermCounter>,
+    regions: Option<regions::RegionSummary>,
+    tally: Option<tally::ParticipantTally>,
+    #[cfg(feature = "duckdb")]
+    duckdb: Option<duckdb_writer::DuckdbWriter>,
+    validator: Option<schema::OutputValidator>,
+    /// Headless progress lines, counted per file rather than per thread
+    progress: Option<ProgressLog>,
+    /// `thread_index` of the next thread with `--include-indices`
+    next_thread_index: Option<usize>,
+    thread_filter: ThreadFilter,
+}
+
+// Give a thread the run's next `thread_index` and number its messages from 0
+fn assign_indices(thread: &mut Thread, next: &mut usize) {
+    thread.thread_index = Some(*next);
+    *next += 1;
+    for (index, message) in thread.messages.iter_mut().enumerate() {
+        message.message_index = Some(index);
+    }
+}
+
+impl Sinks {
+    fn write_thread(&mut self, mut thread: Thread) -> Result<()> {
+        // Numbered here rather than while parsing, so the positions are those of the
+        // output after filtering, sorting, deduplication and `--session-gap` splits. With
+        // several `--jobs` threads are written, and numbered, in the order files finish.
+        if let Some(next) = &mut self.next_thread_index {
+            assign_indices(&mut thread, next);
+        }
+        if let Some(validator) = &self.validator {
+            validator.validate(&thread).context(Failure::Output)?;
+        }
+        if let Some(manifest) = &mut self.manifest {
+            manifest.record(&thread).context(Failure::Output)?;
+        }
+        if let Some(checksums) = &mut self.checksums {
+            checksums.record(&thread).context(Failure::Output)?;
+        }
+        if let Some(histogram) = &mut self.histogram {
+            histogram.record(&thread);
+        }
+        if let Some(matrix) = &mut self.matrix {
+            matrix.record(&thread);
+        }
+        if let Some(per_contact) = &mut self.per_contact {
+            per_contact.record(&thread).context(Failure::Output)?;
+        }
+        if let Some(mirror) = &mut self.mirror {
+            mirror.record(&thread).context(Failure::Output)?;
+        }
+        if let Some(timeline) = &mut self.timeline {
+            timeline.record(&thread).context(Failure::Output)?;
+        }
+        if let Some(terms) = &mut self.terms {
+            terms.record(&thread);
+        }
+        if let Some(regions) = &mut self.regions {
+            regions.record(&thread);
+        }
+        if let Some(tally) = &mut self.tally {
+            tally.record(&thread);
+        }
+        #[cfg(feature = "duckdb")]
+        if let Some(duckdb) = &mut self.duckdb {
+            duckdb.write_thread(&thread).context(Failure::Output)?;
+        }
+        self.writer.write_thread(thread).context(Failure::Output)
+    }
+
+    // Returns the number of threads that differ from `--verify-checksums`
+    fn finish(self) -> Result<usize> {
+        self.writer.finish().context(Failure::Output)?;
+        if let Some(manifest) = self.manifest {
+            manifest.finish().context(Failure::Output)?;
+        }
+        let changed_threads = match self.checksums {
+            Some(checksums) => checksums.finish().context(Failure::Output)?,
+            None => 0,
+        };
+        if let Some(histogram) = self.histogram {
+            histogram.finish().context(Failure::Output)?;
+        }
+        if let Some(matrix) = self.matrix {
+            matrix.finish().context(Failure::Output)?;
+        }
+        if let Some(per_contact) = self.per_contact {
+            per_contact.finish().context(Failure::Output)?;
+        }
+        if let Some(mirror) = self.mirror {
+            mirror.finish().context(Failure::Output)?;
+        }
+        if let Some(timeline) = self.timeline {
+            timeline.finish().context(Failure::Output)?;
+        }
+        if let Some(terms) = self.terms {
+            terms.finish();
+        }
+        if let Some(regions) = self.regions {
+            regions.finish();
+        }
+        if let Some(tally) = self.tally {
+            tally.finish();
+        }
+        #[cfg(feature = "duckdb")]
+        if let Some(duckdb) = self.duckdb {
+            duckdb.finish().context(Failure::Output)?;
+        }
+        self.thread_filter.report();
+        Ok(changed_threads)
+    }
+}
+
+// Everything the run writes besides Neo4j, for `is_conversation_file` to leave out of a
+// directory walk that would otherwise read it back: `--output` (a file or directory),
+// the sidecar files, `--per-contact`, `--mirror-output`, `--cache`, and the chunks of
+// `--chunk-output`. Paths are made absolute so they compare with the walk's.
+#[derive(Debug, Clone, Default)]
+struct RunOutputs {
+    paths: Vec<PathBuf>,
+    chunk_prefix: Option<PathBuf>,
+}
+
+impl RunOutputs {
+    fn from_cli(cli: &Cli) -> Self {
+        let paths = [
+            &cli.output,
+            &cli.state,
+            &cli.manifest,
+            &cli.checksums,
+            &cli.histogram,
+            &cli.matrix,
+            &cli.per_contact,
+            &cli.mirror_output,
+            &cli.timeline,
+            &cli.cache,
+            &cli.checkpoint,
+        ]
+            .into_iter()
+            .flatten()
+            .filter_map(|path| std::path::absolute(path).ok())
+            .collect();
+        let chunk_prefix = cli.chunk_output.as_deref().and_then(|prefix| std::path::absolute(prefix).ok());
+        RunOutputs { paths, chunk_prefix }
+    }
+
+    // Whether `path` is one of the paths, inside one of them, or a chunk:
+    // `<PREFIX>-<number>.json`
+    fn contains(&self, path: &Path) -> bool {
+        let Ok(path) = std::path::absolute(path) else {
+            return false;
+        };
+        let is_chunk = self.chunk_prefix.as_ref().is_some_and(|prefix| {
+            let (Some(prefix), Some(name)) = (prefix.to_str(), path.to_str()) else {
+                return false;
+            };
+            name.strip_prefix(prefix)
+                .and_then(|rest| rest.strip_prefix('-'))
+                .and_then(|rest| rest.strip_suffix(".json"))
+                .is_some_and(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+        });
+        is_chunk || self.paths.iter().any(|output| path.starts_with(output))
+    }
+}
+
+// Refuse to start a run that would replace an earlier one's output without `--force`:
+// an existing file, or a non-empty directory for the outputs that are directories (HTML,
+// Takeout, `--emit`, `--per-contact`, `--mirror-output`). Checked before anything is parsed, so a long run
+// doesn't fail at the end. Stdout (`-` for the histogram) is never guarded.
+fn check_overwrite(cli: &Cli) -> Result<()> {
+    // The output and state of a resumed export are expected to exist
+    let (output, state) = if cli.append { (&None, &None) } else { (&cli.output, &cli.state) };
+    let mut targets: Vec<PathBuf> = [
+        output,
+        state,
+        &cli.manifest,
+        &cli.checksums,
+        &cli.matrix,
+        &cli.per_contact,
+        &cli.mirror_output,
+        &cli.timeline,
+    ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    targets.extend(cli.histogram.iter().filter(|path| path.as_os_str() != "-").cloned());
+    targets.extend(cli.chunk_output.as_deref().map(|prefix| chunk_writer::chunk_path(prefix, 1)));
+    for target in targets {
+        if target.is_dir() {
+            let mut entries = fs::read_dir(&target).with_context(||
+                format!("Failed to read output directory: {:?}", target)
+            )?;
+            if entries.next().is_some() {
+                anyhow::bail!(
+                    "Output directory {:?} is not empty; pass --force to write into it anyway",
+                    target
+                );
+            }
+        } else if target.exists() {
+            anyhow::bail!("Output file {:?} already exists; pass --force to overwrite it", target);
+        }
+    }
+    Ok(())
+}
+
+// Walk `dir` for conversation files, see `is_conversation_file`
+fn collect_files(dir: &Path, options: &ParseOptions) -> Vec<walkdir::DirEntry> {
+    let order = options.file_order;
+    let walk = match order {
+        FileOrder::Name => WalkDir::new(dir).sort_by_file_name(),
+        FileOrder::Mtime | FileOrder::None => WalkDir::new(dir),
+    };
+    let mut files: Vec<walkdir::DirEntry> = walk
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file() && is_conversation_file(e.path(), options))
+        .collect();
+    if order == FileOrder::Mtime {
+        // A file whose time can't be read goes first rather than failing the run
+        files.sort_by_cached_key(|e| {
+            let modified = e.metadata().ok().and_then(|m| m.modified().ok());
+            (modified, e.path().to_path_buf())
+        });
+    }
+    files
+}
+
+// Text and group conversation HTML files are named `... - Text - ...` and
+// `Group Conversation - ...`; calls are skipped, and so are voicemails (`... - Voicemail -
+// ...`) without `--voicemails`. JSON files are taken whatever their name when they start
+// like threads the `json` format wrote, so the other JSON a directory holds (the run's own
+// output and sidecar files among it) isn't read in, or counted as a failure for not being
+// threads.
+fn is_conversation_file(path: &Path, options: &ParseOptions) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    if json_input::is_json_file(path) {
+        return !options.run_outputs.contains(path) && json_input::looks_like_threads(path);
+    }
+    path.extension().is_some_and(|ext| ext == FILE_TYPE) &&
+        (name.contains(TEXT_LABEL) ||
+            name.contains(GROUP_CONVERSATION_LABEL) ||
+            (options.voicemails && name.contains(VOICEMAIL_LABEL)))
+}
+
+// `--watch`: process conversation files created or changed in `dirs` until Ctrl-C.
+// Rewritten files with unchanged content are skipped as duplicates, and in a resumed
+// `cypher` import so are files the checkpoint lists as committed.
+fn watch_directories(
+    dirs: &[PathBuf],
+    output: &OutputOptions,
+    options: &ParseOptions,
+    neo4j: &Neo4jOptions,
+    deduper: &mut FileDeduper,
+    failures: &mut FailureTracker,
+    sinks: &mut Sinks
+) -> Result<()> {
+    let watcher = watch::DirectoryWatcher::new(dirs)?;
+    eprintln!("Watching {:?} for new files (Ctrl-C to stop)", dirs);
+    while let Some(paths) = watcher.next_batch(interrupted) {
+        for path in paths {
+            if interrupted() {
+                break;
+            }
+            if !path.is_file() || !is_conversation_file(&path, options) {
+                continue;
+            }
+            if
+                output.format == OutputFormat::Cypher &&
+                neo4j.committed.contains(path.to_string_lossy().as_ref())
+            {
+                continue;
+            }
+            process_file(&path, output, options, deduper, failures, sinks)?;
+        }
+    }
+    Ok(())
+}
+
+// Expand input paths into the files they contain: directories are walked for
+// conversation files, plain files are taken as-is
+fn expand_files(paths: &[PathBuf], options: &ParseOptions) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .flat_map(|path| {
+            if path.is_dir() {
+                collect_files(path, options)
+                    .into_iter()
+                    .map(|e| e.into_path())
+                    .collect()
+            } else {
+                vec![path.clone()]
+            }
+        })
+        .collect()
+}
+
+// Parse everything and print a single summary line of totals, with no per-thread output
+fn count_paths(
+    paths: &[PathBuf],
+    options: &ParseOptions,
+    deduper: &mut FileDeduper,
+    thread_filter: &mut ThreadFilter
+) -> Result<()> {
+    let mut files = 0;
+    let mut failed_files = 0;
+    let mut messages = 0;
+    let mut participants = HashSet::new();
+
+    for file in expand_files(paths, options) {
+        if deduper.is_duplicate(&file)? {
+            continue;
+        }
+        let threads = match parse_file(&file, options) {
+            Ok(threads) => threads,
+            Err(e) => {
+                error!("Failed to parse file {:?}: {:#}", file, e);
+                failed_files += 1;
+                continue;
+            }
+        };
+        files += 1;
+        for thread in threads.into_iter().filter(|t| thread_filter.keep(t)) {
+            messages += thread.message_count;
+            participants.extend(thread.participants);
+        }
+    }
+
+    println!("files={} messages={} participants={}", files, messages, participants.len());
+    parse_failures(failed_files)
+}
+
+// Parse everything and write only the merged participant roster. Threads go through
+// `parse_file` alone, skipping media extraction and session splitting.
+fn roster_paths(
+    paths: &[PathBuf],
+    options: &ParseOptions,
+    deduper: &mut FileDeduper,
+    thread_filter: &mut ThreadFilter,
+    output: Option<&Path>
+) -> Result<()> {
+    let mut roster = roster::Roster::default();
+    let mut failed_files = 0;
+
+    for file in expand_files(paths, options) {
+        if interrupted() {
+            break;
+        }
+        if deduper.is_duplicate(&file)? {
+            continue;
+        }
+        match parse_file(&file, options) {
+            Ok(mut threads) => {
+                if let Some(merger) = &options.merger {
+                    threads.iter_mut().for_each(|thread| merger.apply(thread));
+                }
+                for thread in threads.iter().filter(|t| thread_filter.keep(t)) {
+                    roster.add(thread);
+                }
+            }
+            Err(e) => {
+                error!("Failed to parse file {:?}: {:#}", file, e);
+                failed_files += 1;
+            }
+        }
+    }
+
+    roster.write(output).context(Failure::Output)?;
+    if interrupted() {
+        return Err(anyhow::Error::msg(Failure::Interrupted));
+    }
+    parse_failures(failed_files)
+}
+
+// First pass of `--merge-similar-participants`: parse every file for its contacts and
+// print the clusters formed. Files that fail are left for the actual run to report.
+// With `--cache` the actual run then loads the files from the cache.
+fn merge_participants(
+    paths: &[PathBuf],
+    options: &ParseOptions,
+    threshold: f64
+) -> Result<merge::ParticipantMerger> {
+    let mut merger = merge::ParticipantMerger::new(threshold);
+    for file in expand_files(paths, options) {
+        if interrupted() {
+            return Err(anyhow::Error::msg(Failure::Interrupted));
+        }
+        if let Ok(threads) = parse_cached(&file, options) {
+            threads.iter().for_each(|thread| merger.add(thread));
+        }
+    }
+    for cluster in merger.cluster() {
+        let merged: Vec<String> = cluster[1..]
+            .iter()
+            .map(|alias| format!("{} ({})", alias.name, alias.phone))
+            .collect();
+        eprintln!(
+            "Merged participants: {} ({}) <- {}",
+            cluster[0].name,
+            cluster[0].phone,
+            merged.join(", ")
+        );
+    }
+    Ok(merger)
+}
+
+// Parse every matching file without producing output and report its warnings
+fn validate_paths(paths: &[PathBuf], options: &ParseOptions) -> Result<()> {
+    let mut files_checked = 0;
+    let mut files_with_warnings = 0;
+    let mut failed_files = 0;
+    let mut misattributed_senders = 0;
+    let mut unresolved_replies = 0;
+    let mut missing_timestamps = 0;
+    let mut invalid_timestamps = 0;
+    let mut message_count_mismatches = 0;
+    let mut repeated_messages = 0;
+    let mut untranscribed_voicemails = 0;
+
+    for file in expand_files(paths, options) {
+        // Sessions are irrelevant to validation, so conversations are checked unsplit
+        files_checked += 1;
+        let threads = match parse_file(&file, options) {
+            Ok(threads) => threads,
+            Err(e) => {
+                println!("{:?}:\n  failed to parse: {:#}", file, e);
+                failed_files += 1;
+                continue;
+            }
+        };
+        let warnings: Vec<&ParseWarning> = threads
+            .iter()
+            .flat_map(|t| &t.warnings)
+            .collect();
+        if !warnings.is_empty() {
+            files_with_warnings += 1;
+            println!("{:?}:", file);
+            for warning in warnings {
+                println!("  {}", warning);
+                match warning {
+                    ParseWarning::SenderNameConflict { .. } | ParseWarning::SenderHasSelfPhone { .. } => {
+                        misattributed_senders += 1;
+                    }
+                    ParseWarning::UnresolvedReply { .. } => {
+                        unresolved_replies += 1;
+                    }
+                    ParseWarning::MissingTimestamp { .. } => {
+                        missing_timestamps += 1;
+                    }
+                    ParseWarning::InvalidTimestamp { .. } => {
+                        invalid_timestamps += 1;
+                    }
+                    ParseWarning::MessageCountMismatch { .. } => {
+                        message_count_mismatches += 1;
+                    }
+                    ParseWarning::RepeatedMessages { dropped } => {
+                        repeated_messages += dropped;
+                    }
+                    ParseWarning::UntranscribedVoicemail { .. } => {
+                        untranscribed_voicemails += 1;
+                    }
+                    ParseWarning::RawContentUnavailable { .. } => {}
+                }
+            }
+        }
+    }
+
+    println!("\nValidation Summary:");
+    println!("Files checked: {}", files_checked);
+    println!("Files with warnings: {}", files_with_warnings);
+    println!("Misattributed senders: {}", misattributed_senders);
+    println!("Unresolved replies: {}", unresolved_replies);
+    println!("Messages without a timestamp: {}", missing_timestamps);
+    println!("Messages with an unparseable timestamp: {}", invalid_timestamps);
+    println!("Message count mismatches: {}", message_count_mismatches);
+    println!("Repeated messages dropped: {}", repeated_messages);
+    println!("Untranscribed voicemails: {}", untranscribed_voicemails);
+    println!("Files failed to parse: {}", failed_files);
+    parse_failures(failed_files)
+}
+
+// Parse and write every conversation file under `dir`. Returns false when the directory
+// yielded no conversation at all (no conversation files, or none passing `--label`),
+// after printing a warning; files skipped by resume, deduplication or parse failures
+// don't count as an empty directory.
+fn process_directory(
+    dir: &Path,
+    output: &OutputOptions,
+    options: &ParseOptions,
+    neo4j: &Neo4jOptions,
+    deduper: &mut FileDeduper,
+    failures: &mut FailureTracker,
+    sinks: &mut Sinks
+) -> Result<bool> {
+    let start_time = Instant::now();
+    let duplicates_before = deduper.duplicates;
+    let failures_before = failures.failed;
+    let cache_before = options.cache.as_ref().map(|cache| cache.counts());
+    let mut files_processed = 0;
+    let mut files_matched = 0;
+    let mut total_messages = 0;
+    let mut all_participants = if output.approx_participants {
+        ParticipantCounter::Approximate(hll::HyperLogLog::default())
+    } else {
+        ParticipantCounter::Exact(HashSet::new())
+    };
+
+    let mut classified_participants = options.classifier.as_ref().map(|_| HashSet::new());
+    let mut misattributed_senders = 0;
+    let mut message_count_mismatches = 0;
+    let mut repeated_messages = 0;
+    let mut missing_timestamps = 0;
+    let mut invalid_timestamps = 0;
+    let mut truncated_messages = options.max_content_length.map(|_| 0);
+    // Every category is listed, including those nothing matched
+    let mut keyword_matches: Option<BTreeMap<String, usize>> = options.keywords.as_ref().map(|keywords| {
+        keywords
+            .categories()
+            .map(|category| (category.to_string(), 0))
+            .collect()
+    });
+
+    // Get the list of files to process
+    let files = collect_files(dir, options);
+
+    // Set up progress bar for Default format
+    let progress_bar = if output.format == OutputFormat::Default && !output.transcript {
+        let pb = ProgressBar::new(files.len() as u64);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})"
+                )
+                .unwrap()
+                .progress_chars("#>-")
+        );
+        Some(pb)
+    } else {
+        None
+    };
+
+
+    // Skip files already imported or seen, then parse the rest
+    let mut files_resumed = 0;
+    let mut to_parse = Vec::new();
+    for entry in files {
+        if interrupted() {
+            break;
+        }
+        if output.format == OutputFormat::Cypher &&
+            neo4j.committed.contains(entry.path().to_string_lossy().as_ref())
+        {
+            files_resumed += 1;
+            continue;
+        }
+        if deduper.is_duplicate(entry.path())? {
+            if let Some(pb) = &progress_bar {
+                pb.inc(1);
+            }
+            continue;
+        }
+        to_parse.push(entry.into_path());
+    }
+
+    if let Some(progress) = &mut sinks.progress {
+        progress.start(dir, to_parse.len());
+    }
+    parse_concurrently(&to_parse, options, |file, parsed| {
+        if let Some(progress) = &mut sinks.progress {
+            progress.file_done().context(Failure::Output)?;
+        }
+        let threads = match parsed {
+            Ok(threads) => threads,
+            Err(e) => {
+                if let Some(pb) = &progress_bar {
+                    pb.inc(1);
+                }
+                return failures.record_failure(file, &e);
+            }
+        };
+        failures.record_success();
+        files_processed += 1;
+        if let Some(pb) = &progress_bar {
+            pb.inc(1);
+        }
+        if threads.iter().flat_map(|t| &t.warnings).any(ParseWarning::is_message_count_mismatch) {
+            message_count_mismatches += 1;
+        }
+        let repeated = repeated_in_file(&threads);
+        if repeated > 0 {
+            warn!("{:?}: dropped {} repeated messages", file, repeated);
+            repeated_messages += repeated;
+        }
+        let mut matched = false;
+        for thread in threads {
+            let Some((thread, thread_matched)) = sinks.thread_filter.filter(thread) else {
+                continue;
+            };
+            matched |= thread_matched;
+            total_messages += thread.message_count;
+            all_participants.add(&thread.participants);
+            if let Some(classified) = &mut classified_participants {
+                classified.extend(thread.participants.iter().cloned());
+            }
+            misattributed_senders += thread.warnings
+                .iter()
+                .filter(|w| matches!(w, ParseWarning::SenderNameConflict { .. } | ParseWarning::SenderHasSelfPhone { .. }))
+                .count();
+            for warning in &thread.warnings {
+                match warning {
+                    ParseWarning::MissingTimestamp { .. } => missing_timestamps += 1,
+                    ParseWarning::InvalidTimestamp { .. } => invalid_timestamps += 1,
+                    _ => {}
+                }
+            }
+            if let Some(keyword_matches) = &mut keyword_matches {
+                for flag in thread.messages.iter().flat_map(|m| &m.flags) {
+                    *keyword_matches.entry(flag.clone()).or_default() += 1;
+                }
+            }
+            if let Some(truncated) = &mut truncated_messages {
+                *truncated += thread.messages.iter().filter(|m| m.truncated).count();
+            }
+            sinks.write_thread(thread)?;
+        }
+        if matched {
+            files_matched += 1;
+        }
+        Ok(())
+    })?;
+    if interrupted() {
+        info!("Interrupted, not starting further files in {:?}", dir);
+    }
+
+    // Finalize progress bar
+    if let Some(pb) = progress_bar {
+        pb.finish_with_message("Processing complete");
+    }
+    if files_resumed > 0 {
+        info!("Skipped {} files already committed according to the checkpoint", files_resumed);
+    }
+    // Calculate and display statistics for Default format
+    if output.format == OutputFormat::Default && !output.transcript {
+        let duration = start_time.elapsed();
+        let stats = RunStatistics {
+            duration,
+            files_processed,
+            messages_extracted: total_messages,
+            unique_participants: all_participants.count(),
+            participants_estimated: matches!(all_participants, ParticipantCounter::Approximate(_)),
+            avg_messages_per_file: (total_messages as f64) / (files_processed as f64),
+            misattributed_senders,
+            message_count_mismatches,
+            repeated_messages,
+            missing_timestamps,
+            dropped_undated: options.drop_undated,
+            invalid_timestamps,
+            duplicate_files: deduper.duplicates - duplicates_before,
+            keyword_matches,
+            cache_counts: options.cache.as_ref().zip(cache_before).map(|(cache, (hits, misses))| {
+                let (total_hits, total_misses) = cache.counts();
+                (total_hits - hits, total_misses - misses)
+            }),
+            participant_types: classified_participants.map(|participants| {
+                let mut counts: BTreeMap<_, usize> = classify::ParticipantType::ALL
+                    .into_iter()
+                    .map(|kind| (kind, 0))
+                    .collect();
+                for kind in participants.iter().filter_map(|p: &Participant| p.participant_type) {
+                    *counts.entry(kind).or_default() += 1;
+                }
+                counts
+            }),
+            truncated_messages,
+        };
+        print_statistics(&stats);
+    }
+
+    let skipped = files_resumed + (deduper.duplicates - duplicates_before) + (failures.failed - failures_before);
+    if files_matched == 0 && skipped == 0 && !interrupted() {
+        eprintln!(
+            "warning: {:?} produced no conversations ({} conversation files found). Check that the \
+             path is the Google Voice `Calls` folder of a Takeout export and that any --label \
+             filter matches.",
+            dir,
+            files_processed
+        );
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Parse `files` on `options.jobs` worker threads and hand each result to `handle` on
+// the calling thread, in the order they finish (file order with a single job). At most
+// `options.channel_capacity` parsed files wait for `handle`, so a slow writer holds
+// parsing back instead of letting parsed threads pile up in memory. No further files
+// are started once the run is interrupted or `handle` fails.
+fn parse_concurrently(
+    files: &[PathBuf],
+    options: &ParseOptions,
+    mut handle: impl FnMut(&Path, Result<Vec<Thread>>) -> Result<()>
+) -> Result<()> {
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        let (tx, rx) = mpsc::sync_channel(options.channel_capacity);
+        for _ in 0..options.jobs {
+            let (tx, next) = (tx.clone(), &next);
+            scope.spawn(move || {
+                while !interrupted() {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(file) = files.get(index) else {
+                        break;
+                    };
+                    // The receiver only hangs up when handling failed
+                    if tx.send((index, parse_threads(file, options))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for (index, parsed) in rx {
+            handle(&files[index], parsed)?;
+        }
+        Ok(())
+    })
+}
+
+fn process_file(
+    file_path: &Path,
+    output: &OutputOptions,
+    options: &ParseOptions,
+    deduper: &mut FileDeduper,
+    failures: &mut FailureTracker,
+    sinks: &mut Sinks
+) -> Result<()> {
+    if !output.transcript {
+        println!("Processing file: {:?}", file_path);
+    }
+    if deduper.is_duplicate(file_path)? {
+        return Ok(());
+    }
+    let threads = match parse_threads(file_path, options) {
+        Ok(threads) => threads,
+        Err(e) => {
+            return failures.record_failure(file_path, &e);
+        }
+    };
+    failures.record_success();
+    write_threads(threads, output, sinks)
+}
+
+// Parse HTML piped in on stdin (`input` given as `-`) and write its threads
+fn process_stdin(
+    output: &OutputOptions,
+    options: &ParseOptions,
+    sinks: &mut Sinks
+) -> Result<()> {
+    let mut content = Vec::new();
+    io::stdin().read_to_end(&mut content).context("Failed to read HTML from stdin")?;
+    let content = decode_html(&content, Path::new(STDIN_SOURCE), options.strict_encoding)?;
+    let threads = parse_html(&content, Path::new(STDIN_SOURCE), options)?;
+    let threads = finish_threads(threads, options)?;
+    write_threads(threads, output, sinks)
+}
+
+// Write the threads of a single input in the selected format
+fn write_threads(
+    threads: Vec<Thread>,
+    output: &OutputOptions,
+    sinks: &mut Sinks
+) -> Result<()> {
+    let repeated = repeated_in_file(&threads);
+    let threads: Vec<Thread> = threads
+        .into_iter()
+        .filter_map(|thread| sinks.thread_filter.filter(thread))
+        .map(|(thread, _)| thread)
+        .collect();
+
+    if output.format == OutputFormat::Default && !output.transcript {
+        let message_count: usize = threads
+            .iter()
+            .map(|t| t.message_count)
+            .sum();
+        println!("Processed 1 file with {} messages", message_count);
+        if repeated > 0 {
+            println!("Repeated messages dropped: {}", repeated);
+        }
+    }
+    for thread in threads {
+        sinks.write_thread(thread)?;
+    }
+
+    Ok(())
+}
+
+// Parse a file into the threads it should be emitted as: one per conversation, or
+// one per session when `--session-gap` is set, redacted with `--redact`
+fn parse_threads(filename: &Path, options: &ParseOptions) -> Result<Vec<Thread>> {
+    finish_threads(parse_cached(filename, options)?, options)
+}
+
+// `parse_file` through the `--cache` when there is one
+fn parse_cached(filename: &Path, options: &ParseOptions) -> Result<Vec<Thread>, ParseError> {
+    match &options.cache {
+        Some(cache) => cache.parse(filename, || parse_file(filename, options)),
+        None => parse_file(filename, options),
+    }
+}
+
+// Apply the post-parse steps (participant merging, media extraction, session
+// splitting, redaction) to the conversations of one input
+fn finish_threads(mut threads: Vec<Thread>, options: &ParseOptions) -> Result<Vec<Thread>> {
+    if let Some(merger) = &options.merger {
+        threads.iter_mut().for_each(|thread| merger.apply(thread));
+    }
+    if let Some(media_dir) = &options.extract_media {
+        for thread in &mut threads {
+            media::extract_media(thread, media_dir)?;
+        }
+    }
+    let mut threads: Vec<Thread> = match options.session_gap {
+        Some(gap) =>
+            threads
+                .into_iter()
+                .flat_map(|thread| split_sessions(thread, gap))
+                .collect(),
+        None => threads,
+    };
+    // Redaction runs last so media extraction still sees the real source path
+    if options.redact {
+        for thread in &mut threads {
+            redact::redact_thread(thread, options.redact_keep_names);
+        }
+    }
+    Ok(threads)
+}
+
+// Parse a file into one thread per conversation it contains. Most files hold a single
+// conversation; concatenated exports are split by `split_conversations`. A `.json` file
+// is read back as the threads an earlier run wrote, without parsing options applied.
+fn parse_file(filename: &Path, options: &ParseOptions) -> Result<Vec<Thread>, ParseError> {
+    if json_input::is_json_file(filename) {
+        return json_input::read_threads(filename);
+    }
+    let (document, sources) = read_document(filename, options)?;
+    parse_document(&document, &sources, filename, options)
+}
+
+// Parse HTML that didn't come from a file; `source_path` is recorded as its provenance
+fn parse_html(
+    content: &str,
+    source_path: &Path,
+    options: &ParseOptions
+) -> Result<Vec<Thread>, ParseError> {
+    let sources = position::message_sources(content, options.raw_content);
+    parse_document(&Html::parse_document(&normalize_export(content)), &sources, source_path, options)
+}
+
+// Every `.message` element of the document is expected to end up in some thread; a
+// shortfall is recorded as a warning on the first thread. `sources` describe the
+// `.message` start tags in the raw HTML, used only if there is one per element (the
+// HTML parser may have restructured broken markup otherwise).
+fn parse_document(
+    document: &Html,
+    sources: &[MessageSource],
+    source_path: &Path,
+    options: &ParseOptions
+) -> Result<Vec<Thread>, ParseError> {
+    let selectors = selectors::get();
+    if options.voicemails && document.select(&selectors.voicemail).next().is_some() {
+        return voicemail::parse_voicemail(document, source_path, options).map(|thread| vec![thread]);
+    }
+    let elements = document.select(&selectors.message).count();
+    if elements == 0 && document.select(&selectors.conversation).next().is_none() {
+        return Err(ParseError::EmptyDocument);
+    }
+    let labels = parse_labels(document);
+    let counterpart = file_counterpart(document, source_path);
+    let conversations = split_conversations(document);
+    let grouped: usize = conversations
+        .iter()
+        .map(|c| c.messages.len())
+        .sum();
+    let element_sources: HashMap<_, &MessageSource> = if sources.len() == elements {
+        document
+            .select(&selectors.message)
+            .map(|el| el.id())
+            .zip(sources.iter())
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let mut threads: Vec<Thread> = conversations
+        .iter()
+        .enumerate()
+        .map(|(conversation_index, conversation)| {
+            let sources: Vec<Option<&MessageSource>> = conversation.messages
+                .iter()
+                .map(|el| element_sources.get(&el.id()).copied())
+                .collect();
+            parse_conversation(
+                conversation,
+                conversation_index,
+                &sources,
+                labels.clone(),
+                counterpart.as_deref(),
+                source_path,
+                options
+            )
+        })
+        .collect::<Result<_, _>>()?;
+    if grouped != elements {
+        threads[0].warnings.push(ParseWarning::MessageCountMismatch { elements, parsed: grouped });
+    }
+    Ok(threads)
+}
+
+// Read and parse a file through the thread's reusable buffer, along with what the raw
+// HTML says about its `.message` elements. The DOM owns copies of the text it needs, so
+// the raw HTML isn't kept alive while threads are built.
+fn read_document(
+    filename: &Path,
+    options: &ParseOptions
+) -> Result<(Html, Vec<MessageSource>), ParseError> {
+    READ_BUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        buffer.clear();
+        fs::File::open(filename).and_then(|mut file| file.read_to_end(&mut buffer))?;
+        let parsed = decode_html(&buffer, filename, options.strict_encoding).map(|html| {
+            let sources = position::message_sources(&html, options.raw_content);
+            (Html::parse_document(&normalize_export(&html)), sources)
+        });
+        buffer.clear();
+        buffer.shrink_to(READ_BUFFER_RETAIN);
+        parsed
+    })
+}
+
+// Decode raw HTML as UTF-8. Exports occasionally contain stray bytes that aren't, which
+// are replaced with U+FFFD (and counted in a warning) so one bad byte doesn't lose the
+// file, unless `strict` asks for the error instead.
+fn decode_html<'a>(bytes: &'a [u8], source: &Path, strict: bool) -> Result<Cow<'a, str>, ParseError> {
+    if let Err(e) = std::str::from_utf8(bytes) {
+        if strict {
+            return Err(ParseError::Encoding { offset: e.valid_up_to() });
+        }
+        let replaced: usize = bytes
+            .utf8_chunks()
+            .map(|chunk| chunk.invalid().len())
+            .sum();
+        warn!("{:?} is not valid UTF-8: replaced {} invalid bytes", source, replaced);
+    }
+    Ok(String::from_utf8_lossy(bytes))
+}
+
+// Exports extracted on Windows may start with a UTF-8 byte order mark and have CRLF line
+// endings, which would otherwise leak into the first text node (often a participant
+// name) and into content. Both are removed before the DOM is built so such files parse
+// exactly like Unix ones; source positions are still taken from the HTML as read, so they
+// match the file.
+fn normalize_export(html: &str) -> Cow<'_, str> {
+    let html = html.strip_prefix('\u{feff}').unwrap_or(html);
+    if html.contains('\r') {
+        Cow::Owned(html.replace("\r\n", "\n"))
+    } else {
+        Cow::Borrowed(html)
+    }
+}
+
+// The `.message` elements of one conversation, with the `.participants` header listing
+// its members when the export has one (group conversations)
+struct Conversation<'a> {
+    roster: Option<ElementRef<'a>>,
+    messages: Vec<ElementRef<'a>>,
+}
+
+// Group the document's `.message` elements by conversation. Each `.hChatLog`
+// container is a conversation, and within a container every `.participants` block
+// that follows earlier messages starts a new one. Always yields at least one
+// (possibly empty) conversation.
+fn split_conversations(document: &Html) -> Vec<Conversation<'_>> {
+    let selectors = selectors::get();
+    let mut containers: Vec<ElementRef> = document.select(&selectors.conversation).collect();
+    if containers.is_empty() {
+        containers.push(document.root_element());
+    }
+
+    let mut conversations = Vec::new();
+    for container in containers {
+        let mut roster = None;
+        let mut current = Vec::new();
+        for element in container.select(&selectors.boundary) {
+            if selectors.participants.matches(&element) {
+                if !current.is_empty() {
+                    conversations.push(Conversation { roster, messages: std::mem::take(&mut current) });
+                }
+                roster = Some(element);
+            } else {
+                current.push(element);
+            }
+        }
+        if !current.is_empty() {
+            conversations.push(Conversation { roster, messages: current });
+        }
+    }
+
+    if conversations.is_empty() {
+        conversations.push(Conversation { roster: None, messages: Vec::new() });
+    }
+    conversations
+}
+
+// Build a thread from the `.message` elements of a single conversation, the
+// `conversation_index`th of its file, with the source of each element where known and
+// who the file says it is with
+fn parse_conversation(
+    conversation: &Conversation,
+    conversation_index: usize,
+    sources: &[Option<&MessageSource>],
+    labels: Vec<String>,
+    counterpart: Option<&str>,
+    filename: &Path,
+    options: &ParseOptions
+) -> Result<Thread, ParseError> {
+    let selectors = selectors::get();
+    let dt_selector = &selectors.datetime;
+    let sender_selector = &selectors.sender;
+    let name_selector = &selectors.name;
+    let q_selector = &selectors.content;
+    let attachment_selector = &selectors.attachment;
+    let reply_selector = &selectors.reply;
+    let reply_sender_selector = &selectors.reply_sender;
+    let message_elements = &conversation.messages;
+    let positions: Vec<Option<SourcePosition>> = sources
+        .iter()
+        .map(|source| source.map(|s| s.position))
+        .collect();
+
+    let known_phones = known_phones(conversation, sender_selector);
+    let known_self_phone = conversation_self_phone(conversation, &known_phones, options);
+    let self_phone_known = known_self_phone.is_some();
+    let self_phone = known_self_phone.clone().unwrap_or_else(|| unknown_self_phone(filename));
+
+    // In order of first appearance, so output doesn't vary between runs
+    let mut participants: Vec<Participant> = Vec::new();
+    let mut me_participant = None;
+
+    // First pass: Collect all participants
+    for (message_index, message_element) in message_elements.iter().enumerate() {
+        let sender_element = message_element
+            .select(sender_selector)
+            .next()
+            .ok_or(ParseError::MissingSender { message_index })?;
+        let name = sender_element
+            .select(name_selector)
+            .next()
+            .and_then(|el| el.text().next())
+            .unwrap_or("");
+
+        let phone_number = sender_phone(&sender_element, name, &known_phones, &self_phone, filename, options);
+        let is_self = options.is_me_sender(&sender_element, name) || options.is_me_phone(&phone_number);
+
+        let mut participant = participant(name, &phone_number, options);
+        participant.alternate_phones = alternate_phones(&sender_element, &phone_number);
+        participant.avatar = sender_avatar(&sender_element);
+        if is_self {
+            if let Some(me_name) = &options.me_name {
+                participant.name = me_name.clone();
+            }
+            if me_participant.is_none() {
+                me_participant = Some(participant.clone());
+            }
+        }
+
+        match participants.iter_mut().find(|p| **p == participant) {
+            Some(known) => {
+                for phone in participant.alternate_phones {
+                    if !known.alternate_phones.contains(&phone) {
+                        known.alternate_phones.push(phone);
+                    }
+                }
+                if known.avatar.is_none() {
+                    known.avatar = participant.avatar;
+                }
+            }
+            None => participants.push(participant),
+        }
+    }
+
+    // Group members who never sent a message are only listed in the header. Numbers
+    // already known from a message keep the name the messages use.
+    if let Some(roster) = conversation.roster {
+        for member in roster.select(sender_selector) {
+            let name = member
+                .select(name_selector)
+                .next()
+                .and_then(|el| el.text().next())
+                .unwrap_or("");
+            let phone_number = sender_phone(&member, name, &known_phones, &self_phone, filename, options);
+            let is_self = options.is_me_sender(&member, name) || options.is_me_phone(&phone_number);
+            if !is_self && !participants.iter().any(|p| p.phone == phone_number) {
+                let mut member_participant = participant(name, &phone_number, options);
+                member_participant.alternate_phones = alternate_phones(&member, &phone_number);
+                member_participant.avatar = sender_avatar(&member);
+                participants.push(member_participant);
+            }
+        }
+    }
+
+    // Without a message from you there is nothing to take your number from. You then get
+    // the file's `me:<hash>` placeholder and `self_phone_known` says so.
+    let me_participant = me_participant.unwrap_or_else(||
+        Participant::new(options.me_name.as_deref().unwrap_or("Me"), &self_phone)
+    );
+    // Texts to your own number only ever involve the self phone; without special casing
+    // their messages would end up with no recipients at all. A conversation the other side
+    // never answered has only your messages too, so it also takes a file named for your
+    // own number.
+    let is_self_thread = counterpart.is_some_and(|c| is_own_number(c, known_self_phone.as_deref().unwrap_or(""), options)) &&
+        !participants.is_empty() &&
+        participants.iter().all(|p| p.phone == me_participant.phone);
+    // Such a conversation still has the other side as the recipient of your messages,
+    // known only from the file
+    if !is_self_thread && !participants.is_empty() && participants.iter().all(|p| p.phone == me_participant.phone) {
+        if let Some(counterpart) = counterpart {
+            let (name, phone) = if looks_like_phone(counterpart) {
+                (String::new(), counterpart.to_string())
+            } else {
+                (counterpart.to_string(), unknown_phone(filename, counterpart, options))
+            };
+            participants.push(participant(&name, &phone, options));
+        }
+    }
+    let full_recipients =
+        options.group_full_recipients && is_group_conversation(&participants, &filename.to_string_lossy());
+
+    // Second pass: Parse messages
+    let mut warnings = Vec::new();
+    // Ids of the messages parsed so far with their sender name and normalized
+    // content, for resolving quoted replies
+    let mut earlier: Vec<(String, String, String)> = Vec::new();
+    let mut undated = 0;
+    let mut messages = message_elements
+        .iter()
+        .enumerate()
+        .filter_map(|(message_index, message_element)| {
+            // A missing `.dt` and one that can't be read are told apart in the warnings,
+            // though both date the message to the epoch
+            let dt = message_element.select(dt_selector).next();
+            let title = dt.and_then(|el| el.value().attr("title"));
+            let parsed = title
+                .and_then(|date_str| DateTime::parse_from_str(date_str, DATETIME_FORMAT).ok())
+                .map(|dt| dt.with_timezone(&Utc));
+            let timestamp = match (dt, parsed) {
+                (_, Some(timestamp)) => timestamp,
+                (None, None) => {
+                    warnings.push(ParseWarning::MissingTimestamp {
+                        message_index,
+                        source_position: positions[message_index],
+                    });
+                    if options.drop_undated {
+                        undated += 1;
+                        return None;
+                    }
+                    DateTime::UNIX_EPOCH
+                }
+                (Some(_), None) => {
+                    warnings.push(ParseWarning::InvalidTimestamp {
+                        message_index,
+                        title: title.map(str::to_string),
+                        source_position: positions[message_index],
+                    });
+                    DateTime::UNIX_EPOCH
+                }
+            };
+
+            // Every message has a sender, checked in the first pass
+            let sender_element = message_element.select(sender_selector).next().unwrap();
+            let name = sender_element
+                .select(name_selector)
+                .next()
+                .and_then(|el| el.text().next())
+                .unwrap_or("");
+            let phone_number = sender_phone(&sender_element, name, &known_phones, &self_phone, filename, options);
+
+            // Senders are resolved by number, and every number was collected in the first
+            // pass. A number shown under several names, or a sender marked as someone else
+            // that has your number, would silently misattribute messages, so those are
+            // flagged.
+            let from = participants
+                .iter()
+                .find(|p| p.phone == phone_number)
+                .unwrap()
+                .clone();
+            let is_self = options.is_me_sender(&sender_element, name) || options.is_me_phone(&phone_number);
+            let shown_as = match &options.me_name {
+                Some(me_name) if is_self => me_name.clone(),
+                _ => participant(name, &phone_number, options).name,
+            };
+            let has_self_phone = options.is_me_phone(&phone_number) ||
+                (self_phone_known && phone_digits(&phone_number) == phone_digits(&self_phone));
+            if sender_is_self(&sender_element) == Some(false) && has_self_phone {
+                warnings.push(ParseWarning::SenderHasSelfPhone {
+                    message_index,
+                    phone: phone_number.clone(),
+                    name: name.to_string(),
+                    source_position: positions[message_index],
+                });
+            } else if from.name != shown_as && options.unknown_phone.as_ref() != Some(&phone_number) {
+                // Collapsed unknown senders share one number on purpose
+                warnings.push(ParseWarning::SenderNameConflict {
+                    message_index,
+                    phone: phone_number.clone(),
+                    name: name.to_string(),
+                    attributed_to: from.name.clone(),
+                    source_position: positions[message_index],
+                });
+            }
+
+            let direction = if is_self_thread || from == me_participant {
+                Direction::Outgoing
+            } else {
+                Direction::Incoming
+            };
+            let to = if is_self_thread {
+                vec![me_participant.clone()]
+            } else if from == me_participant {
+                participants
+                    .iter()
+                    .filter(|&p| p != &me_participant)
+                    .cloned()
+                    .collect()
+            } else if full_recipients {
+                // You, even without a message of your own, and everyone else but the sender under
+                // any of the names their number goes by
+                std::iter::once(me_participant.clone())
+                    .chain(participants.iter().filter(|&p| p.phone != from.phone && p != &me_participant).cloned())
+                    .collect()
+            } else {
+                vec![me_participant.clone()]
+            };
+
+            let content_element = message_element
+                .select(q_selector)
+                .find(|el| !in_reply(el, reply_selector));
+            let content = content_element.map(|el| content_text(&el));
+            // The id is derived from the content as written so it doesn't depend on the flag,
+            // and from its text nodes alone so ids stay the same as before line breaks were kept
+            let id = message_id(
+                &from.phone,
+                &timestamp,
+                content_element.map(|el| el.text().collect::<String>()).as_deref(),
+                filename
+            );
+
+            let kind = message_kind(message_element, name, content.as_deref(), &options.notices);
+            let reply_to = message_element.select(reply_selector).next().and_then(|quote| {
+                let quoted_text = normalize_content(
+                    &quote
+                        .select(q_selector)
+                        .next()
+                        .map(|el| content_text(&el))
+                        .unwrap_or_default()
+                );
+                let quoted_sender = quote
+                    .select(reply_sender_selector)
+                    .next()
+                    .map(|el| el.text().collect::<String>().trim().to_string());
+                // The most recent match wins when the same text was sent more than once
+                let found = earlier
+                    .iter()
+                    .rev()
+                    .find(|(_, sender, text)| {
+                        *text == quoted_text && quoted_sender.as_ref().is_none_or(|name| name == sender)
+                    })
+                    .map(|(id, _, _)| id.clone());
+                if found.is_none() {
+                    warnings.push(ParseWarning::UnresolvedReply {
+                        message_index,
+                        source_position: positions[message_index],
+                    });
+                }
+                found
+            });
+            // A reaction quotes the message it reacts to in its own text
+            let reply_to = match (kind, content.as_deref().and_then(tapback_target)) {
+                (MessageKind::Reaction, Some(target)) if reply_to.is_none() => {
+                    let target = normalize_content(target);
+                    let truncated = target.strip_suffix('…');
+                    earlier
+                        .iter()
+                        .rev()
+                        .find(|(_, _, text)| match truncated {
+                            Some(prefix) => text.starts_with(prefix),
+                            None => *text == target,
+                        })
+                        .map(|(id, _, _)| id.clone())
+                }
+                _ => reply_to,
+            };
+            earlier.push((
+                id.clone(),
+                from.name.clone(),
+                normalize_content(content.as_deref().unwrap_or("")),
+            ));
+            let content = match content {
+                Some(content) if options.normalize_content => Some(normalize_content(&content)),
+                content => content,
+            };
+            // `--raw-content` only replaces the stored text; kind, replies, language and
+            // flags are still derived from the decoded text
+            let body_content = match content.clone() {
+                Some(text) if options.raw_content => {
+                    let raw = sources[message_index].and_then(|s| s.raw_content.clone());
+                    if raw.is_none() {
+                        warnings.push(ParseWarning::RawContentUnavailable {
+                            message_index,
+                            source_position: positions[message_index],
+                        });
+                    }
+                    Some(raw.unwrap_or(text))
+                }
+                content => content,
+            };
+            let (body_content, truncated) = match (body_content, options.max_content_length) {
+                (Some(text), Some(max)) => {
+                    let (text, truncated) = truncate_content(text, max);
+                    (Some(text), truncated)
+                }
+                (content, _) => (content, false),
+            };
+            let language = if options.detect_language && kind == MessageKind::Regular {
+                content.as_deref().and_then(detect_language)
+            } else {
+                None
+            };
+            let flags = match (&options.keywords, content.as_deref()) {
+                (Some(keywords), Some(content)) => keywords.flags(content),
+                _ => Vec::new(),
+            };
+
+            Some(Message {
+                id,
+                from,
+                to,
+                direction,
+                kind,
+                timestamp,
+                body: MessageBody::new(
+                    body_content,
+                    parse_attachments(message_element, attachment_selector, reply_selector, sender_selector)
+                ),
+                reply_to,
+                language,
+                flags,
+                truncated,
+                source_position: positions[message_index].filter(|_| options.include_raw),
+                raw_html: options.include_raw.then(|| message_element.html()),
+                message_index: None,
+            })
+        })
+        .collect::<Vec<_>>();
+    if let Some(rename_map) = &options.rename_map {
+        for_each_participant_mut(&mut participants, &mut messages, |participant| {
+            rename_map.rename(participant)
+        });
+        // Entries for one contact under different names now coincide
+        let mut seen = HashSet::new();
+        participants.retain(|p| seen.insert(p.clone()));
+    }
+    // Messages dropped by `--drop-undated` were parsed, just not kept
+    if messages.len() + undated != message_elements.len() {
+        warnings.push(ParseWarning::MessageCountMismatch {
+            elements: message_elements.len(),
+            parsed: messages.len(),
+        });
+    }
+    if !options.keep_intra_duplicates {
+        let dropped = drop_repeated_messages(&mut messages);
+        if dropped > 0 {
+            warnings.push(ParseWarning::RepeatedMessages { dropped });
+        }
+    }
+
+    if let Some(classifier) = &options.classifier {
+        for_each_participant_mut(&mut participants, &mut messages, |participant| {
+            participant.participant_type = Some(classifier.classify(participant));
+        });
+    }
+
+    if options.skip_system {
+        messages.retain(|m| m.kind == MessageKind::Regular);
+        // A notice sender has nothing left to say in the thread
+        let is_notice_sender = |p: &Participant| options.notices.is_notice_sender(&p.name);
+        participants.retain(|p| !is_notice_sender(p));
+        for message in &mut messages {
+            message.to.retain(|p| !is_notice_sender(p));
+        }
+    }
+    if options.sort_messages {
+        // `sort_by_key` is stable, so equal timestamps keep their document order
+        messages.sort_by_key(|m| m.timestamp);
+    }
+
+    apply_participant_stats(&mut participants, &mut messages);
+    if options.geo {
+        for_each_participant_mut(&mut participants, &mut messages, |participant| {
+            participant.region = geo::region_for_phone(&participant.phone).map(str::to_string);
+        });
+    }
+
+    let message_count = counted_messages(&messages);
+    Ok(Thread {
+        thread_id: thread_id(&participants, filename, conversation_index),
+        source_path: filename.to_string_lossy().to_string(),
+        participant_stats: thread_participant_stats(&participants, &messages),
+        messages,
+        participants,
+        labels,
+        message_count,
+        is_self_thread,
+        self_phone_known,
+        session_index: None,
+        thread_index: None,
+        warnings,
+    })
+}
+
+// Keep the first of the messages that have the same sender, time, content and
+// attachments, returning how many were dropped. Only copies within one conversation are
+// compared; whole files repeated in the input are skipped before parsing instead.
+fn drop_repeated_messages(messages: &mut Vec<Message>) -> usize {
+    let before = messages.len();
+    let mut seen = HashSet::new();
+    messages.retain(|message| {
+        let media: Vec<String> = message.body
+            .media()
+            .iter()
+            .map(|attachment| attachment.src.clone())
+            .collect();
+        seen.insert((
+            message.from.phone.clone(),
+            message.timestamp,
+            message.body.text().map(str::to_string),
+            media,
+        ))
+    });
+    before - messages.len()
+}
+
+// Split a thread wherever consecutive messages (in their current order) are more than
+// `gap` apart. Each session keeps the full participant list with rollups recomputed
+// for its own messages; parse warnings stay with the first session.
+fn split_sessions(thread: Thread, gap: chrono::Duration) -> Vec<Thread> {
+    let Thread {
+        thread_id,
+        source_path,
+        messages,
+        participants,
+        labels,
+        is_self_thread,
+        self_phone_known,
+        warnings,
+        ..
+    } = thread;
+
+    let mut sessions: Vec<Vec<Message>> = Vec::new();
+    for message in messages {
+        let within_gap = sessions
+            .last()
+            .and_then(|session| session.last())
+            .is_some_and(|last| message.timestamp - last.timestamp <= gap);
+        match sessions.last_mut() {
+            Some(session) if within_gap => session.push(message),
+            _ => sessions.push(vec![message]),
+        }
+    }
+    if sessions.is_empty() {
+        sessions.push(Vec::new());
+    }
+
+    let mut warnings = Some(warnings);
+    sessions
+        .into_iter()
+        .enumerate()
+        .map(|(session_index, mut messages)| {
+            let mut participants: Vec<Participant> = participants
+                .iter()
+                .map(|p| Participant {
+                    message_count: 0,
+                    first_seen: None,
+                    last_seen: None,
+                    ..p.clone()
+                })
+                .collect();
+            apply_participant_stats(&mut participants, &mut messages);
+            Thread {
+                thread_id: session_thread_id(&thread_id, session_index),
+                source_path: source_path.clone(),
+                participant_stats: thread_participant_stats(&participants, &messages),
+                message_count: counted_messages(&messages),
+                messages,
+                participants,
+                labels: labels.clone(),
+                is_self_thread,
+                self_phone_known,
+                session_index: Some(session_index),
+                thread_index: None,
+                warnings: warnings.take().unwrap_or_default(),
+            }
+        })
+        .collect()
 }
 
-// Struct to represent a single message
-#[derive(Debug, Clone, Serialize)]
-struct Message {
-    from: Participant,
-    to: Vec<Participant>,
-    timestamp: DateTime<Utc>,
-    content: String,
+// Parse a duration such as `45s`, `90m`, `24h`, `7d` or `1d12h` for `--session-gap`
+fn parse_duration(value: &str) -> Result<chrono::Duration, String> {
+    let mut total = chrono::Duration::zero();
+    let mut number = String::new();
+    for c in value.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let amount: i64 = number
+            .parse()
+            .map_err(|_| format!("invalid duration {:?}: expected a number before {:?}", value, c))?;
+        total += match c {
+            's' => chrono::Duration::seconds(amount),
+            'm' => chrono::Duration::minutes(amount),
+            'h' => chrono::Duration::hours(amount),
+            'd' => chrono::Duration::days(amount),
+            'w' => chrono::Duration::weeks(amount),
+            _ => {
+                return Err(format!("invalid duration unit {:?} in {:?} (use s, m, h, d or w)", c, value));
+            }
+        };
+        number.clear();
+    }
+    if !number.is_empty() || total.is_zero() {
+        return Err(format!("invalid duration {:?}: expected e.g. 30m or 24h", value));
+    }
+    Ok(total)
 }
 
-// Struct to represent a thread of messages
-#[derive(Debug, Serialize)]
-struct Thread {
-    messages: Vec<Message>,
-    participants: Vec<Participant>,
-    labels: Vec<String>,
-    message_count: usize,
+// Parse a byte size such as `4096`, `500K`, `5M` or `1G` (binary multiples) for
+// `--embed-max-size`
+fn parse_size(value: &str) -> Result<u64, String> {
+    let trimmed = value.trim();
+    let (number, multiplier) = match trimmed.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&trimmed[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&trimmed[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&trimmed[..i], 1 << 30),
+        _ => (trimmed, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size {:?}: expected e.g. 500K or 5M", value))
 }
 
-// Command-line interface struct
-#[derive(Parser)]
-#[clap(author, version, about, long_about = None)]
-struct Cli {
-    /// Input file or directory
-    input: PathBuf,
-
-    /// Output format
-    #[clap(value_enum, default_value_t = OutputFormat::Default)]
-    format: OutputFormat,
+// Parse a `--merge-threshold` similarity between 0 and 1
+fn parse_similarity(value: &str) -> Result<f64, String> {
+    let similarity: f64 = value.parse().map_err(|_| format!("invalid similarity {:?}", value))?;
+    if !(0.0..=1.0).contains(&similarity) {
+        return Err(format!("similarity {:?} must be between 0 and 1", value));
+    }
+    Ok(similarity)
 }
 
-// Enum to represent different output formats
-#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
-enum OutputFormat {
-    Debug,
-    Json,
-    Default,
-    Cypher,
+// Parse a positive `--neo4j-max-rate` in messages per second
+fn parse_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value.parse().map_err(|_| format!("invalid rate {:?}", value))?;
+    if !(rate.is_finite() && rate > 0.0) {
+        return Err(format!("rate {:?} must be a positive number", value));
+    }
+    Ok(rate)
 }
 
-// Struct to hold statistics about the processing run
-#[derive(Debug)]
-struct RunStatistics {
-    duration: std::time::Duration,
-    files_processed: usize,
-    messages_extracted: usize,
-    unique_participants: usize,
-    avg_messages_per_file: f64,
+// Labels are spliced into the Cypher, so the prefix is kept to what needs no quoting
+fn parse_label_prefix(value: &str) -> Result<String, String> {
+    let valid = value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') &&
+        !value.starts_with(|c: char| c.is_ascii_digit());
+    if !valid {
+        return Err(format!("label prefix {:?} may only hold letters, digits and underscores and can't start with a digit", value));
+    }
+    Ok(value.to_string())
 }
 
-fn main() -> Result<()> {
-    env_logger::init();
-    info!("Starting...");
-    let cli = Cli::parse();
+// Parse a percentage between 0 and 100 for `--max-failure-rate`
+fn parse_percentage(value: &str) -> Result<f64, String> {
+    let pct: f64 = value.trim_end_matches('%').parse().map_err(|_| format!("invalid percentage {:?}", value))?;
+    if !(0.0..=100.0).contains(&pct) {
+        return Err(format!("percentage {:?} must be between 0 and 100", value));
+    }
+    Ok(pct)
+}
 
-    // Expand the input path, including any glob patterns
-    let expanded_paths: Vec<PathBuf> = glob(&cli.input.to_string_lossy())
-        .with_context(|| format!("Failed to read glob pattern: {:?}", cli.input))?
-        .filter_map(Result::ok)
-        .collect();
+// Parse an IANA time zone name for `--tz-output`
+fn parse_timezone(value: &str) -> Result<Tz, String> {
+    value
+        .parse()
+        .map_err(|_| format!("unknown time zone {:?} (expected an IANA name such as Europe/Paris)", value))
+}
 
-    if expanded_paths.is_empty() {
-        error!("No matching paths found for: {:?}", cli.input);
-        anyhow::bail!("No matching paths found for: {:?}", cli.input);
+// The sender's number from its `tel:` link. Without one your own messages take your
+// number in the conversation (see `conversation_self_phone`), and so do those whose link
+// isn't one of your `--me-phone` numbers; other senders get a placeholder.
+fn sender_phone(
+    sender_element: &ElementRef,
+    name: &str,
+    known_phones: &[String],
+    self_phone: &str,
+    filename: &Path,
+    options: &ParseOptions
+) -> String {
+    let tel = choose_tel(&sender_tels(sender_element), known_phones);
+    if options.is_me_sender(sender_element, name) {
+        return match tel {
+            Some(tel) if options.me_phone.is_none() || options.is_me_phone(&tel) => tel,
+            _ => self_phone.to_string(),
+        };
     }
+    tel.unwrap_or_else(|| unknown_phone(filename, name, options))
+}
 
-    for path in expanded_paths {
-        if path.is_dir() {
-            process_directory(&path, &cli.format)?;
-        } else if path.is_file() {
-            process_file(&path, &cli.format)?;
-        } else {
-            println!("Skipping non-file, non-directory: {:?}", path);
-        }
+// Your number in a conversation: the `tel:` link of the first of your messages that has
+// one, so those without a link are still attributed to the same participant, or else the
+// first `--me-phone`. With `--me-phone` only a link to one of those numbers counts, so
+// the configured number is used throughout. None when neither gives one.
+fn conversation_self_phone(
+    conversation: &Conversation,
+    known_phones: &[String],
+    options: &ParseOptions
+) -> Option<String> {
+    let selectors = selectors::get();
+    conversation.messages
+        .iter()
+        .filter_map(|el| el.select(&selectors.sender).next())
+        .find_map(|sender| {
+            let tel = choose_tel(&sender_tels(&sender), known_phones)?;
+            let name = sender
+                .select(&selectors.name)
+                .next()
+                .and_then(|el| el.text().next())
+                .unwrap_or("");
+            let is_yours = match options.me_phone {
+                Some(_) => options.is_me_phone(&tel),
+                None => options.is_me_sender(&sender, name),
+            };
+            is_yours.then_some(tel)
+        })
+        .or_else(|| options.me_phone.clone())
+}
+
+// Who a one-on-one export is with: the name or number Takeout puts before ` - Text - `
+// in the file name, or the document's `<title>` when the name doesn't follow that
+// pattern (HTML read from stdin, renamed files)
+fn file_counterpart(document: &Html, source_path: &Path) -> Option<String> {
+    let file_name = source_path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+    if file_name.contains(GROUP_CONVERSATION_LABEL) {
+        return None;
     }
+    let from_name = file_name
+        .split_once(TEXT_LABEL)
+        .map(|(counterpart, _)| counterpart.trim().to_string());
+    from_name
+        .or_else(|| {
+            document
+                .select(&selectors::get().title)
+                .next()
+                .map(|title| title.text().collect::<String>().trim().to_string())
+                .filter(|title| title != GROUP_CONVERSATION_LABEL.trim_end_matches('-').trim())
+        })
+        .filter(|counterpart| !counterpart.is_empty())
+}
 
-    Ok(())
+// Takeout names a file for a contact without a name by their number
+fn looks_like_phone(counterpart: &str) -> bool {
+    counterpart.chars().any(|c| c.is_ascii_digit()) &&
+        counterpart.chars().all(|c| c.is_ascii_digit() || "+-() .".contains(c))
 }
 
-fn process_directory(dir: &Path, format: &OutputFormat) -> Result<()> {
-    let start_time = Instant::now();
-    let mut files_processed = 0;
-    let mut total_messages = 0;
-    let mut all_participants = HashSet::new();
+// Whether a file's counterpart is your own number, compared by digits with the self phone
+// of the conversation or any `--me-phone`
+fn is_own_number(counterpart: &str, self_phone: &str, options: &ParseOptions) -> bool {
+    looks_like_phone(counterpart) &&
+        (phone_digits(counterpart) == phone_digits(self_phone) || options.is_me_phone(counterpart))
+}
 
-    // Get the list of files to process
-    let files: Vec<_> = WalkDir::new(dir)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| {
-            e.file_type().is_file() &&
-                e
-                    .path()
-                    .extension()
-                    .map_or(false, |ext| ext == FILE_TYPE) &&
-                (e.file_name().to_str().unwrap_or("").contains(TEXT_LABEL) ||
-                    e.file_name().to_str().unwrap_or("").contains(GROUP_CONVERSATION_LABEL))
+// Numbers that identify someone in a conversation unambiguously: those of senders and
+// header members with a single tel link. A contact card listing several numbers
+// resolves to one of these.
+fn known_phones(conversation: &Conversation, sender_selector: &Selector) -> Vec<String> {
+    conversation.messages
+        .iter()
+        .filter_map(|el| el.select(sender_selector).next())
+        .chain(conversation.roster.iter().flat_map(|roster| roster.select(sender_selector)))
+        .filter_map(|sender| {
+            let mut tels = sender_tels(&sender);
+            (tels.len() == 1).then(|| tels.remove(0))
         })
-        .collect();
+        .collect()
+}
 
-    // Set up progress bar for Default format
-    let progress_bar = if *format == OutputFormat::Default {
-        let pb = ProgressBar::new(files.len() as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template(
-                    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})"
-                )
-                .unwrap()
-                .progress_chars("#>-")
-        );
-        Some(pb)
-    } else {
-        None
-    };
+// Numbers of a sender's `tel:` links in document order; a contact card with several
+// numbers (home and cell) has one link per number
+fn sender_tels(sender_element: &ElementRef) -> Vec<String> {
+    sender_element
+        .select(&selectors::get().tel)
+        .filter_map(|el| el.value().attr("href"))
+        .filter_map(|href| href.strip_prefix("tel:"))
+        .map(str::to_string)
+        .collect()
+}
 
-    // Create a channel for Neo4j writing if needed
-    let channel = if *format == OutputFormat::Cypher {
-        Some(tokio::sync::mpsc::channel(100))
-    } else {
-        None
-    };
+// The number a sender is taken to have: of several, the one someone in the thread is
+// already known by (as it is written there), so a contact isn't split across their
+// numbers, or else the first
+fn choose_tel(tels: &[String], known_phones: &[String]) -> Option<String> {
+    if tels.len() > 1 {
+        for tel in tels {
+            let digits = phone_digits(tel);
+            if let Some(known) = known_phones.iter().find(|known| phone_digits(known) == digits) {
+                return Some(known.clone());
+            }
+        }
+    }
+    tels.first().cloned()
+}
 
-    let (tx, rx) = match channel {
-        Some((tx, rx)) => (Some(tx), Some(rx)),
-        None => (None, None),
-    };
-    // Spawn Neo4j writer thread if Cypher format is selected
-    let neo4j_handle = if let Some(rx) = rx {
-        Some(
-            std::thread::spawn(move || {
-                let rt = tokio::runtime::Runtime::new().unwrap();
-                rt.block_on(async {
-                    if let Err(e) = neo4j_writer(rx).await {
-                        eprintln!("Error in Neo4j writer: {:?}", e);
-                    }
-                });
-            })
-        )
-    } else {
-        None
-    };
+// `src` of a contact photo in the sender markup
+fn sender_avatar(sender_element: &ElementRef) -> Option<String> {
+    sender_element
+        .select(&selectors::get().avatar)
+        .find_map(|img| img.value().attr("src"))
+        .map(str::trim)
+        .filter(|src| !src.is_empty())
+        .map(str::to_string)
+}
 
-    // Process each file
-    for entry in files {
-        let thread = parse_file(entry.path()).context("Failed to parse file")?;
-        files_processed += 1;
-        total_messages += thread.messages.len();
-        all_participants.extend(thread.participants.iter().cloned());
-        match format {
-            OutputFormat::Debug => println!("{:#?}", thread),
-            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&thread)?),
-            OutputFormat::Default => {
-                if let Some(pb) = &progress_bar {
-                    pb.inc(1);
-                }
-            }
-            OutputFormat::Cypher => {
-                if let Some(tx) = &tx {
-                    // Use blocking send for synchronous context
-                    tx.blocking_send(thread).context("Failed to send thread to Neo4j writer")?;
-                }
-            }
+// The numbers of a sender's contact card other than the one chosen for it
+fn alternate_phones(sender_element: &ElementRef, phone: &str) -> Vec<String> {
+    let digits = phone_digits(phone);
+    let mut alternates: Vec<String> = Vec::new();
+    for tel in sender_tels(sender_element) {
+        if phone_digits(&tel) != digits && !alternates.contains(&tel) {
+            alternates.push(tel);
         }
     }
+    alternates
+}
 
-    // Finalize progress bar
-    if let Some(pb) = progress_bar {
-        pb.finish_with_message("Processing complete");
+// What the ids and placeholders derived from a source hash of its path: the file name
+// alone, so moving an export to another directory keeps them
+fn source_name(source_path: &Path) -> Cow<'_, str> {
+    source_path.file_name().unwrap_or(source_path.as_os_str()).to_string_lossy()
+}
+
+// Placeholder phone for a sender without a tel link. Unless collapsing is requested it
+// is `unknown:<hash>` of the source's file name and sender name, so unresolved contacts
+// from different files don't merge into one participant downstream.
+fn unknown_phone(source_path: &Path, name: &str, options: &ParseOptions) -> String {
+    if let Some(placeholder) = &options.unknown_phone {
+        return placeholder.clone();
     }
-    // Drop the sender to signal the receiver that we're done
-    drop(tx);
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(source_name(source_path).as_bytes());
+    hasher.update(b"\0");
+    hasher.update(name.as_bytes());
+    format!("unknown:{}", &hasher.finalize().to_hex()[..16])
+}
+
+// Your phone in a file that doesn't give your number: `me:<hash>` of the source's file
+// name.
+// Like `unknown_phone` it keeps a placeholder from joining participants of other files
+// into one node downstream, and its prefix keeps it apart from unknown contacts.
+fn unknown_self_phone(source_path: &Path) -> String {
+    let hash = blake3::hash(source_name(source_path).as_bytes());
+    format!("me:{}", &hash.to_hex()[..16])
+}
 
-    // Wait for the Neo4j writer to finish if it was started
-    if let Some(handle) = neo4j_handle {
-        handle.join().map_err(|e| anyhow::anyhow!("Failed to join Neo4j writer thread: {:?}", e))?;
+// Derive a stable message id as the hex blake3 hash of the sender phone, RFC 3339
+// timestamp, content and the source's file name. Fields are NUL-terminated so adjacent values
+// can't run together, and missing content hashes differently from empty content.
+fn message_id(
+    from_phone: &str,
+    timestamp: &DateTime<Utc>,
+    content: Option<&str>,
+    source_path: &Path
+) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for field in [from_phone, &timestamp.to_rfc3339(), &source_name(source_path)] {
+        hasher.update(field.as_bytes());
+        hasher.update(b"\0");
     }
-    // Calculate and display statistics for Default format
-    if *format == OutputFormat::Default {
-        let duration = start_time.elapsed();
-        let stats = RunStatistics {
-            duration,
-            files_processed,
-            messages_extracted: total_messages,
-            unique_participants: all_participants.len(),
-            avg_messages_per_file: (total_messages as f64) / (files_processed as f64),
-        };
-        print_statistics(&stats);
+    match content {
+        Some(content) => {
+            hasher.update(b"T");
+            hasher.update(content.as_bytes());
+        }
+        None => {
+            hasher.update(b"N");
+        }
     }
-
-    Ok(())
+    hasher.finalize().to_hex().to_string()
 }
 
-fn process_file(file_path: &Path, format: &OutputFormat) -> Result<()> {
-    println!("Processing file: {:?}", file_path);
-    let thread = parse_file(file_path).context("Failed to parse file")?;
-    // Create a channel for Neo4j writing if needed
+// Derive a stable thread id as the hex blake3 hash of the thread's distinct participant
+// phones sorted bytewise, the source's file name (see `source_name`) and `conversation:<index>`, the conversation's position in the
+// file from 0, each followed by a NUL byte. Google Voice splits a contact's history over
+// many dated files and a concatenated file can hold several conversations with the same
+// people, so the source is needed to tell them apart; the id stays the same across runs
+// regardless of message content.
+fn thread_id(participants: &[Participant], source_path: &Path, conversation_index: usize) -> String {
+    let mut phones: Vec<&str> = participants
+        .iter()
+        .map(|p| p.phone.as_str())
+        .collect();
+    phones.sort_unstable();
+    phones.dedup();
 
-    // Create a channel for Neo4j writing if needed
-    let channel = if *format == OutputFormat::Cypher {
-        Some(tokio::sync::mpsc::channel(100))
-    } else {
-        None
-    };
+    let mut hasher = blake3::Hasher::new();
+    for phone in phones {
+        hasher.update(phone.as_bytes());
+        hasher.update(b"\0");
+    }
+    hasher.update(source_name(source_path).as_bytes());
+    hasher.update(b"\0");
+    hasher.update(format!("conversation:{}", conversation_index).as_bytes());
+    hasher.update(b"\0");
+    hasher.finalize().to_hex().to_string()
+}
 
-    let (tx, rx) = match channel {
-        Some((tx, rx)) => (Some(tx), Some(rx)),
-        None => (None, None),
-    };
-    // Spawn Neo4j writer thread if Cypher format is selected
-    let neo4j_handle = if let Some(rx) = rx {
-        Some(
-            std::thread::spawn(move || {
-                let rt = tokio::runtime::Runtime::new().unwrap();
-                rt.block_on(async {
-                    if let Err(e) = neo4j_writer(rx).await {
-                        eprintln!("Error in Neo4j writer: {:?}", e);
-                    }
-                });
+// The id of a `--session-gap` session: the hex blake3 hash of its conversation's id and
+// `session:<index>`, each followed by a NUL byte
+fn session_thread_id(thread_id: &str, session_index: usize) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for field in [thread_id, &format!("session:{}", session_index)] {
+        hasher.update(field.as_bytes());
+        hasher.update(b"\0");
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+// Compute per-participant rollups from the thread's messages and stamp them onto
+// the participant list as well as every message's `from`/`to` copies
+fn apply_participant_stats(participants: &mut [Participant], messages: &mut [Message]) {
+    let mut stats: HashMap<String, (usize, DateTime<Utc>, DateTime<Utc>)> = HashMap::new();
+    for message in messages.iter().filter(|m| m.kind == MessageKind::Regular) {
+        stats
+            .entry(message.from.phone.clone())
+            .and_modify(|(count, first, last)| {
+                *count += 1;
+                *first = (*first).min(message.timestamp);
+                *last = (*last).max(message.timestamp);
             })
-        )
-    } else {
-        None
-    };
-    match format {
-        OutputFormat::Debug => println!("{:#?}", thread),
-        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&thread)?),
-        OutputFormat::Default => {
-            println!("Processed 1 file with {} messages", thread.messages.len());
+            .or_insert((1, message.timestamp, message.timestamp));
+    }
+
+    for_each_participant_mut(participants, messages, |participant| {
+        if let Some(&(count, first, last)) = stats.get(&participant.phone) {
+            participant.message_count = count;
+            participant.first_seen = Some(first);
+            participant.last_seen = Some(last);
         }
-        OutputFormat::Cypher => {
-            if let Some(tx) = &tx {
-                // Use blocking send for synchronous context
-                tx.blocking_send(thread).context("Failed to send thread to Neo4j writer")?;
+    });
+}
+
+// Classify each participant's role in the thread from the earliest message, which is
+// found by timestamp so it doesn't depend on `--sort-messages`. When senders tie for
+// the earliest timestamp each of them is a co-initiator.
+fn thread_participant_stats(
+    participants: &[Participant],
+    messages: &[Message]
+) -> Vec<ThreadParticipantStats> {
+    let regular = || messages.iter().filter(|m| m.kind == MessageKind::Regular);
+    let first_timestamp = regular()
+        .map(|m| m.timestamp)
+        .min();
+    let initiators: HashSet<&str> = regular()
+        .filter(|m| Some(m.timestamp) == first_timestamp)
+        .map(|m| m.from.phone.as_str())
+        .collect();
+    let mut latencies = reply_latencies(messages);
+
+    participants
+        .iter()
+        .map(|participant| {
+            let role = if initiators.contains(participant.phone.as_str()) {
+                if initiators.len() == 1 {
+                    ParticipantRole::Initiator
+                } else {
+                    ParticipantRole::CoInitiator
+                }
+            } else if participant.message_count > 0 {
+                ParticipantRole::Responder
+            } else {
+                ParticipantRole::Silent
+            };
+            let total = counted_messages(messages);
+            let message_share = if total == 0 {
+                0.0
+            } else {
+                ((participant.message_count as f64) * 100.0) / (total as f64)
+            };
+            ThreadParticipantStats {
+                phone: participant.phone.clone(),
+                role,
+                message_share,
+                median_reply_secs: latencies.get_mut(participant.phone.as_str()).map(|gaps| median(gaps)),
             }
+        })
+        .collect()
+}
+
+// Seconds from each regular message to the next one when that is by a different sender,
+// collected per replying sender's phone. Messages are taken in timestamp order with
+// ties in document order, like `--sort-messages`. A message with the same timestamp as
+// the one before it isn't counted as a reply: identical timestamps come from bulk
+// sends and exports with whole-second times, not from instant answers.
+fn reply_latencies(messages: &[Message]) -> HashMap<&str, Vec<f64>> {
+    let mut regular: Vec<&Message> = messages
+        .iter()
+        .filter(|m| m.kind == MessageKind::Regular)
+        .collect();
+    regular.sort_by_key(|m| m.timestamp);
+    let mut latencies: HashMap<&str, Vec<f64>> = HashMap::new();
+    for pair in regular.windows(2) {
+        let (previous, reply) = (pair[0], pair[1]);
+        if reply.from.phone != previous.from.phone && reply.timestamp > previous.timestamp {
+            let gap = (reply.timestamp - previous.timestamp).num_milliseconds() as f64 / 1000.0;
+            latencies.entry(reply.from.phone.as_str()).or_default().push(gap);
         }
     }
-
-    Ok(())
+    latencies
 }
 
-fn parse_file(filename: &Path) -> Result<Thread> {
-    let content = fs::read_to_string(filename).context("Failed to read file")?;
-    let document = Html::parse_document(&content);
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
 
-    // Initialize selectors
-    let message_selector = Selector::parse(MESSAGE_SELECTOR).unwrap();
-    let dt_selector = Selector::parse(DATETIME_SELECTOR).unwrap();
-    let sender_selector = Selector::parse(SENDER_SELECTOR).unwrap();
-    let q_selector = Selector::parse(CONTENT_SELECTOR).unwrap();
+// Apply `f` to the thread's participant list and to every participant copy held by
+// its messages, keeping them consistent
+fn for_each_participant_mut(
+    participants: &mut [Participant],
+    messages: &mut [Message],
+    mut f: impl FnMut(&mut Participant)
+) {
+    participants.iter_mut().for_each(&mut f);
+    for message in messages.iter_mut() {
+        f(&mut message.from);
+        message.to.iter_mut().for_each(&mut f);
+    }
+}
 
-    let mut participants = HashSet::new();
-    let mut me_participant = None;
+// Text of a `<q>` element with each `<br>` as a line break, the way Takeout encodes
+// multi-line messages. Trailing `<br>`s, which Takeout often appends, are dropped.
+fn content_text(element: &ElementRef) -> String {
+    let mut text = String::new();
+    let mut pending_breaks = 0;
+    for node in element.descendants() {
+        match node.value() {
+            scraper::Node::Text(fragment) => {
+                text.extend(std::iter::repeat_n('\n', pending_breaks));
+                pending_breaks = 0;
+                text.push_str(fragment);
+            }
+            scraper::Node::Element(el) if el.name() == "br" => pending_breaks += 1,
+            _ => {}
+        }
+    }
+    text
+}
 
-    // First pass: Collect all participants
-    for message_element in document.select(&message_selector) {
-        let sender_element = message_element.select(&sender_selector).next().unwrap();
-        let phone_number = sender_element
-            .select(&Selector::parse("a.tel").unwrap())
-            .next()
-            .and_then(|el| el.value().attr("href"))
-            .and_then(|href| href.strip_prefix("tel:"))
-            .unwrap_or("Unknown");
+// Trim, collapse every whitespace run to one space and normalize to NFC for `--normalize-content`
+fn normalize_content(content: &str) -> String {
+    content.split_whitespace().collect::<Vec<_>>().join(" ").nfc().collect()
+}
 
-        let name = sender_element
-            .select(&Selector::parse("span.fn, abbr.fn").unwrap())
-            .next()
-            .and_then(|el| el.text().next())
-            .unwrap_or("");
+// Decide from markup whether a sender element is the account owner. Exports render
+// the owner's name as `abbr.fn` and everyone else's as `span.fn`; an explicit self
+// class on the sender also counts. Returns `None` when the markup is ambiguous, in
+// which case callers fall back to the "Me" name heuristic.
+fn sender_is_self(sender_element: &ElementRef) -> Option<bool> {
+    if sender_element.value().classes().any(|c| SELF_SENDER_CLASSES.contains(&c)) {
+        return Some(true);
+    }
+    let name_element = sender_element.select(&selectors::get().self_name).next()?;
+    match name_element.value().name() {
+        "abbr" => Some(true),
+        "span" => Some(false),
+        _ => None,
+    }
+}
 
-        let participant = Participant {
-            name: name.to_string(),
-            phone: phone_number.to_string(),
-        };
+// Media quoted from an earlier message belongs to that message and is skipped
+fn parse_attachments(
+    message_element: &ElementRef,
+    attachment_selector: &Selector,
+    reply_selector: &Selector,
+    sender_selector: &Selector
+) -> Vec<Attachment> {
+    message_element
+        .select(attachment_selector)
+        // A photo in the sender markup is the contact's avatar, not an attachment
+        .filter(|el| !in_reply(el, reply_selector) && !within(el, sender_selector))
+        .filter_map(|el| {
+            let element = el.value();
+            let classes: Vec<&str> = element.classes().collect();
+            let kind = match element.name() {
+                "img" => AttachmentKind::Image,
+                "audio" => AttachmentKind::Audio,
+                "video" => AttachmentKind::Video,
+                _ if classes.contains(&"image") => AttachmentKind::Image,
+                _ if classes.contains(&"video") => AttachmentKind::Video,
+                _ if classes.contains(&"audio") => AttachmentKind::Audio,
+                _ => AttachmentKind::Contact,
+            };
+            let src = element.attr("src").or_else(|| element.attr("href"))?;
+            Some(Attachment {
+                kind,
+                src: src.to_string(),
+                extracted_path: None,
+            })
+        })
+        .collect()
+}
 
-        if name == "Me" {
-            me_participant = Some(participant.clone());
+// Build a participant from the name and phone in the markup, cleaning up the name
+// with `--clean-names`
+fn participant(name: &str, phone: &str, options: &ParseOptions) -> Participant {
+    let mut participant = if options.clean_names {
+        let cleaned = clean_name(name, options.strip_name_annotations);
+        let mut participant = Participant::new(&cleaned, phone);
+        if cleaned != name {
+            participant.raw_name = Some(name.to_string());
         }
-
-        participants.insert(participant);
+        participant
+    } else {
+        Participant::new(name, phone)
+    };
+    if let Some(placeholder) = options.unknown_name.as_ref().filter(|_| participant.name.trim().is_empty()) {
+        participant.name = placeholder.clone();
     }
+    participant
+}
 
-    let participants: Vec<Participant> = participants.into_iter().collect();
-    let me_participant = me_participant.unwrap_or_else(|| {
-        Participant {
-            name: "Me".to_string(),
-            phone: "Unknown".to_string(),
+// Trim and collapse whitespace; with `strip_annotations` also remove trailing
+// parenthesized suffixes, e.g. "John  Smith (Mobile) " becomes "John Smith". A name that
+// is nothing but an annotation is left alone rather than emptied.
+fn clean_name(name: &str, strip_annotations: bool) -> String {
+    let mut cleaned = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if strip_annotations {
+        while let Some(open) = cleaned.strip_suffix(')').and_then(|rest| rest.rfind('(')) {
+            let stripped = cleaned[..open].trim_end();
+            if stripped.is_empty() {
+                break;
+            }
+            cleaned = stripped.to_string();
         }
-    });
+    }
+    cleaned
+}
 
-    // Second pass: Parse messages
-    let messages = document
-        .select(&message_selector)
-        .map(|message_element| {
-            let timestamp = message_element
-                .select(&dt_selector)
-                .next()
-                .and_then(|el| el.value().attr("title"))
-                .and_then(|date_str| DateTime::parse_from_str(date_str, DATETIME_FORMAT).ok())
-                .map(|dt| dt.with_timezone(&Utc))
-                .unwrap_or_else(|| DateTime::from_timestamp(0, 0).expect("Invalid timestamp"));
+// Language of a message's text, or `None` when whatlang isn't confident
+fn detect_language(content: &str) -> Option<String> {
+    whatlang::detect(content)
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang().code().to_string())
+}
 
-            let sender_element = message_element.select(&sender_selector).next().unwrap();
-            let phone_number = sender_element
-                .select(&Selector::parse("a.tel").unwrap())
-                .next()
-                .and_then(|el| el.value().attr("href"))
-                .and_then(|href| href.strip_prefix("tel:"))
-                .unwrap_or("Unknown");
+// A filtered-out thread as kept by `--keep-empty`: same conversation and participants,
+// but no messages and all per-message statistics reset
+fn empty_thread(mut thread: Thread) -> Thread {
+    thread.messages.clear();
+    thread.message_count = 0;
+    for participant in &mut thread.participants {
+        participant.message_count = 0;
+        participant.first_seen = None;
+        participant.last_seen = None;
+    }
+    thread.participant_stats = thread_participant_stats(&thread.participants, &thread.messages);
+    thread
+}
 
-            let from = participants
-                .iter()
-                .find(|p| p.phone == phone_number)
-                .unwrap_or(&me_participant)
-                .clone();
+// The first `max` grapheme clusters of `text` and a `…`, so an emoji or a letter with
+// combining marks is never split; text that fits is returned as it is
+fn truncate_content(text: String, max: usize) -> (String, bool) {
+    match text.grapheme_indices(true).nth(max) {
+        Some((end, _)) => (format!("{}…", &text[..end]), true),
+        None => (text, false),
+    }
+}
 
-            let to = if from == me_participant {
-                participants
-                    .iter()
-                    .filter(|&p| p != &me_participant)
-                    .cloned()
-                    .collect()
-            } else {
-                vec![me_participant.clone()]
-            };
+// Messages that count towards `message_count`: everything but reactions and notices
+fn counted_messages(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .filter(|m| m.kind == MessageKind::Regular)
+        .count()
+}
 
-            let content = message_element
-                .select(&q_selector)
-                .next()
-                .map(|el| el.text().collect::<String>())
-                .unwrap_or_default();
+// Classify a `.message` by its markup classes or the notice patterns, falling back to
+// recognizing tapback text
+fn message_kind(
+    message_element: &ElementRef,
+    sender: &str,
+    content: Option<&str>,
+    notices: &notices::NoticePatterns
+) -> MessageKind {
+    let mut classes = message_element.value().classes();
+    if classes.any(|c| SYSTEM_MESSAGE_CLASSES.contains(&c)) || notices.is_notice(sender, content) {
+        return MessageKind::System;
+    }
+    if message_element.value().has_class(REACTION_MESSAGE_CLASS, scraper::CaseSensitivity::CaseSensitive) ||
+        content.and_then(tapback_target).is_some()
+    {
+        return MessageKind::Reaction;
+    }
+    MessageKind::Regular
+}
 
-            Message {
-                from,
-                to,
-                timestamp,
-                content,
-            }
-        })
-        .collect::<Vec<_>>();
+// The quoted text of a tapback fallback such as `Loved “Dinner at 8?”` or
+// `Reacted 👍 to "Dinner at 8?"`, or `None` when `content` isn't one
+fn tapback_target(content: &str) -> Option<&str> {
+    let content = content.trim();
+    let quoted = TAPBACK_VERBS.iter()
+        .find_map(|verb| content.strip_prefix(verb)?.strip_prefix(' '))
+        .or_else(|| {
+            let rest = content.strip_prefix("Reacted ")?;
+            Some(&rest[rest.find(" to ")? + 4..])
+        })?;
+    quoted
+        .strip_prefix('“')
+        .and_then(|q| q.strip_suffix('”'))
+        .or_else(|| quoted.strip_prefix('"').and_then(|q| q.strip_suffix('"')))
+}
 
-    let labels = parse_labels(&document);
+// Whether an element sits inside the quoted-reply block of its message
+fn in_reply(element: &ElementRef, reply_selector: &Selector) -> bool {
+    within(element, reply_selector)
+}
 
-    Ok(Thread {
-        messages: messages.clone(),
-        participants,
-        labels,
-        message_count: messages.len(),
-    })
+fn within(element: &ElementRef, selector: &Selector) -> bool {
+    element
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .any(|ancestor| selector.matches(&ancestor))
 }
 
+// Labels from every `.tags` element (`Labels: Text, Inbox`) in document order, without
+// duplicates. A tags element without the heading is read as a bare list.
 fn parse_labels(document: &Html) -> Vec<String> {
-    let tags_selector = Selector::parse(TAGS_SELECTOR).unwrap();
-    document
-        .select(&tags_selector)
-        .next()
-        .map(|tags_element| {
-            tags_element
-                .text()
-                .collect::<String>()
-                .split(':')
-                .nth(1)
-                .unwrap_or("")
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .filter(|s| !s.is_empty())
-                .collect()
-        })
-        .unwrap_or_default()
+    let mut labels: Vec<String> = Vec::new();
+    for tags_element in document.select(&selectors::get().tags) {
+        let text = tags_element.text().collect::<String>();
+        // Only the first colon ends the "Labels:" heading; later ones belong to a label
+        let list = text.split_once(':').map_or(text.as_str(), |(_, rest)| rest);
+        for label in list.split(',').map(str::trim) {
+            if !label.is_empty() && !labels.iter().any(|l| l == label) {
+                labels.push(label.to_string());
+            }
+        }
+    }
+    labels
 }
 
 fn print_statistics(stats: &RunStatistics) {
@@ -415,6 +4453,38 @@ fn print_statistics(stats: &RunStatistics) {
     println!("Duration: {:?}", stats.duration);
     println!("Files processed: {}", stats.files_processed);
     println!("Messages extracted: {}", stats.messages_extracted);
-    println!("Unique participants: {}", stats.unique_participants);
+    if stats.participants_estimated {
+        println!("Unique participants: ~{} (estimated)", stats.unique_participants);
+    } else {
+        println!("Unique participants: {}", stats.unique_participants);
+    }
     println!("Average messages per file: {:.2}", stats.avg_messages_per_file);
+    println!("Misattributed senders: {}", stats.misattributed_senders);
+    println!("Files with message count mismatches: {}", stats.message_count_mismatches);
+    println!("Repeated messages dropped: {}", stats.repeated_messages);
+    if stats.dropped_undated {
+        println!("Messages without a timestamp (dropped): {}", stats.missing_timestamps);
+    } else {
+        println!("Messages without a timestamp: {}", stats.missing_timestamps);
+    }
+    println!("Messages with an unparseable timestamp: {}", stats.invalid_timestamps);
+    println!("Duplicate files skipped: {}", stats.duplicate_files);
+    if let Some((hits, misses)) = stats.cache_counts {
+        println!("Cache hits: {}, misses: {}", hits, misses);
+    }
+    if let Some(participant_types) = &stats.participant_types {
+        println!("Participants by type:");
+        for (kind, count) in participant_types {
+            println!("  {}: {}", kind.as_str(), count);
+        }
+    }
+    if let Some(truncated) = stats.truncated_messages {
+        println!("Messages truncated: {}", truncated);
+    }
+    if let Some(keyword_matches) = &stats.keyword_matches {
+        println!("Messages flagged by keyword category:");
+        for (category, count) in keyword_matches {
+            println!("  {}: {}", category, count);
+        }
+    }
 }
@@ -0,0 +1,122 @@
+use anyhow::{ Context, Result };
+use serde::Serialize;
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::path::Path;
+use crate::timestamp::{ self, TimestampValue };
+use crate::{ is_group_thread, Thread };
+use log::info;
+
+// Table of contents for a run with one row per thread, written as CSV when the path
+// ends in `.csv` and as a JSON array otherwise. Rows are written as threads go by,
+// so the manifest never holds more than one thread.
+pub struct Manifest {
+    writer: BufWriter<File>,
+    format: ManifestFormat,
+    rows: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ManifestFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Serialize)]
+struct ManifestRow<'a> {
+    thread_id: &'a str,
+    source_path: &'a str,
+    participants: Vec<&'a str>,
+    message_count: usize,
+    first_timestamp: Option<TimestampValue>,
+    last_timestamp: Option<TimestampValue>,
+    labels: &'a [String],
+    is_group: bool,
+}
+
+impl<'a> ManifestRow<'a> {
+    fn new(thread: &'a Thread) -> Self {
+        let first = thread.messages.iter().map(|m| m.timestamp).min();
+        let last = thread.messages.iter().map(|m| m.timestamp).max();
+        ManifestRow {
+            thread_id: &thread.thread_id,
+            source_path: &thread.source_path,
+            participants: thread.participants
+                .iter()
+                .map(|p| if p.name.is_empty() { p.phone.as_str() } else { p.name.as_str() })
+                .collect(),
+            message_count: thread.message_count,
+            first_timestamp: first.as_ref().map(timestamp::value),
+            last_timestamp: last.as_ref().map(timestamp::value),
+            labels: &thread.labels,
+            is_group: is_group_thread(thread),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn create(path: &Path) -> Result<Self> {
+        let format = match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("csv") => ManifestFormat::Csv,
+            _ => ManifestFormat::Json,
+        };
+        let file = File::create(path).with_context(||
+            format!("Failed to create manifest file: {:?}", path)
+        )?;
+        let mut writer = BufWriter::new(file);
+        match format {
+            ManifestFormat::Csv => {
+                writeln!(
+                    writer,
+                    "thread_id,source_path,participants,message_count,first_timestamp,last_timestamp,labels,is_group"
+                )?;
+            }
+            ManifestFormat::Json => write!(writer, "[")?,
+        }
+        info!("Writing manifest to {:?}", path);
+        Ok(Manifest { writer, format, rows: 0 })
+    }
+
+    pub fn record(&mut self, thread: &Thread) -> Result<()> {
+        let row = ManifestRow::new(thread);
+        match self.format {
+            ManifestFormat::Csv => {
+                let fields = [
+                    row.thread_id.to_string(),
+                    csv_field(row.source_path),
+                    csv_field(&row.participants.join("; ")),
+                    row.message_count.to_string(),
+                    row.first_timestamp.map(|t| t.to_string()).unwrap_or_default(),
+                    row.last_timestamp.map(|t| t.to_string()).unwrap_or_default(),
+                    csv_field(&row.labels.join("; ")),
+                    row.is_group.to_string(),
+                ];
+                writeln!(self.writer, "{}", fields.join(","))?;
+            }
+            ManifestFormat::Json => {
+                let separator = if self.rows == 0 { "\n" } else { ",\n" };
+                write!(self.writer, "{}{}", separator, serde_json::to_string(&row)?)?;
+            }
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        if self.format == ManifestFormat::Json {
+            writeln!(self.writer, "\n]")?;
+        }
+        self.writer.flush().context("Failed to flush manifest")?;
+        info!("Manifest complete. Total threads listed: {}", self.rows);
+        Ok(())
+    }
+}
+
+// Quote a CSV field when it contains a separator, quote or line break
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{ Context, Result };
+use chrono_tz::Tz;
+use std::io::Write;
+use crate::takeout_writer::other_participants;
+use crate::transcript::transcript_lines;
+use crate::writer::ThreadWriter;
+use crate::Thread;
+use log::info;
+
+// A readable chat log in one Markdown document: a section per thread, with its messages
+// under a heading per day in `tz` as `HH:MM **Sender**: text` list items. Threads are
+// written as they arrive.
+pub struct MarkdownWriter {
+    out: Box<dyn Write>,
+    tz: Tz,
+    threads: usize,
+}
+
+impl MarkdownWriter {
+    pub fn create(out: Box<dyn Write>, tz: Tz) -> Self {
+        MarkdownWriter { out, tz, threads: 0 }
+    }
+}
+
+impl ThreadWriter for MarkdownWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        if self.threads > 0 {
+            writeln!(self.out)?;
+        }
+        writeln!(self.out, "## {}\n", escape_markdown(&section_title(&thread)))?;
+        writeln!(
+            self.out,
+            "{} {} from `{}`",
+            thread.messages.len(),
+            if thread.messages.len() == 1 { "message" } else { "messages" },
+            thread.source_path.replace('`', "'")
+        )?;
+        let mut day = None;
+        for message in &thread.messages {
+            let local = message.timestamp.with_timezone(&self.tz);
+            let date = local.date_naive();
+            if day != Some(date) {
+                writeln!(self.out, "\n### {}\n", local.format("%A, %B %-d, %Y"))?;
+                day = Some(date);
+            }
+            let sender = if message.from.name.trim().is_empty() { &message.from.phone } else { &message.from.name };
+            // Continuation lines stay inside the list item, each after a hard line break
+            let text: Vec<String> = transcript_lines(message)
+                .iter()
+                .map(|line| escape_markdown(line))
+                .collect();
+            writeln!(
+                self.out,
+                "- {} **{}**: {}",
+                local.format("%H:%M"),
+                escape_markdown(sender),
+                text.join("\\\n  ")
+            )?;
+        }
+        self.threads += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush().context("Failed to flush output")?;
+        info!("Markdown output complete. Total threads written: {}", self.threads);
+        Ok(())
+    }
+}
+
+// Who the thread is with, or "Note to self"
+fn section_title(thread: &Thread) -> String {
+    let names: Vec<&str> = other_participants(thread)
+        .into_iter()
+        .map(|p| if p.name.trim().is_empty() { p.phone.as_str() } else { p.name.as_str() })
+        .collect();
+    if names.is_empty() {
+        "Note to self".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+// Backslash-escape the characters Markdown would otherwise read as formatting, so
+// message text is shown as it was sent
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '~') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
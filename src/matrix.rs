@@ -0,0 +1,94 @@
+use anyhow::{ Context, Result };
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::path::Path;
+use crate::manifest::csv_field;
+use crate::{ phone_digits, MessageKind, Participant, Thread };
+
+// Who-texts-whom counts for `--matrix`, aggregated over every thread written in the run.
+// Written as a CSV adjacency matrix: row = sender, column = recipient, cell = number of
+// messages, with participants merged by phone digits as in the roster.
+pub struct AdjacencyMatrix {
+    out: BufWriter<File>,
+    /// Row/column index of each participant, by phone key
+    index: HashMap<String, usize>,
+    /// Header label parts for each index: name and phone
+    participants: Vec<(String, String)>,
+    counts: HashMap<(usize, usize), usize>,
+}
+
+impl AdjacencyMatrix {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(||
+            format!("Failed to create matrix file: {:?}", path)
+        )?;
+        Ok(AdjacencyMatrix {
+            out: BufWriter::new(file),
+            index: HashMap::new(),
+            participants: Vec::new(),
+            counts: HashMap::new(),
+        })
+    }
+
+    // Count the thread's regular messages; reactions and notices are left out, as in
+    // `message_count`
+    pub fn record(&mut self, thread: &Thread) {
+        for message in thread.messages.iter().filter(|m| m.kind == MessageKind::Regular) {
+            let from = self.participant_index(&message.from);
+            for recipient in &message.to {
+                let to = self.participant_index(recipient);
+                *self.counts.entry((from, to)).or_default() += 1;
+            }
+        }
+    }
+
+    fn participant_index(&mut self, participant: &Participant) -> usize {
+        let digits = phone_digits(&participant.phone);
+        let key = if digits.is_empty() { participant.phone.clone() } else { digits };
+        let index = *self.index.entry(key).or_insert_with(|| {
+            self.participants.push((String::new(), participant.phone.clone()));
+            self.participants.len() - 1
+        });
+        let name = &mut self.participants[index].0;
+        if name.is_empty() && !participant.name.trim().is_empty() {
+            *name = participant.name.clone();
+        }
+        index
+    }
+
+    // Write the matrix with participants sorted by name (case-insensitive, nameless
+    // contacts last), the same order for rows and columns
+    pub fn finish(mut self) -> Result<()> {
+        let mut order: Vec<usize> = (0..self.participants.len()).collect();
+        order.sort_by_key(|&i| {
+            let (name, phone) = &self.participants[i];
+            (name.is_empty(), name.trim().to_lowercase(), phone.clone())
+        });
+        let labels: Vec<String> = order
+            .iter()
+            .map(|&i| csv_field(&header_label(&self.participants[i])))
+            .collect();
+
+        writeln!(self.out, "from \\ to,{}", labels.join(","))?;
+        for (&row, label) in order.iter().zip(&labels) {
+            let cells: Vec<String> = order
+                .iter()
+                .map(|&column| self.counts.get(&(row, column)).copied().unwrap_or(0).to_string())
+                .collect();
+            writeln!(self.out, "{},{}", label, cells.join(","))?;
+        }
+        self.out.flush().context("Failed to flush matrix")
+    }
+}
+
+// `Name (+16502074150)`, or just the phone for a nameless participant
+fn header_label((name, phone): &(String, String)) -> String {
+    if name.is_empty() {
+        phone.clone()
+    } else if phone.is_empty() {
+        name.clone()
+    } else {
+        format!("{} ({})", name, phone)
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::{ Context, Result };
+use std::fs;
+use std::path::{ Path, PathBuf };
+use crate::Thread;
+use log::{ info, warn };
+
+// Locate an attachment on disk relative to the HTML file that references it.
+// Takeout often omits the media file's extension from `src`, so when the exact path
+// doesn't exist a file in the same directory with a matching stem is accepted.
+pub fn resolve_media(html_path: &Path, src: &str) -> Option<PathBuf> {
+    let dir = html_path.parent().unwrap_or_else(|| Path::new("."));
+    let candidate = dir.join(src);
+    if candidate.is_file() {
+        return Some(candidate);
+    }
+
+    let wanted = candidate.file_name()?.to_os_string();
+    let search_dir = candidate.parent()?;
+    let mut matches: Vec<PathBuf> = fs::read_dir(search_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.file_stem() == Some(wanted.as_os_str()))
+        .collect();
+    matches.sort();
+    matches.into_iter().next()
+}
+
+// Copy every attachment of `thread` into `target_dir` under a deterministic name
+// (`<message id prefix>-<attachment index>.<ext>`) and record the new location.
+// Media that can't be found is logged and skipped.
+pub fn extract_media(thread: &mut Thread, target_dir: &Path) -> Result<()> {
+    fs::create_dir_all(target_dir).with_context(||
+        format!("Failed to create media directory: {:?}", target_dir)
+    )?;
+    let html_path = PathBuf::from(&thread.source_path);
+    let mut copied = 0;
+
+    for message in &mut thread.messages {
+        for (index, attachment) in message.body.media_mut().iter_mut().enumerate() {
+            let Some(source) = resolve_media(&html_path, &attachment.src) else {
+                warn!("Media file {:?} referenced by {:?} not found", attachment.src, html_path);
+                continue;
+            };
+            let mut file_name = format!("{}-{}", &message.id[..16], index);
+            if let Some(extension) = source.extension() {
+                file_name.push('.');
+                file_name.push_str(&extension.to_string_lossy());
+            }
+            let destination = target_dir.join(file_name);
+            fs::copy(&source, &destination).with_context(||
+                format!("Failed to copy media {:?} to {:?}", source, destination)
+            )?;
+            attachment.extracted_path = Some(destination.to_string_lossy().to_string());
+            copied += 1;
+        }
+    }
+
+    if copied > 0 {
+        info!("Extracted {} media files from {:?}", copied, html_path);
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use serde::{ Deserialize, Serialize };
+use std::collections::{ HashMap, HashSet };
+use crate::per_contact::contact_key;
+use crate::takeout_writer::other_participants;
+use crate::{ apply_participant_stats, for_each_participant_mut, thread_participant_stats, Participant, Thread };
+use log::info;
+
+// One of the name/phone combinations a merged participant was seen under
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParticipantAlias {
+    pub name: String,
+    pub phone: String,
+}
+
+// `--merge-similar-participants`: contacts (phone digits, as in `--participants-only`)
+// whose names are alike, such as `Mom`, `Mom ❤️` and `mom`, clustered from a first pass
+// over the whole input. Every member of a cluster is replaced by the member with the
+// most messages, listing all of them in `merged_from`. Names are compared by
+// Jaro-Winkler similarity after lowercasing and dropping everything but letters, digits
+// and spaces; clusters are transitive, so a chain of similar names ends up as one.
+#[derive(Debug, Default)]
+pub struct ParticipantMerger {
+    threshold: f64,
+    contacts: HashMap<String, Contact>,
+    /// Canonical member and aliases of each contact that was merged, by contact key
+    merged: HashMap<String, (ParticipantAlias, Vec<ParticipantAlias>)>,
+}
+
+#[derive(Debug)]
+struct Contact {
+    name: String,
+    phone: String,
+    message_count: usize,
+}
+
+impl ParticipantMerger {
+    pub fn new(threshold: f64) -> Self {
+        ParticipantMerger {
+            threshold,
+            ..Default::default()
+        }
+    }
+
+    // Collect the contacts of a thread from the first pass; yourself and nameless
+    // contacts are never merged
+    pub fn add(&mut self, thread: &Thread) {
+        for participant in other_participants(thread) {
+            if participant.name.trim().is_empty() {
+                continue;
+            }
+            let contact = self.contacts.entry(contact_key(participant)).or_insert_with(|| Contact {
+                name: participant.name.clone(),
+                phone: participant.phone.clone(),
+                message_count: 0,
+            });
+            contact.message_count += participant.message_count;
+        }
+    }
+
+    // Form the clusters once every thread was added, returning them for the report as
+    // the canonical member followed by the others
+    pub fn cluster(&mut self) -> Vec<Vec<ParticipantAlias>> {
+        let mut keys: Vec<&String> = self.contacts.keys().collect();
+        keys.sort();
+        let names: Vec<String> = keys
+            .iter()
+            .map(|key| comparable_name(&self.contacts[*key].name))
+            .collect();
+
+        // Union-find over contact indexes
+        let mut parent: Vec<usize> = (0..keys.len()).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for i in 0..keys.len() {
+            for j in i + 1..keys.len() {
+                if !names[i].is_empty() && strsim::jaro_winkler(&names[i], &names[j]) >= self.threshold {
+                    let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                    parent[b] = a;
+                }
+            }
+        }
+        let mut clusters: HashMap<usize, Vec<usize>> = HashMap::new();
+        for i in 0..keys.len() {
+            clusters.entry(root(&mut parent, i)).or_default().push(i);
+        }
+
+        let mut report = Vec::new();
+        for mut members in clusters.into_values().filter(|m| m.len() > 1) {
+            // Most messages first, then by name so the choice is stable between runs
+            members.sort_by(|&a, &b| {
+                let (a, b) = (&self.contacts[keys[a]], &self.contacts[keys[b]]);
+                b.message_count.cmp(&a.message_count).then_with(|| (&a.name, &a.phone).cmp(&(&b.name, &b.phone)))
+            });
+            let aliases: Vec<ParticipantAlias> = members
+                .iter()
+                .map(|&i| {
+                    let contact = &self.contacts[keys[i]];
+                    ParticipantAlias { name: contact.name.clone(), phone: contact.phone.clone() }
+                })
+                .collect();
+            for &i in &members {
+                self.merged.insert(keys[i].clone(), (aliases[0].clone(), aliases.clone()));
+            }
+            report.push(aliases);
+        }
+        report.sort_by(|a, b| a[0].name.cmp(&b[0].name));
+        info!("Merged {} contacts into {} participants", self.merged.len(), report.len());
+        report
+    }
+
+    // Replace merged contacts in a thread by their canonical member. Members of one
+    // cluster in the same thread become a single participant, with rollups and roles
+    // recomputed; thread and message ids are left as parsed.
+    pub fn apply(&self, thread: &mut Thread) {
+        let mut changed = false;
+        for_each_participant_mut(&mut thread.participants, &mut thread.messages, |participant| {
+            if let Some((canonical, aliases)) = self.merged.get(&contact_key(participant)) {
+                participant.name = canonical.name.clone();
+                participant.phone = canonical.phone.clone();
+                participant.merged_from = aliases.clone();
+                changed = true;
+            }
+        });
+        if !changed {
+            return;
+        }
+        let mut seen = HashSet::new();
+        thread.participants.retain(|p| seen.insert(p.clone()));
+        for_each_participant_mut(&mut thread.participants, &mut thread.messages, reset_rollups);
+        apply_participant_stats(&mut thread.participants, &mut thread.messages);
+        thread.participant_stats = thread_participant_stats(&thread.participants, &thread.messages);
+    }
+}
+
+fn reset_rollups(participant: &mut Participant) {
+    participant.message_count = 0;
+    participant.first_seen = None;
+    participant.last_seen = None;
+}
+
+// Lowercase letters, digits and single spaces, so emoji and punctuation don't count
+fn comparable_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
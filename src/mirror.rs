@@ -0,0 +1,162 @@
+use anyhow::{ Context, Result };
+use std::collections::{ HashMap, HashSet };
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ BufWriter, Write };
+use std::path::{ Component, Path, PathBuf };
+use crate::writer::to_json;
+use crate::{ Message, OutputOptions, Thread };
+use log::info;
+
+// `--mirror-output`: an NDJSON file of the messages of each source file, at the source's
+// path relative to the input it was found under with the extension replaced, e.g.
+// `Takeout/Voice/Calls/Ann - Text - ….html` becomes `<DIR>/Calls/Ann - Text - ….ndjson`
+// for the input `Takeout/Voice`. A source given as a file, or whose path isn't under any
+// input (stdin, redacted paths), goes to the top of the directory by its file name. Each
+// line is a message with its thread's id and source path. A file is written as
+// `.ndjson.partial` while its threads arrive and renamed once the next source starts,
+// so a complete `.ndjson` is never half written. Sources that would land on the same
+// file get `-2`, `-3`, … appended to the later ones' names.
+pub struct MirrorFiles {
+    dir: PathBuf,
+    roots: Vec<PathBuf>,
+    options: OutputOptions,
+    /// Output file of each source seen so far
+    targets: HashMap<String, PathBuf>,
+    /// Output files taken, lowercased for case-insensitive file systems
+    taken: HashSet<String>,
+    /// The source being written, its output file and the open spool
+    current: Option<(String, PathBuf, BufWriter<File>)>,
+    messages: usize,
+}
+
+#[derive(serde::Serialize)]
+struct SourceMessage<'a> {
+    thread_id: &'a str,
+    source_path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_index: Option<usize>,
+    #[serde(flatten)]
+    message: &'a Message,
+}
+
+impl MirrorFiles {
+    pub fn create(dir: &Path, roots: &[PathBuf], options: OutputOptions) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create mirror directory: {:?}", dir))?;
+        // Every line is one message, whatever `--compact` says
+        let options = OutputOptions { compact_json: true, ..options };
+        Ok(MirrorFiles {
+            dir: dir.to_path_buf(),
+            roots: roots.iter().filter(|root| root.is_dir()).cloned().collect(),
+            options,
+            targets: HashMap::new(),
+            taken: HashSet::new(),
+            current: None,
+            messages: 0,
+        })
+    }
+
+    pub fn record(&mut self, thread: &Thread) -> Result<()> {
+        let is_current = self.current.as_ref().is_some_and(|(source, _, _)| *source == thread.source_path);
+        if !is_current {
+            self.complete_current()?;
+            self.current = Some(self.open(&thread.source_path)?);
+        }
+        let (_, target, out) = self.current.as_mut().expect("A mirror file is open");
+        for message in &thread.messages {
+            let entry = SourceMessage {
+                thread_id: &thread.thread_id,
+                source_path: &thread.source_path,
+                thread_index: thread.thread_index,
+                message,
+            };
+            writeln!(out, "{}", to_json(&entry, &self.options)?).with_context(||
+                format!("Failed to write {:?}", target)
+            )?;
+            self.messages += 1;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.complete_current()?;
+        info!("Mirror output complete. Total messages written: {}", self.messages);
+        eprintln!("Wrote {} messages into {} mirror files in {:?}", self.messages, self.targets.len(), self.dir);
+        Ok(())
+    }
+
+    // The spool of a new source, or the finished file of one seen earlier in the run
+    // (its threads didn't all arrive together) opened to append to
+    fn open(&mut self, source: &str) -> Result<(String, PathBuf, BufWriter<File>)> {
+        let (target, file) = match self.targets.get(source) {
+            Some(target) => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .open(target)
+                    .with_context(|| format!("Failed to open {:?} for appending", target))?;
+                (target.clone(), file)
+            }
+            None => {
+                let target = self.target_path(source);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+                }
+                let spool = spool_path(&target);
+                let file = File::create(&spool).with_context(|| format!("Failed to create {:?}", spool))?;
+                self.targets.insert(source.to_string(), target.clone());
+                (target, file)
+            }
+        };
+        Ok((source.to_string(), target, BufWriter::new(file)))
+    }
+
+    // Flush the source being written and move its spool into place
+    fn complete_current(&mut self) -> Result<()> {
+        let Some((_, target, mut out)) = self.current.take() else {
+            return Ok(());
+        };
+        out.flush().with_context(|| format!("Failed to write {:?}", target))?;
+        drop(out);
+        let spool = spool_path(&target);
+        if spool.exists() {
+            fs::rename(&spool, &target).with_context(|| format!("Failed to rename {:?}", spool))?;
+        }
+        Ok(())
+    }
+
+    // The source's path under the input it was found in, with `.ndjson` for its
+    // extension and numbered if another source got that name first
+    fn target_path(&mut self, source: &str) -> PathBuf {
+        let source = Path::new(source);
+        // Only plain components, so a path read back from JSON input can't escape `dir`
+        let relative = self.roots
+            .iter()
+            .find_map(|root| source.strip_prefix(root).ok())
+            .filter(|relative| {
+                relative.components().next().is_some() &&
+                    relative.components().all(|c| matches!(c, Component::Normal(_)))
+            })
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from(source.file_name().unwrap_or("source".as_ref())));
+        // Appended rather than set, as names like `Dr. Lee - Text - ….html` have more dots
+        let stem = relative.with_extension("").into_os_string();
+        let mut number = 1;
+        loop {
+            let mut name = stem.clone();
+            if number > 1 {
+                name.push(format!("-{}", number));
+            }
+            name.push(".ndjson");
+            let name = PathBuf::from(name);
+            if self.taken.insert(name.to_string_lossy().to_lowercase()) {
+                return self.dir.join(name);
+            }
+            number += 1;
+        }
+    }
+}
+
+fn spool_path(target: &Path) -> PathBuf {
+    let mut spool = target.as_os_str().to_owned();
+    spool.push(".partial");
+    PathBuf::from(spool)
+}
@@ -0,0 +1,96 @@
+use anyhow::{ Context, Result };
+use regex::{ Regex, RegexBuilder };
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+// Names Google Voice's own notices are sent under
+const SENDER_PATTERNS: &[&str] = &[r"^google voice$", r"^voicemail$", r"^(carrier|network) (notice|message)s?$"];
+
+// Content of the notices Google Voice and carriers inject into conversations
+const CONTENT_PATTERNS: &[&str] = &[
+    r"^your voicemail transcript(ion)? is (ready|available)",
+    r"^you have (a )?new voicemail",
+    r"^(this|your) (message|text) (was|could) not be (delivered|sent)",
+    r"^message from your (carrier|wireless provider)",
+    r"^(free msg|carrier msg|carrier notice):",
+    r"^google voice:",
+];
+
+// Notices that arrive as ordinary messages ("Your voicemail transcript is ready",
+// carrier messages), recognized by their sender's name or their content and given the
+// `system` kind. Patterns are case-insensitive regexes; a `--system-patterns` file
+// replaces the built-in English ones, e.g. for exports in other languages.
+#[derive(Debug, Clone)]
+pub struct NoticePatterns {
+    senders: Vec<Regex>,
+    content: Vec<Regex>,
+}
+
+// The `--system-patterns` file (TOML); a key left out keeps its built-in patterns
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PatternFile {
+    /// Regexes for the display names of notice senders
+    senders: Option<Vec<String>>,
+    /// Regexes for notice content, matched against the trimmed message text
+    content: Option<Vec<String>>,
+}
+
+impl Default for NoticePatterns {
+    fn default() -> Self {
+        let builtin = |patterns: &[&str]| {
+            let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+            compile(&patterns).expect("Invalid built-in notice pattern")
+        };
+        NoticePatterns {
+            senders: builtin(SENDER_PATTERNS),
+            content: builtin(CONTENT_PATTERNS),
+        }
+    }
+}
+
+impl NoticePatterns {
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(NoticePatterns::default());
+        };
+        let content = fs::read_to_string(path).with_context(||
+            format!("Failed to read system patterns: {:?}", path)
+        )?;
+        let file: PatternFile = toml::from_str(&content).with_context(||
+            format!("Invalid system patterns: {:?}", path)
+        )?;
+        let defaults = NoticePatterns::default();
+        Ok(NoticePatterns {
+            senders: file.senders.as_deref().map(compile).transpose()?.unwrap_or(defaults.senders),
+            content: file.content.as_deref().map(compile).transpose()?.unwrap_or(defaults.content),
+        })
+    }
+
+    pub fn is_notice(&self, sender: &str, content: Option<&str>) -> bool {
+        self.is_notice_sender(sender) ||
+            content.is_some_and(|content| {
+                let content = content.trim();
+                self.content.iter().any(|p| p.is_match(content))
+            })
+    }
+
+    // Whether a display name is one notices are sent under
+    pub fn is_notice_sender(&self, name: &str) -> bool {
+        let name = name.trim();
+        self.senders.iter().any(|p| p.is_match(name))
+    }
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .with_context(|| format!("Invalid system pattern: {:?}", pattern))
+        })
+        .collect()
+}
@@ -0,0 +1,174 @@
+use anyhow::{ Context, Result };
+use arrow_array::builder::{ ListBuilder, StringBuilder, TimestampMillisecondBuilder, UInt64Builder };
+use arrow_array::{ ArrayRef, RecordBatch };
+use arrow_schema::{ DataType, Field, Schema, SchemaRef, TimeUnit };
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use crate::writer::ThreadWriter;
+use crate::{ Thread, PARQUET_ROW_GROUP_SIZE };
+use log::info;
+
+// Writes one row per message into a single Parquet file. Rows are buffered in Arrow
+// builders and flushed as a row group every `PARQUET_ROW_GROUP_SIZE` messages, so
+// memory stays bounded regardless of corpus size.
+pub struct ParquetWriter {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    rows: RowBuilders,
+    buffered: usize,
+    total: usize,
+}
+
+struct RowBuilders {
+    from_phone: StringBuilder,
+    from_name: StringBuilder,
+    to_phones: ListBuilder<StringBuilder>,
+    direction: StringBuilder,
+    timestamp: TimestampMillisecondBuilder,
+    content: StringBuilder,
+    thread_id: StringBuilder,
+    source_path: StringBuilder,
+    labels: ListBuilder<StringBuilder>,
+    thread_index: UInt64Builder,
+    message_index: UInt64Builder,
+}
+
+impl RowBuilders {
+    fn new() -> Self {
+        RowBuilders {
+            from_phone: StringBuilder::new(),
+            from_name: StringBuilder::new(),
+            to_phones: ListBuilder::new(StringBuilder::new()),
+            direction: StringBuilder::new(),
+            timestamp: TimestampMillisecondBuilder::new().with_timezone("UTC"),
+            content: StringBuilder::new(),
+            thread_id: StringBuilder::new(),
+            source_path: StringBuilder::new(),
+            labels: ListBuilder::new(StringBuilder::new()),
+            thread_index: UInt64Builder::new(),
+            message_index: UInt64Builder::new(),
+        }
+    }
+
+    // Drain the builders into columns, leaving them empty for the next row group
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.from_phone.finish()),
+            Arc::new(self.from_name.finish()),
+            Arc::new(self.to_phones.finish()),
+            Arc::new(self.direction.finish()),
+            Arc::new(self.timestamp.finish()),
+            Arc::new(self.content.finish()),
+            Arc::new(self.thread_id.finish()),
+            Arc::new(self.source_path.finish()),
+            Arc::new(self.labels.finish()),
+            Arc::new(self.thread_index.finish()),
+            Arc::new(self.message_index.finish())
+        ]
+    }
+}
+
+fn schema() -> SchemaRef {
+    let list_of_strings = DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)));
+    Arc::new(
+        Schema::new(
+            vec![
+                Field::new("from_phone", DataType::Utf8, false),
+                Field::new("from_name", DataType::Utf8, false),
+                Field::new("to_phones", list_of_strings.clone(), false),
+                Field::new("direction", DataType::Utf8, false),
+                Field::new(
+                    "timestamp",
+                    DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+                    false
+                ),
+                Field::new("content", DataType::Utf8, true),
+                Field::new("thread_id", DataType::Utf8, false),
+                Field::new("source_path", DataType::Utf8, false),
+                Field::new("labels", list_of_strings, false),
+                Field::new("thread_index", DataType::UInt64, true),
+                Field::new("message_index", DataType::UInt64, true)
+            ]
+        )
+    )
+}
+
+impl ParquetWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(||
+            format!("Failed to create Parquet file: {:?}", path)
+        )?;
+        let schema = schema();
+        let props = WriterProperties::builder()
+            .set_max_row_group_row_count(Some(PARQUET_ROW_GROUP_SIZE))
+            .build();
+        let writer = ArrowWriter::try_new(file, schema.clone(), Some(props)).context(
+            "Failed to create Parquet writer"
+        )?;
+        info!("Writing Parquet output to {:?}", path);
+        Ok(ParquetWriter {
+            writer,
+            schema,
+            rows: RowBuilders::new(),
+            buffered: 0,
+            total: 0,
+        })
+    }
+
+    fn flush_row_group(&mut self) -> Result<()> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+        let batch = RecordBatch::try_new(self.schema.clone(), self.rows.finish()).context(
+            "Failed to build Parquet record batch"
+        )?;
+        self.writer.write(&batch).context("Failed to write Parquet row group")?;
+        self.writer.flush().context("Failed to flush Parquet row group")?;
+        self.total += self.buffered;
+        self.buffered = 0;
+        Ok(())
+    }
+}
+
+impl ThreadWriter for ParquetWriter {
+    // Append every message of `thread`; rows carry the thread's id and source path
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        for m in &thread.messages {
+            let rows = &mut self.rows;
+            rows.from_phone.append_value(&m.from.phone);
+            rows.from_name.append_value(&m.from.name);
+            for to in &m.to {
+                rows.to_phones.values().append_value(&to.phone);
+            }
+            rows.to_phones.append(true);
+            rows.direction.append_value(m.direction.as_str());
+            rows.timestamp.append_value(m.timestamp.timestamp_millis());
+            rows.content.append_option(m.body.text());
+            rows.thread_id.append_value(&thread.thread_id);
+            rows.source_path.append_value(&thread.source_path);
+            for label in &thread.labels {
+                rows.labels.values().append_value(label);
+            }
+            rows.labels.append(true);
+            rows.thread_index.append_option(thread.thread_index.map(|index| index as u64));
+            rows.message_index.append_option(m.message_index.map(|index| index as u64));
+
+            self.buffered += 1;
+            if self.buffered >= PARQUET_ROW_GROUP_SIZE {
+                self.flush_row_group()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Flush any buffered rows and write the Parquet footer
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush_row_group()?;
+        self.writer.close().context("Failed to finalize Parquet file")?;
+        info!("Parquet output complete. Total messages written: {}", self.total);
+        Ok(())
+    }
+}
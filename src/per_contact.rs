@@ -0,0 +1,192 @@
+use anyhow::{ Context, Result };
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{ HashMap, HashSet };
+use std::fs::{ self, OpenOptions };
+use std::io::{ BufRead, BufReader, BufWriter, Write };
+use std::path::{ Path, PathBuf };
+use crate::takeout_writer::other_participants;
+use crate::writer::to_json;
+use crate::{ phone_digits, GroupFanOut, Message, OutputOptions, Participant, Thread };
+use log::info;
+
+// `--per-contact`: one JSON file per contact holding every message exchanged with them
+// across the run, sorted by time, named after the contact's phone digits. Messages are
+// spooled to a `.partial` file per contact as threads arrive and each file is sorted and
+// written when the run finishes, so only one contact's messages are in memory at a
+// time. Contacts are merged by phone digits as in `--participants-only`.
+pub struct ContactFiles {
+    dir: PathBuf,
+    fan_out: GroupFanOut,
+    options: OutputOptions,
+    contacts: HashMap<String, Contact>,
+}
+
+struct Contact {
+    /// First non-empty name seen
+    name: String,
+    phone: String,
+    file_stem: String,
+    threads: HashSet<String>,
+    messages: usize,
+}
+
+#[derive(Serialize)]
+struct ContactMessage<'a> {
+    thread_id: &'a str,
+    source_path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_index: Option<usize>,
+    #[serde(flatten)]
+    message: &'a Message,
+}
+
+#[derive(Serialize)]
+struct ContactFile<'a> {
+    name: &'a str,
+    phone: &'a str,
+    thread_count: usize,
+    message_count: usize,
+    messages: Vec<Value>,
+}
+
+impl ContactFiles {
+    pub fn create(dir: &Path, fan_out: GroupFanOut, options: OutputOptions) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(||
+            format!("Failed to create per-contact directory: {:?}", dir)
+        )?;
+        // Spools left behind by an interrupted run would be appended to otherwise
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "partial") {
+                fs::remove_file(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+            }
+        }
+        Ok(ContactFiles {
+            dir: dir.to_path_buf(),
+            fan_out,
+            options,
+            contacts: HashMap::new(),
+        })
+    }
+
+    // Spool the thread's messages for each contact in it other than yourself. A group
+    // thread goes to every member's file with `--group-fan-out all` and is skipped with
+    // `none`; note-to-self threads have no contact and are skipped.
+    pub fn record(&mut self, thread: &Thread) -> Result<()> {
+        // One contact listed under several names counts and gets the messages once
+        let mut keys = HashSet::new();
+        let contacts: Vec<&Participant> = other_participants(thread)
+            .into_iter()
+            .filter(|participant| keys.insert(contact_key(participant)))
+            .collect();
+        if contacts.len() > 1 && self.fan_out == GroupFanOut::None {
+            return Ok(());
+        }
+        for participant in contacts {
+            let contact = contact_entry(&mut self.contacts, participant);
+            contact.threads.insert(thread.thread_id.clone());
+            contact.messages += thread.messages.len();
+            let path = spool_path(&self.dir, &contact.file_stem);
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Failed to write {:?}", path))?;
+            let mut out = BufWriter::new(file);
+            for message in &thread.messages {
+                let entry = ContactMessage {
+                    thread_id: &thread.thread_id,
+                    source_path: &thread.source_path,
+                    thread_index: thread.thread_index,
+                    message,
+                };
+                // The sort key goes first so `finish` needn't parse timestamps back
+                writeln!(
+                    out,
+                    "{}\t{}",
+                    message.timestamp.timestamp_millis(),
+                    serde_json::to_string(&entry)?
+                )?;
+            }
+            out.flush().with_context(|| format!("Failed to write {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    // Sort each contact's spooled messages by time (keeping arrival order for equal
+    // times) into its `<phone digits>.json` and remove the spool
+    pub fn finish(self) -> Result<()> {
+        for contact in self.contacts.values() {
+            let spool = spool_path(&self.dir, &contact.file_stem);
+            let reader = BufReader::new(
+                fs::File::open(&spool).with_context(|| format!("Failed to read {:?}", spool))?
+            );
+            let mut messages: Vec<(i64, Value)> = Vec::with_capacity(contact.messages);
+            for line in reader.lines() {
+                let line = line.with_context(|| format!("Failed to read {:?}", spool))?;
+                let (key, json) = line.split_once('\t').context("Corrupt per-contact spool")?;
+                messages.push((key.parse()?, serde_json::from_str(json)?));
+            }
+            messages.sort_by_key(|(timestamp, _)| *timestamp);
+
+            let file = ContactFile {
+                name: &contact.name,
+                phone: &contact.phone,
+                thread_count: contact.threads.len(),
+                message_count: messages.len(),
+                messages: messages.into_iter().map(|(_, message)| message).collect(),
+            };
+            let path = self.dir.join(format!("{}.json", contact.file_stem));
+            fs::write(&path, to_json(&file, &self.options)? + "\n").with_context(||
+                format!("Failed to write {:?}", path)
+            )?;
+            fs::remove_file(&spool).with_context(|| format!("Failed to remove {:?}", spool))?;
+        }
+        info!("Wrote {} per-contact files to {:?}", self.contacts.len(), self.dir);
+        Ok(())
+    }
+}
+
+// The contact a participant belongs to, added on first sight
+fn contact_entry<'a>(
+    contacts: &'a mut HashMap<String, Contact>,
+    participant: &Participant
+) -> &'a mut Contact {
+    let key = contact_key(participant);
+    let contact = contacts.entry(key.clone()).or_insert_with(|| Contact {
+        name: String::new(),
+        phone: participant.phone.clone(),
+        file_stem: file_stem(&key),
+        threads: HashSet::new(),
+        messages: 0,
+    });
+    if contact.name.is_empty() && !participant.name.trim().is_empty() {
+        contact.name = participant.name.clone();
+    }
+    contact
+}
+
+// Phone digits identify a contact. Placeholder and redacted phones (`unknown:<hash>`,
+// `redacted:<hash>`) are kept whole, as the digits of their hashes aren't phone numbers.
+pub fn contact_key(participant: &Participant) -> String {
+    if participant.phone.contains(':') {
+        participant.phone.clone()
+    } else {
+        phone_digits(&participant.phone)
+    }
+}
+
+fn spool_path(dir: &Path, file_stem: &str) -> PathBuf {
+    dir.join(format!("{}.json.partial", file_stem))
+}
+
+// Phone digits as they are, anything else (placeholders, redacted phones) with
+// characters other than letters and digits replaced by `-`
+fn file_stem(key: &str) -> String {
+    let stem: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    if stem.is_empty() { "unknown".to_string() } else { stem }
+}
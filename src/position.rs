@@ -0,0 +1,158 @@
+use serde::{ Deserialize, Serialize };
+
+// Where a `.message` element starts in its source file. The DOM doesn't keep source
+// positions, so they come from a scan of the raw HTML for start tags with the
+// `message` class, matched to the parsed elements in document order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourcePosition {
+    /// Byte offset of the element's start tag
+    pub offset: usize,
+    /// 1-based line of the start tag
+    pub line: usize,
+}
+
+impl std::fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, byte {}", self.line, self.offset)
+    }
+}
+
+// Positions of the start tags whose `class` contains `message`, in document order.
+// Comments are skipped; tags are assumed to be otherwise well formed, as they are in
+// Takeout exports.
+pub fn message_positions(html: &str) -> Vec<SourcePosition> {
+    let bytes = html.as_bytes();
+    let mut positions = Vec::new();
+    let mut line = 1;
+    let mut counted = 0;
+    let mut pos = 0;
+    while let Some(found) = html[pos..].find('<') {
+        let start = pos + found;
+        if html[start..].starts_with("<!--") {
+            pos = html[start..].find("-->").map_or(html.len(), |end| start + end + 3);
+            continue;
+        }
+        let Some(end) = tag_end(bytes, start) else {
+            break;
+        };
+        pos = end;
+        if !bytes.get(start + 1).is_some_and(u8::is_ascii_alphabetic) {
+            continue;
+        }
+        if has_message_class(&html[start..end]) {
+            line += bytes[counted..start].iter().filter(|&&b| b == b'\n').count();
+            counted = start;
+            positions.push(SourcePosition { offset: start, line });
+        }
+    }
+    positions
+}
+
+// What the raw HTML says about a `.message` element beyond the DOM
+#[derive(Debug, Clone)]
+pub struct MessageSource {
+    pub position: SourcePosition,
+    /// Inner HTML of the message's content `<q>` byte for byte (but for CRLF line endings,
+    /// written as LF), with `--raw-content`
+    pub raw_content: Option<String>,
+}
+
+// `message_positions` plus, with `raw_content`, each message's content `<q>` as written
+pub fn message_sources(html: &str, raw_content: bool) -> Vec<MessageSource> {
+    let positions = message_positions(html);
+    let ends = positions
+        .iter()
+        .skip(1)
+        .map(|p| p.offset)
+        .chain(std::iter::once(html.len()));
+    positions
+        .iter()
+        .zip(ends)
+        .map(|(&position, end)| MessageSource {
+            position,
+            raw_content: if raw_content { raw_content_between(html, position.offset, end) } else { None },
+        })
+        .collect()
+}
+
+// Inner HTML of the first `<q>` in `html[start..end]` that isn't part of a quoted reply
+// (`<blockquote>`), up to the next `</q>`; `<q>` elements aren't nested in exports
+fn raw_content_between(html: &str, start: usize, end: usize) -> Option<String> {
+    let bytes = html.as_bytes();
+    let mut blockquotes = 0usize;
+    let mut pos = start;
+    while pos < end {
+        let tag_start = pos + html[pos..end].find('<')?;
+        if html[tag_start..].starts_with("<!--") {
+            pos = html[tag_start..].find("-->").map_or(end, |close| tag_start + close + 3);
+            continue;
+        }
+        let tag_end = tag_end(bytes, tag_start)?;
+        pos = tag_end;
+        let tag = html[tag_start..tag_end].to_ascii_lowercase();
+        match tag_name(&tag) {
+            "blockquote" => blockquotes += 1,
+            "/blockquote" => blockquotes = blockquotes.saturating_sub(1),
+            "q" if blockquotes == 0 => {
+                let close = html[tag_end..end].to_ascii_lowercase().find("</q")?;
+                return Some(html[tag_end..tag_end + close].replace("\r\n", "\n"));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Lowercased tag name after the `<`, with a leading `/` for end tags
+fn tag_name(tag: &str) -> &str {
+    let name = &tag[1..];
+    let len = name
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_alphanumeric() || (i == 0 && c == '/')))
+        .map_or(name.len(), |(i, _)| i);
+    &name[..len]
+}
+
+// Index just past the `>` closing the tag that starts at `start`, ignoring any `>`
+// inside quoted attribute values
+fn tag_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut quote = None;
+    for (i, &b) in bytes.iter().enumerate().skip(start + 1) {
+        match quote {
+            Some(q) if b == q => {
+                quote = None;
+            }
+            Some(_) => {}
+            None if b == b'"' || b == b'\'' => {
+                quote = Some(b);
+            }
+            None if b == b'>' => {
+                return Some(i + 1);
+            }
+            None => {}
+        }
+    }
+    None
+}
+
+// Whether the tag has a `class` attribute with `message` among its classes
+fn has_message_class(tag: &str) -> bool {
+    // Attribute names are case-insensitive, class names aren't
+    let lower = tag.to_ascii_lowercase();
+    lower.match_indices("class").any(|(i, _)| {
+        let before = lower[..i].chars().next_back();
+        if !before.is_some_and(char::is_whitespace) {
+            return false;
+        }
+        let rest = tag[i + "class".len()..].trim_start();
+        let Some(value) = rest.strip_prefix('=') else {
+            return false;
+        };
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(q @ ('"' | '\'')) => value[1..].split(q).next().unwrap_or(""),
+            _ => value.split(|c: char| c.is_whitespace() || c == '>').next().unwrap_or(""),
+        };
+        value.split_whitespace().any(|class| class == "message")
+    })
+}
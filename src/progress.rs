@@ -0,0 +1,84 @@
+use anyhow::{ Context, Result };
+use serde::Serialize;
+use std::fs::File;
+use std::io::{ self, Write };
+use std::path::Path;
+use std::time::Instant;
+
+// Machine-readable progress for headless runs (`--progress-to-file`,
+// `--progress-interval`): a JSON line every `interval` files of a directory and after
+// its last file, with counts, elapsed time and an ETA. Lines go to the file, or stderr
+// without one, and are flushed as written so the file can be followed while it grows.
+pub struct ProgressLog {
+    out: Box<dyn Write>,
+    interval: usize,
+    directory: String,
+    processed: usize,
+    total: usize,
+    started: Instant,
+}
+
+#[derive(Serialize)]
+struct ProgressLine<'a> {
+    directory: &'a str,
+    processed: usize,
+    total: usize,
+    elapsed_secs: f64,
+    /// Remaining time at the average rate so far; unknown before the first file
+    eta_secs: Option<f64>,
+}
+
+impl ProgressLog {
+    pub fn create(path: Option<&Path>, interval: usize) -> Result<Self> {
+        let out: Box<dyn Write> = match path {
+            Some(path) =>
+                Box::new(
+                    File::create(path).with_context(||
+                        format!("Failed to create progress file: {:?}", path)
+                    )?
+                ),
+            None => Box::new(io::stderr()),
+        };
+        Ok(ProgressLog {
+            out,
+            interval,
+            directory: String::new(),
+            processed: 0,
+            total: 0,
+            started: Instant::now(),
+        })
+    }
+
+    // Start counting the `total` files about to be parsed from `directory`
+    pub fn start(&mut self, directory: &Path, total: usize) {
+        self.directory = directory.to_string_lossy().to_string();
+        self.processed = 0;
+        self.total = total;
+        self.started = Instant::now();
+    }
+
+    // Record one parsed (or failed) file
+    pub fn file_done(&mut self) -> Result<()> {
+        self.processed += 1;
+        if self.processed.is_multiple_of(self.interval) || self.processed == self.total {
+            self.write_line()?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self) -> Result<()> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let remaining = self.total.saturating_sub(self.processed) as f64;
+        let line = ProgressLine {
+            directory: &self.directory,
+            processed: self.processed,
+            total: self.total,
+            elapsed_secs: (elapsed * 1000.0).round() / 1000.0,
+            eta_secs: (self.processed > 0).then(|| {
+                ((elapsed / (self.processed as f64)) * remaining * 10.0).round() / 10.0
+            }),
+        };
+        writeln!(self.out, "{}", serde_json::to_string(&line)?)?;
+        self.out.flush().context("Failed to write progress")
+    }
+}
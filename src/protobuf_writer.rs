@@ -0,0 +1,237 @@
+use anyhow::{ Context, Result };
+use chrono::{ DateTime, Utc };
+use prost::Message as _;
+use std::io::Write;
+use crate::writer::ThreadWriter;
+use crate::{ AttachmentKind, Direction, MessageKind, Thread };
+use log::info;
+
+// Threads as length-delimited protobuf messages, one per thread in the order they are
+// written, so a consumer can decode them as a stream without the whole run in memory.
+// The schema is shipped as `schema/google_voice.proto`.
+pub struct ProtobufWriter {
+    out: Box<dyn Write>,
+    threads: usize,
+    buffer: Vec<u8>,
+}
+
+impl ProtobufWriter {
+    pub fn create(out: Box<dyn Write>) -> Self {
+        ProtobufWriter { out, threads: 0, buffer: Vec::new() }
+    }
+}
+
+impl ThreadWriter for ProtobufWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        self.buffer.clear();
+        proto::Thread::from(&thread).encode_length_delimited(&mut self.buffer)?;
+        self.out.write_all(&self.buffer).context("Failed to write protobuf output")?;
+        self.threads += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush().context("Failed to flush output")?;
+        info!("Protobuf output complete. Total threads written: {}", self.threads);
+        Ok(())
+    }
+}
+
+fn timestamp(timestamp: &DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: timestamp.timestamp(),
+        nanos: timestamp.timestamp_subsec_nanos() as i32,
+    }
+}
+
+impl From<&crate::Participant> for proto::Participant {
+    fn from(participant: &crate::Participant) -> Self {
+        proto::Participant {
+            name: participant.name.clone(),
+            phone: participant.phone.clone(),
+            message_count: participant.message_count as u64,
+            first_seen: participant.first_seen.as_ref().map(timestamp),
+            last_seen: participant.last_seen.as_ref().map(timestamp),
+            region: participant.region.clone(),
+            raw_name: participant.raw_name.clone(),
+            avatar: participant.avatar.clone(),
+        }
+    }
+}
+
+impl From<&crate::Message> for proto::Message {
+    fn from(message: &crate::Message) -> Self {
+        let direction = match message.direction {
+            Direction::Outgoing => proto::Direction::Outgoing,
+            Direction::Incoming => proto::Direction::Incoming,
+        };
+        let kind = match message.kind {
+            MessageKind::Regular => proto::MessageKind::Regular,
+            MessageKind::Reaction => proto::MessageKind::Reaction,
+            MessageKind::System => proto::MessageKind::System,
+        };
+        proto::Message {
+            id: message.id.clone(),
+            from: Some((&message.from).into()),
+            to: message.to.iter().map(Into::into).collect(),
+            direction: direction as i32,
+            kind: kind as i32,
+            timestamp: Some(timestamp(&message.timestamp)),
+            content: message.body.text().map(str::to_string),
+            attachments: message.body
+                .media()
+                .iter()
+                .map(|attachment| {
+                    let kind = match attachment.kind {
+                        AttachmentKind::Image => proto::AttachmentKind::Image,
+                        AttachmentKind::Video => proto::AttachmentKind::Video,
+                        AttachmentKind::Audio => proto::AttachmentKind::Audio,
+                        AttachmentKind::Contact => proto::AttachmentKind::Contact,
+                    };
+                    proto::Attachment {
+                        kind: kind as i32,
+                        src: attachment.src.clone(),
+                        extracted_path: attachment.extracted_path.clone(),
+                    }
+                })
+                .collect(),
+            reply_to: message.reply_to.clone(),
+            language: message.language.clone(),
+            flags: message.flags.clone(),
+            truncated: message.truncated,
+            message_index: message.message_index.map(|index| index as u64),
+        }
+    }
+}
+
+impl From<&Thread> for proto::Thread {
+    fn from(thread: &Thread) -> Self {
+        proto::Thread {
+            thread_id: thread.thread_id.clone(),
+            source_path: thread.source_path.clone(),
+            messages: thread.messages.iter().map(Into::into).collect(),
+            participants: thread.participants.iter().map(Into::into).collect(),
+            labels: thread.labels.clone(),
+            message_count: thread.message_count as u64,
+            is_self_thread: thread.is_self_thread,
+            self_phone_known: thread.self_phone_known,
+            session_index: thread.session_index.map(|index| index as u64),
+            thread_index: thread.thread_index.map(|index| index as u64),
+        }
+    }
+}
+
+// The messages of `schema/google_voice.proto`, written out as prost would generate them
+// so the build doesn't need `protoc`. Tags and types must be kept in step with the file.
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Participant {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub phone: String,
+        #[prost(uint64, tag = "3")]
+        pub message_count: u64,
+        #[prost(message, optional, tag = "4")]
+        pub first_seen: Option<prost_types::Timestamp>,
+        #[prost(message, optional, tag = "5")]
+        pub last_seen: Option<prost_types::Timestamp>,
+        #[prost(string, optional, tag = "6")]
+        pub region: Option<String>,
+        #[prost(string, optional, tag = "7")]
+        pub raw_name: Option<String>,
+        #[prost(string, optional, tag = "8")]
+        pub avatar: Option<String>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Direction {
+        Unspecified = 0,
+        Outgoing = 1,
+        Incoming = 2,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum MessageKind {
+        Unspecified = 0,
+        Regular = 1,
+        Reaction = 2,
+        System = 3,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum AttachmentKind {
+        Unspecified = 0,
+        Image = 1,
+        Video = 2,
+        Audio = 3,
+        Contact = 4,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Attachment {
+        #[prost(enumeration = "AttachmentKind", tag = "1")]
+        pub kind: i32,
+        #[prost(string, tag = "2")]
+        pub src: String,
+        #[prost(string, optional, tag = "3")]
+        pub extracted_path: Option<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Message {
+        #[prost(string, tag = "1")]
+        pub id: String,
+        #[prost(message, optional, tag = "2")]
+        pub from: Option<Participant>,
+        #[prost(message, repeated, tag = "3")]
+        pub to: Vec<Participant>,
+        #[prost(enumeration = "Direction", tag = "4")]
+        pub direction: i32,
+        #[prost(enumeration = "MessageKind", tag = "5")]
+        pub kind: i32,
+        #[prost(message, optional, tag = "6")]
+        pub timestamp: Option<prost_types::Timestamp>,
+        #[prost(string, optional, tag = "7")]
+        pub content: Option<String>,
+        #[prost(message, repeated, tag = "8")]
+        pub attachments: Vec<Attachment>,
+        #[prost(string, optional, tag = "9")]
+        pub reply_to: Option<String>,
+        #[prost(string, optional, tag = "10")]
+        pub language: Option<String>,
+        #[prost(string, repeated, tag = "11")]
+        pub flags: Vec<String>,
+        #[prost(bool, tag = "12")]
+        pub truncated: bool,
+        #[prost(uint64, optional, tag = "13")]
+        pub message_index: Option<u64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Thread {
+        #[prost(string, tag = "1")]
+        pub thread_id: String,
+        #[prost(string, tag = "2")]
+        pub source_path: String,
+        #[prost(message, repeated, tag = "3")]
+        pub messages: Vec<Message>,
+        #[prost(message, repeated, tag = "4")]
+        pub participants: Vec<Participant>,
+        #[prost(string, repeated, tag = "5")]
+        pub labels: Vec<String>,
+        #[prost(uint64, tag = "6")]
+        pub message_count: u64,
+        #[prost(bool, tag = "7")]
+        pub is_self_thread: bool,
+        #[prost(bool, tag = "8")]
+        pub self_phone_known: bool,
+        #[prost(uint64, optional, tag = "9")]
+        pub session_index: Option<u64>,
+        #[prost(uint64, optional, tag = "10")]
+        pub thread_index: Option<u64>,
+    }
+}
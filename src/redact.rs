@@ -0,0 +1,96 @@
+use std::path::Path;
+use crate::{ for_each_participant_mut, ParseWarning, Participant, Thread };
+
+// Strip private data from a parsed thread while keeping its shape. Message text keeps
+// its length and whitespace, phone numbers become stable hashes so the same contact
+// still links up across threads, and unless `keep_names` is set display names and the
+// source file name (which embeds the contact's name) are replaced too.
+pub fn redact_thread(thread: &mut Thread, keep_names: bool) {
+    for message in &mut thread.messages {
+        if let Some(content) = message.body.text_mut() {
+            *content = content
+                .chars()
+                .map(|c| if c.is_whitespace() { c } else { 'x' })
+                .collect();
+        }
+        // Takeout names media after the conversation file, so it embeds the contact name
+        if !keep_names {
+            for attachment in message.body.media_mut() {
+                attachment.src = redacted_file_name(&attachment.src);
+                attachment.extracted_path = attachment.extracted_path.as_deref().map(redacted_file_name);
+            }
+        }
+    }
+    for_each_participant_mut(&mut thread.participants, &mut thread.messages, |participant| {
+        redact_participant(participant, keep_names)
+    });
+    // The id hashes the phones and file name, so it is replaced by a hash of itself
+    thread.thread_id = blake3::hash(format!("redacted:{}", thread.thread_id).as_bytes()).to_hex().to_string();
+    for stats in &mut thread.participant_stats {
+        stats.phone = redacted_phone(&stats.phone);
+    }
+    for warning in &mut thread.warnings {
+        match warning {
+            ParseWarning::SenderNameConflict { phone, name, attributed_to, .. } => {
+                if !keep_names {
+                    *name = redacted_name(name, phone);
+                    *attributed_to = redacted_name(attributed_to, phone);
+                }
+                *phone = redacted_phone(phone);
+            }
+            ParseWarning::SenderHasSelfPhone { phone, name, .. } => {
+                if !keep_names {
+                    *name = redacted_name(name, phone);
+                }
+                *phone = redacted_phone(phone);
+            }
+            ParseWarning::UnresolvedReply { .. } |
+            ParseWarning::RawContentUnavailable { .. } |
+            ParseWarning::MissingTimestamp { .. } |
+            ParseWarning::InvalidTimestamp { .. } |
+            ParseWarning::MessageCountMismatch { .. } |
+            ParseWarning::RepeatedMessages { .. } |
+            ParseWarning::UntranscribedVoicemail { .. } => {}
+        }
+    }
+    if !keep_names {
+        thread.source_path = redacted_file_name(&thread.source_path);
+    }
+}
+
+// Replace a path's file name with a hash of the full path, keeping the directory and extension
+fn redacted_file_name(path: &str) -> String {
+    let original = Path::new(path);
+    let mut file_name = format!("redacted-{}", &blake3::hash(path.as_bytes()).to_hex()[..16]);
+    if let Some(extension) = original.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    original.with_file_name(file_name).to_string_lossy().to_string()
+}
+
+fn redact_participant(participant: &mut Participant, keep_names: bool) {
+    if !keep_names {
+        participant.name = redacted_name(&participant.name, &participant.phone);
+        participant.raw_name = None;
+    }
+    participant.phone = redacted_phone(&participant.phone);
+}
+
+fn redacted_phone(phone: &str) -> String {
+    // An empty phone (your own unresolved number in JSON from before `me:` placeholders)
+    // reveals nothing
+    if phone.is_empty() {
+        return String::new();
+    }
+    format!("redacted:{}", &blake3::hash(phone.as_bytes()).to_hex()[..16])
+}
+
+// "Me" carries nothing private and is what downstream writers use to tell direction,
+// so it is kept; every other name becomes a label derived from the phone
+fn redacted_name(name: &str, phone: &str) -> String {
+    if name == "Me" || name.is_empty() {
+        return name.to_string();
+    }
+    format!("Contact {}", &blake3::hash(phone.as_bytes()).to_hex()[..8])
+}
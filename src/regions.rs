@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use crate::geo::region_for_phone;
+use crate::per_contact::contact_key;
+use crate::takeout_writer::other_participants;
+use crate::Thread;
+
+// Bucket for numbers outside the North American Numbering Plan, unlisted area codes and
+// placeholder phones
+const UNKNOWN_REGION: &str = "Unknown";
+
+// `--region-summary`: the contacts of the run by the region their area code belongs to
+// (see `geo`), with the messages they sent. Contacts are merged by phone digits as in
+// `--participants-only` and placed by the first number seen for them; you aren't counted.
+#[derive(Default)]
+pub struct RegionSummary {
+    /// Region and messages sent, by contact key
+    contacts: HashMap<String, (&'static str, usize)>,
+}
+
+impl RegionSummary {
+    pub fn record(&mut self, thread: &Thread) {
+        for participant in other_participants(thread) {
+            let (_, messages) = self.contacts
+                .entry(contact_key(participant))
+                .or_insert_with(|| (region_for_phone(&participant.phone).unwrap_or(UNKNOWN_REGION), 0));
+            *messages += participant.message_count;
+        }
+    }
+
+    // Print the regions with the most contacts first, ties by messages then name, and
+    // the unknown bucket last
+    pub fn finish(self) {
+        let mut regions: HashMap<&str, (usize, usize)> = HashMap::new();
+        for (region, messages) in self.contacts.into_values() {
+            let totals = regions.entry(region).or_default();
+            totals.0 += 1;
+            totals.1 += messages;
+        }
+        let mut ranked: Vec<(&str, (usize, usize))> = regions.into_iter().collect();
+        ranked.sort_by(|a, b| {
+            (a.0 == UNKNOWN_REGION)
+                .cmp(&(b.0 == UNKNOWN_REGION))
+                .then_with(|| b.1.cmp(&a.1))
+                .then_with(|| a.0.cmp(b.0))
+        });
+        let width = ranked
+            .iter()
+            .map(|(region, _)| region.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("Region".len());
+        println!("\nContacts by region:");
+        if ranked.is_empty() {
+            println!("  (none)");
+            return;
+        }
+        println!("  {:<width$}  {:>8}  {:>8}", "Region", "Contacts", "Messages", width = width);
+        for (region, (contacts, messages)) in ranked {
+            println!("  {:<width$}  {:>8}  {:>8}", region, contacts, messages, width = width);
+        }
+    }
+}
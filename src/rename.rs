@@ -0,0 +1,88 @@
+use anyhow::{ Context, Result };
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use crate::{ phone_digits, Participant };
+
+// Display-name overrides from `--rename-map`. Keys are phone numbers, matched by their
+// digits, or names as they appear after parsing; values are the names to use instead.
+#[derive(Debug, Clone, Default)]
+pub struct RenameMap {
+    by_phone: HashMap<String, String>,
+    by_name: HashMap<String, String>,
+}
+
+impl RenameMap {
+    // Load a `.toml` file of `"key" = "New name"` pairs, or a CSV file with one
+    // `key,New name` pair per line (an optional `key,name` header and `#` comments allowed)
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(||
+            format!("Failed to read rename map: {:?}", path)
+        )?;
+        let is_toml = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("toml"));
+        let pairs: Vec<(String, String)> = if is_toml {
+            toml::from_str::<HashMap<String, String>>(&content)
+                .with_context(|| format!("Invalid rename map: {:?}", path))?
+                .into_iter()
+                .collect()
+        } else {
+            parse_csv_pairs(&content).with_context(|| format!("Invalid rename map: {:?}", path))?
+        };
+
+        let mut map = RenameMap::default();
+        for (key, name) in pairs {
+            if is_phone(&key) {
+                map.by_phone.insert(phone_digits(&key), name);
+            } else {
+                map.by_name.insert(key, name);
+            }
+        }
+        Ok(map)
+    }
+
+    // Phone entries take precedence over name entries
+    pub fn rename(&self, participant: &mut Participant) {
+        let renamed = self.by_phone
+            .get(&phone_digits(&participant.phone))
+            .or_else(|| self.by_name.get(&participant.name));
+        if let Some(name) = renamed {
+            participant.name = name.clone();
+        }
+    }
+}
+
+fn parse_csv_pairs(content: &str) -> Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, name)) = line.split_once(',') else {
+            anyhow::bail!("line {}: expected `key,name`", index + 1);
+        };
+        let (key, name) = (unquote(key), unquote(name));
+        if index == 0 && key.eq_ignore_ascii_case("key") && name.eq_ignore_ascii_case("name") {
+            continue;
+        }
+        pairs.push((key.to_string(), name.to_string()));
+    }
+    Ok(pairs)
+}
+
+fn unquote(field: &str) -> &str {
+    let field = field.trim();
+    field
+        .strip_prefix('"')
+        .and_then(|f| f.strip_suffix('"'))
+        .unwrap_or(field)
+}
+
+// A key made only of digits and phone punctuation is a number rather than a name
+fn is_phone(key: &str) -> bool {
+    key.chars().any(|c| c.is_ascii_digit()) &&
+        key.chars().all(|c| c.is_ascii_digit() || "+-(). ".contains(c))
+}
@@ -0,0 +1,95 @@
+use anyhow::{ Context, Result };
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ self, BufWriter, Write };
+use std::path::Path;
+use crate::manifest::csv_field;
+use crate::per_contact::contact_key;
+use crate::Thread;
+use log::info;
+
+// Every distinct participant across a run with their total message count, for
+// `--participants-only`. Entries are merged by phone digits, so `+1 (650) 555-0100`
+// and `+16505550100` are one contact.
+#[derive(Debug, Default)]
+pub struct Roster {
+    entries: HashMap<String, RosterEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RosterEntry {
+    pub name: String,
+    pub phone: String,
+    pub message_count: usize,
+    pub threads: usize,
+}
+
+impl Roster {
+    pub fn add(&mut self, thread: &Thread) {
+        for participant in &thread.participants {
+            // Placeholder phones (`unknown:<hash>`, `me:<hash>`) stay distinct
+            let key = contact_key(participant);
+            let entry = self.entries.entry(key).or_insert_with(|| RosterEntry {
+                name: String::new(),
+                phone: participant.phone.clone(),
+                message_count: 0,
+                threads: 0,
+            });
+            if entry.name.is_empty() && !participant.name.trim().is_empty() {
+                entry.name = participant.name.clone();
+            }
+            entry.message_count += participant.message_count;
+            entry.threads += 1;
+        }
+    }
+
+    // The entries sorted by name (case-insensitive, nameless contacts last)
+    pub fn into_sorted(self) -> Vec<RosterEntry> {
+        let mut entries: Vec<RosterEntry> = self.entries.into_values().collect();
+        entries.sort_by(|a, b| {
+            (a.name.is_empty(), a.name.to_lowercase(), &a.phone).cmp(
+                &(b.name.is_empty(), b.name.to_lowercase(), &b.phone)
+            )
+        });
+        entries
+    }
+
+    // Write the sorted roster: CSV when `path` ends in `.csv`, otherwise JSON; stdout
+    // gets JSON
+    pub fn write(self, path: Option<&Path>) -> Result<()> {
+        let entries = self.into_sorted();
+
+        let csv = path
+            .and_then(|p| p.extension())
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+        let mut out: Box<dyn Write> = match path {
+            Some(path) => {
+                let file = File::create(path).with_context(||
+                    format!("Failed to create roster file: {:?}", path)
+                )?;
+                Box::new(BufWriter::new(file))
+            }
+            None => Box::new(io::stdout()),
+        };
+        if csv {
+            writeln!(out, "name,phone,message_count,threads")?;
+            for entry in &entries {
+                writeln!(
+                    out,
+                    "{},{},{},{}",
+                    csv_field(&entry.name),
+                    csv_field(&entry.phone),
+                    entry.message_count,
+                    entry.threads
+                )?;
+            }
+        } else {
+            writeln!(out, "{}", serde_json::to_string_pretty(&entries)?)?;
+        }
+        out.flush().context("Failed to flush roster")?;
+        info!("Roster complete. Total participants: {}", entries.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::{ Context, Result };
+use rand::rngs::StdRng;
+use rand::{ Rng, SeedableRng };
+use serde::Serialize;
+use std::io::Write;
+use crate::writer::{ to_json, ThreadWriter };
+use crate::{ Message, OutputOptions, Thread };
+use log::info;
+
+// `--sample`: keeps a uniform random sample of the run's messages using reservoir
+// sampling, so memory stays at `size` messages however large the corpus is. The sample
+// is written once the run finishes, as a JSON array ordered by timestamp.
+pub struct SampleWriter {
+    out: Box<dyn Write>,
+    options: OutputOptions,
+    size: usize,
+    rng: StdRng,
+    /// Messages seen so far across all threads
+    seen: usize,
+    reservoir: Vec<SampledMessage>,
+}
+
+#[derive(Serialize)]
+struct SampledMessage {
+    thread_id: String,
+    source_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_index: Option<usize>,
+    #[serde(flatten)]
+    message: Message,
+}
+
+impl SampleWriter {
+    pub fn new(out: Box<dyn Write>, options: OutputOptions, size: usize, seed: u64) -> Self {
+        SampleWriter {
+            out,
+            options,
+            size,
+            rng: StdRng::seed_from_u64(seed),
+            seen: 0,
+            reservoir: Vec::with_capacity(size),
+        }
+    }
+}
+
+impl ThreadWriter for SampleWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        for message in thread.messages {
+            // Algorithm R: the n-th message replaces a random slot with probability size/n
+            let slot = if self.reservoir.len() < self.size {
+                Some(self.reservoir.len())
+            } else {
+                Some(self.rng.gen_range(0..=self.seen)).filter(|&j| j < self.size)
+            };
+            self.seen += 1;
+            let Some(slot) = slot else {
+                continue;
+            };
+            let sampled = SampledMessage {
+                thread_id: thread.thread_id.clone(),
+                source_path: thread.source_path.clone(),
+                thread_index: thread.thread_index,
+                message,
+            };
+            if slot == self.reservoir.len() {
+                self.reservoir.push(sampled);
+            } else {
+                self.reservoir[slot] = sampled;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.reservoir.sort_by_key(|s| s.message.timestamp);
+        let json = to_json(&self.reservoir, &self.options)?;
+        writeln!(self.out, "{}", json)?;
+        self.out.flush().context("Failed to flush sample output")?;
+        info!("Sampled {} of {} messages", self.reservoir.len(), self.seen);
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use anyhow::{ Context, Result };
+use jsonschema::Validator;
+use std::sync::OnceLock;
+use crate::Thread;
+
+// The published JSON Schema of a serialized thread, `schema/thread.schema.json`. It is
+// the output contract for downstream consumers and carries the crate version it
+// describes, so a change to the output shape means a change to the schema.
+const THREAD_SCHEMA: &str = include_str!("../schema/thread.schema.json");
+
+// Errors listed per invalid thread before the rest are summarized
+const MAX_REPORTED_ERRORS: usize = 5;
+
+// Checks threads against the schema before they are written, for `--validate-output`
+pub struct OutputValidator {
+    validator: Validator,
+}
+
+impl OutputValidator {
+    pub fn new() -> Result<Self> {
+        let schema: serde_json::Value = serde_json::from_str(THREAD_SCHEMA)
+            .context("Bundled thread schema is not valid JSON")?;
+        let validator = jsonschema::options()
+            .should_validate_formats(true)
+            .build(&schema)
+            .map_err(|e| anyhow::anyhow!("Bundled thread schema is invalid: {}", e))?;
+        Ok(OutputValidator { validator })
+    }
+
+    // Fail with every violation (up to a limit) located by its JSON pointer
+    pub fn validate(&self, thread: &Thread) -> Result<()> {
+        let instance = serde_json::to_value(thread)?;
+        match self.report(&instance) {
+            None => Ok(()),
+            Some(report) =>
+                anyhow::bail!(
+                    "Thread from {:?} doesn't match the output schema:\n  {}",
+                    thread.source_path,
+                    report
+                ),
+        }
+    }
+
+    // The violations of a serialized thread, one per line, or `None` if it is valid
+    fn report(&self, instance: &serde_json::Value) -> Option<String> {
+        let errors: Vec<String> = self.validator
+            .iter_errors(instance)
+            .map(|e| format!("{}: {}", e.instance_path(), e))
+            .collect();
+        if errors.is_empty() {
+            return None;
+        }
+        let mut report = errors[..errors.len().min(MAX_REPORTED_ERRORS)].join("\n  ");
+        if errors.len() > MAX_REPORTED_ERRORS {
+            report.push_str(&format!("\n  ... and {} more", errors.len() - MAX_REPORTED_ERRORS));
+        }
+        Some(report)
+    }
+}
+
+// Check a thread read back from JSON input against the schema, with the validator built
+// on first use
+pub fn check_thread(instance: &serde_json::Value) -> Option<String> {
+    static VALIDATOR: OnceLock<OutputValidator> = OnceLock::new();
+    VALIDATOR
+        .get_or_init(|| OutputValidator::new().expect("Invalid bundled thread schema"))
+        .report(instance)
+}
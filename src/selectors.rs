@@ -0,0 +1,92 @@
+use scraper::Selector;
+use std::sync::OnceLock;
+use crate::error::{ self, ParseError };
+use crate::{
+    ATTACHMENT_SELECTOR,
+    AVATAR_SELECTOR,
+    CALLER_SELECTOR,
+    CONTENT_SELECTOR,
+    CONVERSATION_SELECTOR,
+    DATETIME_SELECTOR,
+    MESSAGE_SELECTOR,
+    NAME_SELECTOR,
+    PARTICIPANTS_SELECTOR,
+    PUBLISHED_SELECTOR,
+    REPLY_SELECTOR,
+    REPLY_SENDER_SELECTOR,
+    SELF_NAME_SELECTOR,
+    SENDER_SELECTOR,
+    TAGS_SELECTOR,
+    TEL_SELECTOR,
+    TITLE_SELECTOR,
+    TRANSCRIPTION_SELECTOR,
+    VOICEMAIL_SELECTOR,
+};
+
+// Every selector the parser uses, compiled once per run. `compile` runs before any file
+// is parsed, so a selector that doesn't compile fails the run up front with an error
+// naming it, instead of failing every file or panicking mid-parse; parsing code then
+// reads the compiled set with `get`.
+pub struct Selectors {
+    pub message: Selector,
+    pub conversation: Selector,
+    pub participants: Selector,
+    /// `.participants` or `.message`, the elements a conversation is split at
+    pub boundary: Selector,
+    pub datetime: Selector,
+    pub sender: Selector,
+    /// A sender's display name
+    pub name: Selector,
+    /// The name element whose tag tells you apart from other senders
+    pub self_name: Selector,
+    pub tel: Selector,
+    pub avatar: Selector,
+    pub content: Selector,
+    pub reply: Selector,
+    pub reply_sender: Selector,
+    pub attachment: Selector,
+    pub tags: Selector,
+    pub title: Selector,
+    /// A voicemail's `.haudio` element, the caller's card in it, when it was left and
+    /// its transcription
+    pub voicemail: Selector,
+    pub caller: Selector,
+    pub published: Selector,
+    pub transcription: Selector,
+}
+
+static SELECTORS: OnceLock<Selectors> = OnceLock::new();
+
+// Compile the selectors unless an earlier call did
+pub fn compile() -> Result<&'static Selectors, ParseError> {
+    if let Some(selectors) = SELECTORS.get() {
+        return Ok(selectors);
+    }
+    let selectors = Selectors {
+        message: error::compile(MESSAGE_SELECTOR)?,
+        conversation: error::compile(CONVERSATION_SELECTOR)?,
+        participants: error::compile(PARTICIPANTS_SELECTOR)?,
+        boundary: error::compile(&format!("{}, {}", PARTICIPANTS_SELECTOR, MESSAGE_SELECTOR))?,
+        datetime: error::compile(DATETIME_SELECTOR)?,
+        sender: error::compile(SENDER_SELECTOR)?,
+        name: error::compile(NAME_SELECTOR)?,
+        self_name: error::compile(SELF_NAME_SELECTOR)?,
+        tel: error::compile(TEL_SELECTOR)?,
+        avatar: error::compile(AVATAR_SELECTOR)?,
+        content: error::compile(CONTENT_SELECTOR)?,
+        reply: error::compile(REPLY_SELECTOR)?,
+        reply_sender: error::compile(REPLY_SENDER_SELECTOR)?,
+        attachment: error::compile(ATTACHMENT_SELECTOR)?,
+        tags: error::compile(TAGS_SELECTOR)?,
+        title: error::compile(TITLE_SELECTOR)?,
+        voicemail: error::compile(VOICEMAIL_SELECTOR)?,
+        caller: error::compile(CALLER_SELECTOR)?,
+        published: error::compile(PUBLISHED_SELECTOR)?,
+        transcription: error::compile(TRANSCRIPTION_SELECTOR)?,
+    };
+    Ok(SELECTORS.get_or_init(|| selectors))
+}
+
+pub fn get() -> &'static Selectors {
+    SELECTORS.get().expect("Selectors are compiled before parsing starts")
+}
@@ -0,0 +1,78 @@
+use anyhow::{ Context, Result };
+use serde::Serialize;
+use std::io::Write;
+use crate::writer::ThreadWriter;
+use crate::{ phone_digits, Thread };
+use log::info;
+
+// The message-backup JSON schema several chat importers accept: one flat array of
+// messages with E.164 phone numbers and epoch-millisecond timestamps. Messages stream
+// out as threads arrive, one per line.
+pub struct SignalJsonWriter {
+    out: Box<dyn Write>,
+    messages: usize,
+}
+
+#[derive(Serialize)]
+struct BackupMessage<'a> {
+    sender: String,
+    recipients: Vec<String>,
+    timestamp_ms: i64,
+    body: &'a str,
+    thread_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_index: Option<usize>,
+}
+
+impl SignalJsonWriter {
+    pub fn create(mut out: Box<dyn Write>) -> Result<Self> {
+        write!(out, "[")?;
+        Ok(SignalJsonWriter { out, messages: 0 })
+    }
+}
+
+impl ThreadWriter for SignalJsonWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        for message in &thread.messages {
+            let backup = BackupMessage {
+                sender: e164(&message.from.phone),
+                recipients: message.to
+                    .iter()
+                    .map(|p| e164(&p.phone))
+                    .collect(),
+                timestamp_ms: message.timestamp.timestamp_millis(),
+                body: message.body.text().unwrap_or(""),
+                thread_id: &thread.thread_id,
+                thread_index: thread.thread_index,
+                message_index: message.message_index,
+            };
+            let separator = if self.messages == 0 { "\n" } else { ",\n" };
+            write!(self.out, "{}{}", separator, serde_json::to_string(&backup)?)?;
+            self.messages += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        writeln!(self.out, "\n]")?;
+        self.out.flush().context("Failed to flush output")?;
+        info!("Backup JSON output complete. Total messages written: {}", self.messages);
+        Ok(())
+    }
+}
+
+// Format a phone number as E.164. Takeout numbers are usually `+<digits>` already;
+// bare 10-digit numbers are taken to be North American. Placeholders for unknown
+// senders have no digits and are passed through unchanged.
+fn e164(phone: &str) -> String {
+    let digits = phone_digits(phone);
+    if digits.is_empty() {
+        phone.to_string()
+    } else if !phone.trim_start().starts_with('+') && digits.len() == 10 {
+        format!("+1{}", digits)
+    } else {
+        format!("+{}", digits)
+    }
+}
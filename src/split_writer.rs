@@ -0,0 +1,108 @@
+use anyhow::{ Context, Result };
+use chrono_tz::Tz;
+use std::collections::BTreeMap;
+use std::fs::{ self, File };
+use std::io::{ BufRead, BufReader, BufWriter, Write };
+use std::path::{ Path, PathBuf };
+use crate::writer::{ to_json, ThreadWriter };
+use crate::{ Message, OutputOptions, SplitPeriod, Thread };
+use log::info;
+
+// `--split-by`: every message of the run in the NDJSON file of the month or year it was
+// sent in, by its time in `--tz-output`, e.g. `2023-07.ndjson`. Each line is a message
+// with its thread's id and source path, so one thread can span several files. Messages
+// are spooled to a `.partial` file per period as threads arrive; when the run finishes
+// each period is sorted by time (keeping arrival order for equal times) into its file.
+pub struct SplitWriter {
+    dir: PathBuf,
+    period: SplitPeriod,
+    tz: Tz,
+    options: OutputOptions,
+    spools: BTreeMap<String, BufWriter<File>>,
+    messages: usize,
+}
+
+impl SplitWriter {
+    pub fn create(dir: &Path, period: SplitPeriod, tz: Tz, options: OutputOptions) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create output directory: {:?}", dir))?;
+        // Every line is one message, whatever `--compact` says
+        let options = OutputOptions { compact_json: true, ..options };
+        Ok(SplitWriter { dir: dir.to_path_buf(), period, tz, options, spools: BTreeMap::new(), messages: 0 })
+    }
+
+    fn period_name(&self, message: &Message) -> String {
+        let local = message.timestamp.with_timezone(&self.tz);
+        match self.period {
+            SplitPeriod::Month => local.format("%Y-%m").to_string(),
+            SplitPeriod::Year => local.format("%Y").to_string(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct PeriodMessage<'a> {
+    thread_id: &'a str,
+    source_path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_index: Option<usize>,
+    #[serde(flatten)]
+    message: &'a Message,
+}
+
+impl ThreadWriter for SplitWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        for message in &thread.messages {
+            let name = self.period_name(message);
+            let spool = match self.spools.get_mut(&name) {
+                Some(spool) => spool,
+                None => {
+                    let path = spool_path(&self.dir, &name);
+                    let file = File::create(&path).with_context(|| format!("Failed to create {:?}", path))?;
+                    self.spools.entry(name).or_insert(BufWriter::new(file))
+                }
+            };
+            let entry = PeriodMessage {
+                thread_id: &thread.thread_id,
+                source_path: &thread.source_path,
+                thread_index: thread.thread_index,
+                message,
+            };
+            // The sort key goes first so `finish` needn't parse timestamps back
+            writeln!(spool, "{}\t{}", message.timestamp.timestamp_millis(), to_json(&entry, &self.options)?)?;
+            self.messages += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let periods = self.spools.len();
+        for (name, mut spool) in self.spools {
+            spool.flush().context("Failed to flush output")?;
+            drop(spool);
+            let spool = spool_path(&self.dir, &name);
+            let reader = BufReader::new(File::open(&spool).with_context(|| format!("Failed to read {:?}", spool))?);
+            let mut lines: Vec<(i64, String)> = Vec::new();
+            for line in reader.lines() {
+                let line = line.with_context(|| format!("Failed to read {:?}", spool))?;
+                let (key, json) = line.split_once('\t').context("Corrupt --split-by spool")?;
+                lines.push((key.parse()?, json.to_string()));
+            }
+            lines.sort_by_key(|(timestamp, _)| *timestamp);
+
+            let path = self.dir.join(format!("{}.ndjson", name));
+            let mut out = BufWriter::new(File::create(&path).with_context(|| format!("Failed to create {:?}", path))?);
+            for (_, json) in lines {
+                writeln!(out, "{}", json)?;
+            }
+            out.flush().with_context(|| format!("Failed to write {:?}", path))?;
+            fs::remove_file(&spool).with_context(|| format!("Failed to remove {:?}", spool))?;
+        }
+        info!("Split output complete. Total messages written: {}", self.messages);
+        eprintln!("Wrote {} messages into {} period files in {:?}", self.messages, periods, self.dir);
+        Ok(())
+    }
+}
+
+fn spool_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.ndjson.partial", name))
+}
@@ -0,0 +1,233 @@
+use anyhow::{ Context, Result };
+use std::collections::{ HashMap, HashSet };
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use crate::html_writer::escape_html;
+use crate::writer::ThreadWriter;
+use crate::{
+    AttachmentKind,
+    Direction,
+    MessageKind,
+    Participant,
+    Thread,
+    DATETIME_FORMAT,
+    GROUP_CONVERSATION_LABEL,
+    TEXT_LABEL,
+};
+use log::info;
+
+// Writes threads back out as Google Voice Takeout HTML, one file per thread, using the
+// markup the parser reads. Parsing the result gives the same threads again, apart from
+// source paths and the message ids derived from them, so parsed (and `--redact`ed)
+// exports can be turned into shareable test fixtures.
+pub struct TakeoutWriter {
+    dir: PathBuf,
+    used_names: HashSet<String>,
+}
+
+impl TakeoutWriter {
+    pub fn create(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(||
+            format!("Failed to create Takeout output directory: {:?}", dir)
+        )?;
+        Ok(TakeoutWriter {
+            dir: dir.to_path_buf(),
+            used_names: HashSet::new(),
+        })
+    }
+
+    // `<contact> - Text - <first timestamp>.html`, or `Group Conversation - ...` for
+    // groups, as Takeout names them; a counter keeps the names unique
+    fn file_name(&mut self, thread: &Thread) -> String {
+        let timestamp = thread.messages
+            .iter()
+            .map(|m| m.timestamp)
+            .min()
+            .map(|t| t.format("%Y-%m-%dT%H_%M_%SZ").to_string())
+            .unwrap_or_else(|| "1970-01-01T00_00_00Z".to_string());
+        let others = other_participants(thread);
+        let stem = if others.len() > 1 {
+            format!("{} {}", GROUP_CONVERSATION_LABEL, timestamp)
+        } else {
+            let contact: String = counterpart(thread, &others)
+                .chars()
+                .map(|c| if "/\\:*?\"<>|".contains(c) { '_' } else { c })
+                .collect();
+            format!("{} {} {}", contact, TEXT_LABEL, timestamp)
+        };
+
+        let mut name = format!("{}.html", stem);
+        let mut n = 2;
+        while !self.used_names.insert(name.clone()) {
+            name = format!("{}-{}.html", stem, n);
+            n += 1;
+        }
+        name
+    }
+}
+
+impl ThreadWriter for TakeoutWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        let file_name = self.file_name(&thread);
+        let path = self.dir.join(file_name);
+        fs::write(&path, to_html(&thread)).with_context(||
+            format!("Failed to write Takeout file: {:?}", path)
+        )
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        info!("Takeout output complete. Total files written: {}", self.used_names.len());
+        Ok(())
+    }
+}
+
+// Render a thread as a Takeout conversation page: a `.hChatLog` of `.message` elements
+// (with a `.participants` header for groups) followed by the `.tags` labels
+pub fn to_html(thread: &Thread) -> String {
+    let others = other_participants(thread);
+    let title = if others.len() > 1 { "Group Conversation".to_string() } else { counterpart(thread, &others) };
+
+    let mut html = format!(
+        "<?xml version=\"1.0\" ?>\n<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\"><html xmlns=\"http://www.w3.org/1999/xhtml\"><head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=UTF-8\" />\n<title>{}</title>\n</head>\n<body><div class=\"hChatLog hfeed\">",
+        escape_html(&title)
+    );
+    if others.len() > 1 {
+        let cites: Vec<String> = others
+            .iter()
+            .map(|p| sender_cite(p, false))
+            .collect();
+        let _ = writeln!(html, "<div class=\"participants\">Group conversation with:\n{}</div>", cites.join(", "));
+    }
+
+    // Quoted replies repeat the sender and text of the message they point at
+    let by_id: HashMap<&str, (&str, &str)> = thread.messages
+        .iter()
+        .map(|m| (m.id.as_str(), (m.from.name.as_str(), m.body.text().unwrap_or(""))))
+        .collect();
+    for message in &thread.messages {
+        let class = match message.kind {
+            MessageKind::Regular => "message",
+            MessageKind::Reaction => "message reaction",
+            MessageKind::System => "message system",
+        };
+        let _ = write!(
+            html,
+            "<div class=\"{}\"><abbr class=\"dt\" title=\"{}\">{}</abbr>:\n{}:\n",
+            class,
+            message.timestamp.format(DATETIME_FORMAT),
+            message.timestamp.format("%b %-d, %Y, %-I:%M:%S %p UTC"),
+            sender_cite(&message.from, message.direction == Direction::Outgoing)
+        );
+        if let Some((name, text)) = message.reply_to.as_deref().and_then(|id| by_id.get(id)) {
+            let _ = writeln!(
+                html,
+                "<blockquote class=\"reply\"><cite>{}</cite><q>{}</q></blockquote>",
+                escape_html(name),
+                content_html(text)
+            );
+        }
+        if let Some(text) = message.body.text() {
+            let _ = writeln!(html, "<q>{}</q>", content_html(text));
+        }
+        for attachment in message.body.media() {
+            let src = escape_html(&attachment.src);
+            let _ = writeln!(html, "{}", match attachment.kind {
+                AttachmentKind::Image => format!("<img src=\"{}\" alt=\"\" />", src),
+                AttachmentKind::Video => format!("<a class=\"video\" href=\"{}\">Video attachment</a>", src),
+                AttachmentKind::Audio => format!("<a class=\"audio\" href=\"{}\">Audio attachment</a>", src),
+                AttachmentKind::Contact => format!("<a class=\"vcard\" href=\"{}\">Contact card attachment</a>", src),
+            });
+        }
+        html.push_str("</div>\n");
+    }
+    html.push_str("</div>\n");
+
+    if !thread.labels.is_empty() {
+        let tags: Vec<String> = thread.labels
+            .iter()
+            .map(|label| {
+                format!(
+                    "<a rel=\"tag\" href=\"http://www.google.com/voice#{}\">{}</a>",
+                    escape_html(&label.to_lowercase()),
+                    escape_html(label)
+                )
+            })
+            .collect();
+        let _ = writeln!(html, "<div class=\"tags\">Labels:\n{}</div>", tags.join(", "));
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+// Who a one-on-one page is with: the other participant, or for a note to self your own
+// number, which is what makes it read back as one
+fn counterpart(thread: &Thread, others: &[&Participant]) -> String {
+    if let Some(other) = others.first() {
+        return if other.name.is_empty() { other.phone.clone() } else { other.name.clone() };
+    }
+    thread.participants
+        .first()
+        .filter(|p| thread.is_self_thread && !p.phone.contains(':'))
+        .map(|p| p.phone.clone())
+        .unwrap_or_else(|| "Me".to_string())
+}
+
+// Participants other than yourself, judged by who sends the outgoing messages
+pub fn other_participants(thread: &Thread) -> Vec<&Participant> {
+    let me: HashSet<&str> = thread.messages
+        .iter()
+        .filter(|m| m.direction == Direction::Outgoing)
+        .map(|m| m.from.phone.as_str())
+        .collect();
+    let mut others: Vec<&Participant> = thread.participants
+        .iter()
+        .filter(|p| !me.contains(p.phone.as_str()) && p.name != "Me")
+        .collect();
+    others.sort_by(|a, b| (&a.name, &a.phone).cmp(&(&b.name, &b.phone)));
+    others
+}
+
+// Takeout marks your own name with `abbr.fn` and everyone else's with `span.fn`.
+// Placeholder and empty phones stand for a missing `tel:` link and are left out.
+fn sender_cite(participant: &Participant, is_self: bool) -> String {
+    let name = escape_html(&participant.name);
+    let name = if is_self {
+        format!("<abbr class=\"fn\" title=\"\">{}</abbr>", name)
+    } else {
+        format!("<span class=\"fn\">{}</span>", name)
+    };
+    let avatar = participant.avatar
+        .as_deref()
+        .map(|src| format!("<img class=\"photo\" src=\"{}\" alt=\"\" />", escape_html(src)))
+        .unwrap_or_default();
+    // `Unknown` or a `--unknown-phone-placeholder` without digits; a numeric placeholder is
+    // written as a number and reads back the same. `unknown:<hash>` and `me:<hash>` aren't
+    // numbers at all.
+    let has_phone =
+        !participant.phone.contains(':') &&
+        participant.phone.chars().any(|c| c.is_ascii_digit());
+    if has_phone {
+        // Other numbers of a multi-number contact card follow as links of their own
+        let alternates: String = participant.alternate_phones
+            .iter()
+            .map(|phone| format!("<a class=\"tel\" href=\"tel:{}\"></a>", escape_html(phone)))
+            .collect();
+        format!(
+            "<cite class=\"sender vcard\">{}<a class=\"tel\" href=\"tel:{}\">{}</a>{}</cite>",
+            avatar,
+            escape_html(&participant.phone),
+            name,
+            alternates
+        )
+    } else {
+        format!("<cite class=\"sender vcard\">{}{}</cite>", avatar, name)
+    }
+}
+
+// Message text as Takeout writes it, with line breaks as `<br>`. Trailing line breaks
+// stay literal, since the parser drops the trailing `<br>`s Takeout tends to add.
+fn content_html(text: &str) -> String {
+    let body = text.trim_end_matches('\n');
+    escape_html(body).replace('\n', "<br>") + &text[body.len()..]
+}
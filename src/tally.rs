@@ -0,0 +1,109 @@
+use std::collections::{ BTreeMap, BTreeSet, HashSet };
+use std::fmt::Write as _;
+use crate::per_contact::contact_key;
+use crate::takeout_writer::other_participants;
+use crate::{ MessageKind, Thread };
+
+// Contacts listed before the rest are summarized
+const MAX_LISTED: usize = 50;
+
+// `--participant-summary`: every contact of the run with the messages they sent and
+// received and each name they appeared under. Contacts are merged by phone digits as in
+// `--participants-only`; you aren't counted. Only regular messages count, as in
+// `message_count`. The totals are kept in ordered maps and only ever added to, so they
+// come out the same however many `--jobs` parse the files and in whatever order threads
+// arrive.
+#[derive(Default)]
+pub struct ParticipantTally {
+    contacts: BTreeMap<String, Tally>,
+}
+
+#[derive(Default)]
+struct Tally {
+    /// Lowest phone written for the contact, so the one shown doesn't depend on order
+    phone: String,
+    names: BTreeSet<String>,
+    threads: usize,
+    sent: usize,
+    received: usize,
+}
+
+impl ParticipantTally {
+    pub fn record(&mut self, thread: &Thread) {
+        let mut keys = HashSet::new();
+        for participant in other_participants(thread) {
+            let key = contact_key(participant);
+            let tally = self.contacts.entry(key.clone()).or_default();
+            if tally.phone.is_empty() || participant.phone < tally.phone {
+                tally.phone = participant.phone.clone();
+            }
+            if !participant.name.trim().is_empty() {
+                tally.names.insert(participant.name.clone());
+            }
+            // One contact listed under several names is in the thread once
+            if keys.insert(key) {
+                tally.threads += 1;
+            }
+        }
+        for message in thread.messages.iter().filter(|m| m.kind == MessageKind::Regular) {
+            let sender = contact_key(&message.from);
+            if let Some(tally) = self.contacts.get_mut(&sender).filter(|_| keys.contains(&sender)) {
+                tally.sent += 1;
+            }
+            let recipients: HashSet<String> = message.to.iter().map(contact_key).collect();
+            for recipient in recipients.iter().filter(|key| keys.contains(*key)) {
+                if let Some(tally) = self.contacts.get_mut(recipient) {
+                    tally.received += 1;
+                }
+            }
+        }
+    }
+
+    // Print the summary
+    pub fn finish(self) {
+        print!("{}", self.summary());
+    }
+
+    // The table of contacts with the most messages first, ties by key
+    pub fn summary(&self) -> String {
+        let mut ranked: Vec<(&String, &Tally)> = self.contacts.iter().collect();
+        ranked.sort_by(|a, b| (b.1.sent + b.1.received).cmp(&(a.1.sent + a.1.received)).then_with(|| a.0.cmp(b.0)));
+        let mut summary = String::from("\nMessages by participant:\n");
+        if ranked.is_empty() {
+            summary.push_str("  (none)\n");
+            return summary;
+        }
+        let width = ranked
+            .iter()
+            .take(MAX_LISTED)
+            .map(|(_, tally)| tally.phone.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("Phone".len());
+        let _ = writeln!(summary, "  {:<width$}  {:>7}  {:>4}  {:>8}  Names", "Phone", "Threads", "Sent", "Received", width = width);
+        for (_, tally) in ranked.iter().take(MAX_LISTED) {
+            let names: Vec<&str> = tally.names.iter().map(String::as_str).collect();
+            let _ = writeln!(
+                summary,
+                "  {:<width$}  {:>7}  {:>4}  {:>8}  {}",
+                tally.phone,
+                tally.threads,
+                tally.sent,
+                tally.received,
+                if names.is_empty() { "(no name)".to_string() } else { names.join(", ") },
+                width = width
+            );
+        }
+        if ranked.len() > MAX_LISTED {
+            let rest = &ranked[MAX_LISTED..];
+            let _ = writeln!(
+                summary,
+                "  ... and {} more contacts with {} messages sent and {} received",
+                rest.len(),
+                rest.iter().map(|(_, tally)| tally.sent).sum::<usize>(),
+                rest.iter().map(|(_, tally)| tally.received).sum::<usize>()
+            );
+        }
+        summary
+    }
+}
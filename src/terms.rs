@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use crate::per_contact::contact_key;
+use crate::{ MessageKind, Thread };
+
+// Common English words left out of the counts. Terms are compared with their
+// apostrophes removed, so contractions are listed that way too.
+const STOPWORDS: &[&str] = &[
+    "a", "about", "after", "again", "all", "also", "am", "an", "and", "any", "are", "as", "at",
+    "be", "because", "been", "before", "being", "but", "by", "can", "cant", "could", "did",
+    "didnt", "do", "does", "doesnt", "doing", "dont", "for", "from", "get", "got", "had", "has",
+    "have", "he", "her", "here", "hers", "him", "his", "how", "i", "id", "if", "ill", "im", "in",
+    "into", "is", "isnt", "it", "its", "ive", "just", "let", "lets", "like", "me", "my", "no",
+    "not", "now", "of", "off", "oh", "ok", "okay", "on", "one", "or", "our", "out", "over", "she",
+    "so", "some", "than", "that", "thats", "the", "their", "them", "then", "there", "theres",
+    "these", "they", "this", "those", "to", "too", "up", "us", "very", "was", "we", "were",
+    "what", "whats", "when", "where", "which", "who", "why", "will", "with", "would", "yeah",
+    "yes", "you", "youll", "your", "youre", "yours",
+];
+
+// `--top-terms`: word frequencies over the content of every regular message written in
+// the run, overall and optionally per sender (contacts merged by phone digits as in
+// `--participants-only`). Words are split at anything but letters, digits and
+// apostrophes, lowercased and stripped of apostrophes; stopwords, numbers and words shorter
+// than `min_length` characters are skipped.
+pub struct TermCounter {
+    top: usize,
+    min_length: usize,
+    counts: HashMap<String, usize>,
+    /// Each sender's counts, by contact key
+    by_participant: Option<HashMap<String, SenderTerms>>,
+}
+
+struct SenderTerms {
+    name: String,
+    phone: String,
+    counts: HashMap<String, usize>,
+}
+
+impl TermCounter {
+    pub fn new(top: usize, min_length: usize, by_participant: bool) -> Self {
+        TermCounter {
+            top,
+            min_length,
+            counts: HashMap::new(),
+            by_participant: by_participant.then(HashMap::new),
+        }
+    }
+
+    pub fn record(&mut self, thread: &Thread) {
+        for message in &thread.messages {
+            if message.kind != MessageKind::Regular {
+                continue;
+            }
+            let Some(content) = message.body.text() else {
+                continue;
+            };
+            let mut sender_counts = self.by_participant.as_mut().map(|by_participant| {
+                &mut by_participant
+                    .entry(contact_key(&message.from))
+                    .or_insert_with(|| SenderTerms {
+                        name: message.from.name.clone(),
+                        phone: message.from.phone.clone(),
+                        counts: HashMap::new(),
+                    }).counts
+            });
+            for term in terms(content, self.min_length) {
+                if let Some(counts) = sender_counts.as_mut() {
+                    *counts.entry(term.clone()).or_default() += 1;
+                }
+                *self.counts.entry(term).or_default() += 1;
+            }
+        }
+    }
+
+    // Print the top terms, then each sender's by name
+    pub fn finish(self) {
+        println!("\nTop terms:");
+        print_top(&self.counts, self.top);
+        let Some(by_participant) = self.by_participant else {
+            return;
+        };
+        let mut senders: Vec<_> = by_participant.into_values().collect();
+        senders.sort_by(|a, b| (&a.name, &a.phone).cmp(&(&b.name, &b.phone)));
+        for SenderTerms { name, phone, counts } in senders {
+            if name.trim().is_empty() {
+                println!("\nTop terms of {}:", phone);
+            } else {
+                println!("\nTop terms of {} ({}):", name, phone);
+            }
+            print_top(&counts, self.top);
+        }
+    }
+}
+
+// Most frequent first, ties in alphabetical order
+fn print_top(counts: &HashMap<String, usize>, top: usize) {
+    let mut ranked: Vec<(&String, &usize)> = counts.iter().collect();
+    ranked.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    if ranked.is_empty() {
+        println!("  (none)");
+    }
+    for (term, count) in ranked.into_iter().take(top) {
+        println!("  {:>6}  {}", count, term);
+    }
+}
+
+fn terms(content: &str, min_length: usize) -> impl Iterator<Item = String> + '_ {
+    content
+        .split(|c: char| !c.is_alphanumeric() && c != '\'' && c != '’')
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(move |term| {
+            term.chars().count() >= min_length.max(1) &&
+                !term.chars().all(|c| c.is_numeric()) &&
+                !STOPWORDS.contains(&term.as_str())
+        })
+}
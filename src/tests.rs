@@ -0,0 +1,623 @@
+use clap::Parser;
+use std::path::Path;
+use crate::append::AppendWriter;
+use crate::tally::ParticipantTally;
+use crate::voicemail::Placeholders;
+use crate::writer::ThreadWriter;
+use crate::{
+    parse_html,
+    parse_threads,
+    Cli,
+    is_conversation_file,
+    AttachmentKind,
+    Direction,
+    IcsEvents,
+    JsonCase,
+    OutputFormat,
+    OutputOptions,
+    ParseOptions,
+    ParseWarning,
+    ParticipantRole,
+    Thread,
+    ThreadFilter,
+};
+
+// Parse options as the command line would give them for `args`
+fn options(args: &[&str]) -> ParseOptions {
+    let cli = Cli::parse_from(["google_voice_importer", "test"].into_iter().chain(args.iter().copied()));
+    ParseOptions::from_cli(&cli).unwrap()
+}
+
+// The threads of one of the fixtures in `test/`
+fn fixture(name: &str, options: &ParseOptions) -> Vec<Thread> {
+    parse_threads(&Path::new("test").join(name), options).unwrap()
+}
+
+// A one-on-one export holding `messages`, as Takeout writes them
+fn export(title: &str, messages: &str) -> String {
+    format!(
+        "<html><head><title>{}</title></head><body><div class=\"hChatLog hfeed\">{}</div></body></html>",
+        title,
+        messages
+    )
+}
+
+fn message_from_me(tel: &str, time: &str, text: &str) -> String {
+    format!(
+        "<div class=\"message\"><abbr class=\"dt\" title=\"{}\">{}</abbr>: \
+         <cite class=\"sender vcard\"><a class=\"tel\" href=\"tel:{}\"><abbr class=\"fn\" title=\"\">Me</abbr></a></cite>: \
+         <q>{}</q></div>",
+        time,
+        time,
+        tel,
+        text
+    )
+}
+
+#[test]
+fn note_to_self_is_named_for_your_number() {
+    let threads = fixture("+16502074150 - Text - 2024-01-10T03_42_10Z.html", &options(&[]));
+    let thread = &threads[0];
+    assert!(thread.is_self_thread);
+    for message in &thread.messages {
+        assert_eq!(message.direction, Direction::Outgoing);
+        assert_eq!(message.to.len(), 1);
+        assert_eq!(message.to[0].phone, "+16502074150");
+    }
+}
+
+#[test]
+fn unanswered_conversation_is_not_a_note_to_self() {
+    let html = export(
+        "Bob Jones",
+        &[
+            message_from_me("+16502074150", "2024-01-09T19:42:10.518-08:00", "Are we still on for Friday?"),
+            message_from_me("+16502074150", "2024-01-11T08:03:55.102-08:00", "Bob?"),
+        ].concat()
+    );
+    let path = Path::new("Bob Jones - Text - 2024-01-10T03_42_10Z.html");
+    let threads = parse_html(&html, path, &options(&[])).unwrap();
+    let thread = &threads[0];
+    assert!(!thread.is_self_thread);
+    let bob = thread.participants
+        .iter()
+        .find(|p| p.name == "Bob Jones")
+        .expect("the contact the file is named for is a participant");
+    for message in &thread.messages {
+        assert_eq!(message.direction, Direction::Outgoing);
+        assert_eq!(message.to.len(), 1);
+        assert_eq!(message.to[0].phone, bob.phone);
+    }
+}
+
+#[test]
+fn unknown_self_phone_is_a_placeholder_per_file() {
+    let options = options(&[]);
+    let mut self_phones = Vec::new();
+    for name in ["Hana Kim - Text - 2021-05-20T19_03_09Z.html", "Sam Okafor - Text - 2024-02-02T20_10_44Z.html"] {
+        let thread = &fixture(name, &options)[0];
+        assert!(!thread.self_phone_known);
+        let mine: Vec<&str> = thread.messages
+            .iter()
+            .flat_map(|m| std::iter::once(&m.from).chain(&m.to))
+            .filter(|p| p.name == "Me")
+            .map(|p| p.phone.as_str())
+            .collect();
+        assert!(!mine.is_empty());
+        assert!(mine.iter().all(|phone| phone.starts_with("me:") && *phone == mine[0]));
+        self_phones.push(mine[0].to_string());
+    }
+    assert_ne!(self_phones[0], self_phones[1]);
+}
+
+#[test]
+fn note_to_self_reads_back_from_takeout() {
+    let options = options(&[]);
+    let thread = &fixture("+16502074150 - Text - 2024-01-10T03_42_10Z.html", &options)[0];
+    let html = crate::takeout_writer::to_html(thread);
+    let read_back = &parse_html(&html, Path::new("written.html"), &options).unwrap()[0];
+    assert!(read_back.is_self_thread);
+    assert_eq!(read_back.messages.len(), thread.messages.len());
+}
+
+fn message_from(name: &str, tel: &str, time: &str, text: &str) -> String {
+    format!(
+        "<div class=\"message\"><abbr class=\"dt\" title=\"{}\">{}</abbr>: \
+         <cite class=\"sender vcard\"><a class=\"tel\" href=\"tel:{}\"><span class=\"fn\">{}</span></a></cite>: \
+         <q>{}</q></div>",
+        time,
+        time,
+        tel,
+        name,
+        text
+    )
+}
+
+#[test]
+fn number_under_another_name_is_flagged() {
+    let html = export(
+        "Alice",
+        &[
+            message_from("Alice", "+14155550101", "2024-01-09T19:42:10.518-08:00", "Hi"),
+            message_from_me("+16502074150", "2024-01-09T19:43:10.518-08:00", "Hi Alice"),
+            message_from("Bob", "+14155550101", "2024-01-09T19:44:10.518-08:00", "It's Bob on her phone"),
+        ].concat()
+    );
+    let threads = parse_html(&html, Path::new("Alice - Text - 2024-01-10T03_42_10Z.html"), &options(&[])).unwrap();
+    let conflicts: Vec<_> = threads[0].warnings
+        .iter()
+        .filter_map(|w| match w {
+            ParseWarning::SenderNameConflict { message_index, name, attributed_to, .. } =>
+                Some((*message_index, name.as_str(), attributed_to.as_str())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(conflicts, [(2, "Bob", "Alice")]);
+}
+
+#[test]
+fn other_sender_with_your_number_is_flagged() {
+    let html = export(
+        "Alice",
+        &[
+            message_from_me("+16502074150", "2024-01-09T19:42:10.518-08:00", "Hi"),
+            message_from("Alice", "+16502074150", "2024-01-09T19:43:10.518-08:00", "Hello"),
+        ].concat()
+    );
+    let threads = parse_html(&html, Path::new("Alice - Text - 2024-01-10T03_42_10Z.html"), &options(&[])).unwrap();
+    let flagged: Vec<usize> = threads[0].warnings
+        .iter()
+        .filter_map(|w| match w {
+            ParseWarning::SenderHasSelfPhone { message_index, .. } => Some(*message_index),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(flagged, [1]);
+}
+
+#[test]
+fn thread_ids_tell_sources_and_conversations_apart() {
+    let options = options(&[]);
+    let messages = [
+        message_from("Alice", "+14155550101", "2024-01-09T19:42:10.518-08:00", "Hi"),
+        message_from_me("+16502074150", "2024-01-09T19:43:10.518-08:00", "Hi Alice"),
+    ].concat();
+    let html = export("Alice", &messages);
+    let id = |html: &str, name: &str| -> Vec<String> {
+        parse_html(html, Path::new(name), &options)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.thread_id)
+            .collect()
+    };
+    let first = id(&html, "Alice - Text - 2024-01-10T03_42_10Z.html");
+    // Stable across runs and directories
+    assert_eq!(first, id(&html, "Takeout/Voice/Calls/Alice - Text - 2024-01-10T03_42_10Z.html"));
+    // Another dated file of the same contact
+    assert_ne!(first, id(&html, "Alice - Text - 2024-02-11T09_00_00Z.html"));
+    // Two conversations with the same people in one file
+    let concatenated = html.replace("</div></body>", &format!("</div><div class=\"hChatLog hfeed\">{}</div></body>", messages));
+    let ids = id(&concatenated, "Alice - Text - 2024-01-10T03_42_10Z.html");
+    assert_eq!(ids.len(), 2);
+    assert_eq!(ids[0], first[0]);
+    assert_ne!(ids[0], ids[1]);
+}
+
+#[test]
+fn output_format_follows_the_extension() {
+    for (path, format) in [
+        ("out.json", OutputFormat::Json),
+        ("out.ndjson", OutputFormat::Ndjson),
+        ("out.JSONL", OutputFormat::Ndjson),
+        ("out.csv", OutputFormat::Csv),
+        ("out.md", OutputFormat::Markdown),
+    ] {
+        assert_eq!(OutputFormat::from_extension(Path::new(path)), Some(format), "{}", path);
+    }
+    assert_eq!(OutputFormat::from_extension(Path::new("out.txt")), None);
+    assert_eq!(OutputFormat::from_extension(Path::new("out")), None);
+}
+
+#[test]
+fn csv_has_a_row_per_message() {
+    let thread = &fixture("Dana Whitfield - Text - 2023-03-04T17_15_22Z.html", &options(&[]))[0];
+    let rows = crate::csv_writer::csv_rows(thread);
+    assert!(thread.messages.len() > 1);
+    assert_eq!(rows.matches(thread.thread_id.as_str()).count(), thread.messages.len());
+}
+
+fn output_options(format: OutputFormat) -> OutputOptions {
+    OutputOptions {
+        format,
+        compact_json: false,
+        json_case: JsonCase::Snake,
+        approx_participants: false,
+        embed_media: None,
+        collapse: None,
+        ics_events: IcsEvents::Day,
+        tz: chrono_tz::UTC,
+        sample: None,
+        split_by: None,
+        transcript: false,
+    }
+}
+
+// Write `threads` with `--state`, resuming with `--append` when `append` is set
+fn write_resumable(dir: &Path, format: OutputFormat, threads: &[Thread], append: bool) {
+    let writer = AppendWriter::open(&dir.join("out"), &dir.join("state"), append, output_options(format)).unwrap();
+    let mut writer: Box<dyn ThreadWriter> = Box::new(writer);
+    for thread in threads {
+        writer.write_thread(thread.clone()).unwrap();
+    }
+    writer.finish().unwrap();
+}
+
+#[test]
+fn append_resumes_repeated_threads_and_keeps_one_csv_header() {
+    let options = options(&[]);
+    let dana = fixture("Dana Whitfield - Text - 2023-03-04T17_15_22Z.html", &options).remove(0);
+    let ed = fixture("Ed Clark - Text - 2019-11-01T17_14_03Z.html", &options).remove(0);
+    // The same file read twice gives two threads with one key
+    let run = [dana.clone(), dana, ed];
+    for format in [OutputFormat::Json, OutputFormat::Ndjson, OutputFormat::Csv] {
+        let dir = std::env::temp_dir().join(format!("gvi-append-{:?}-{}", format, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_resumable(&dir, format, &run, false);
+        let uninterrupted = std::fs::read_to_string(dir.join("out")).unwrap();
+        // Interrupted after the first thread, then resumed with the whole run again
+        write_resumable(&dir, format, &run[..1], false);
+        write_resumable(&dir, format, &run, true);
+        let resumed = std::fs::read_to_string(dir.join("out")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(resumed, uninterrupted, "{:?}", format);
+        assert!(resumed.matches(run[0].thread_id.as_str()).count() >= 2, "{:?}", format);
+        if format == OutputFormat::Csv {
+            assert_eq!(resumed.matches("timestamp,thread_id").count(), 1);
+        }
+    }
+}
+
+#[test]
+fn directory_walk_only_reads_thread_json() {
+    let dir = std::env::temp_dir().join(format!("gvi-walk-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let threads = fixture("Ed Clark - Text - 2019-11-01T17_14_03Z.html", &options(&[]));
+    let json = serde_json::to_string_pretty(&threads[0]).unwrap();
+    std::fs::write(dir.join("export.json"), &json).unwrap();
+    std::fs::write(dir.join("array.json"), format!("[\n{}\n]", json)).unwrap();
+    std::fs::write(dir.join("package.json"), "{\"name\": \"site\"}").unwrap();
+    // Left behind by an earlier run that wrote the same files as this one
+    std::fs::write(dir.join("checksums.json"), &json).unwrap();
+    std::fs::write(dir.join("out-0001.json"), &json).unwrap();
+    let checksums = dir.join("checksums.json");
+    let prefix = dir.join("out");
+    let options = options(&["--checksums", checksums.to_str().unwrap(), "--chunk-output", prefix.to_str().unwrap()]);
+    let mut found: Vec<String> = crate::expand_files(std::slice::from_ref(&dir), &options)
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    found.sort();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(found, ["array.json", "export.json"]);
+}
+
+const VOICEMAIL: &str = "Priya Nair - Voicemail - 2023-09-04T16_02_45Z.html";
+const UNTRANSCRIBED_VOICEMAIL: &str = "+13125550177 - Voicemail - 2023-10-02T13_15_08Z.html";
+
+#[test]
+fn voicemail_is_an_incoming_message_with_its_recording() {
+    let path = Path::new("test").join(VOICEMAIL);
+    assert!(!is_conversation_file(&path, &options(&[])));
+    let options = options(&["--voicemails"]);
+    assert!(is_conversation_file(&path, &options));
+    let threads = fixture(VOICEMAIL, &options);
+    assert_eq!(threads.len(), 1);
+    let thread = &threads[0];
+    assert_eq!(thread.message_count, 1);
+    assert!(thread.warnings.is_empty());
+    assert_eq!(thread.labels, ["Voicemail", "Unread"]);
+    let message = &thread.messages[0];
+    assert_eq!(message.direction, Direction::Incoming);
+    assert_eq!(message.from.name, "Priya Nair");
+    assert_eq!(message.from.phone, "+14155550162");
+    assert!(message.to[0].phone.starts_with("me:"));
+    assert_eq!(
+        message.body.text(),
+        Some("Hey, it's Priya. The movers come Saturday at nine,\ncall me back when you can.")
+    );
+    let media = message.body.media();
+    assert_eq!(media.len(), 1);
+    assert_eq!(media[0].kind, AttachmentKind::Audio);
+    assert_eq!(media[0].src, "Priya Nair - Voicemail - 2023-09-04T16_02_45Z.mp3");
+}
+
+#[test]
+fn skip_untranscribed_drops_placeholder_voicemails() {
+    let options = options(&["--voicemails"]);
+    let untranscribed = fixture(UNTRANSCRIBED_VOICEMAIL, &options);
+    assert!(matches!(
+        untranscribed[0].warnings.as_slice(),
+        [ParseWarning::UntranscribedVoicemail { transcription: Some(text) }] if text == "(Unable to transcribe)"
+    ));
+    let mut filter = ThreadFilter::new(&[], false, None, &[], 0, true);
+    assert!(filter.keep(&fixture(VOICEMAIL, &options)[0]));
+    assert!(!filter.keep(&untranscribed[0]));
+    assert_eq!(filter.untranscribed, 1);
+    let mut keep_all = ThreadFilter::new(&[], false, None, &[], 0, false);
+    assert!(keep_all.keep(&untranscribed[0]));
+}
+
+#[test]
+fn placeholders_can_be_added_for_other_languages() {
+    let builtin = Placeholders::default();
+    assert!(builtin.is_untranscribed(None));
+    assert!(builtin.is_untranscribed(Some("  ")));
+    assert!(builtin.is_untranscribed(Some("(Unable to transcribe)")));
+    assert!(builtin.is_untranscribed(Some("Transcription not available.")));
+    assert!(!builtin.is_untranscribed(Some("(Transcription impossible)")));
+    assert!(!builtin.is_untranscribed(Some("Call me back")));
+    let french = Placeholders::new(&["Transcription impossible.".to_string()]);
+    assert!(french.is_untranscribed(Some("(transcription impossible)")));
+    assert!(french.is_untranscribed(Some("(Unable to transcribe)")));
+}
+
+// Every thread of a directory run over `test/`
+fn directory_run(options: &ParseOptions) -> Vec<Thread> {
+    crate::expand_files(&[Path::new("test").to_path_buf()], options)
+        .iter()
+        .flat_map(|path| parse_threads(path, options).unwrap())
+        .collect()
+}
+
+#[test]
+fn either_me_phone_makes_a_message_outgoing() {
+    let accounts = ["+16502074150", "+14155550177"];
+    let threads = directory_run(&options(&["--me-phone", accounts[0], "--me-phone", accounts[1]]));
+    let mut sent_from = [0, 0];
+    for message in threads.iter().flat_map(|t| &t.messages) {
+        if let Some(account) = accounts.iter().position(|phone| message.from.phone == *phone) {
+            assert_eq!(message.direction, Direction::Outgoing, "{:?}", message.body.text());
+            sent_from[account] += 1;
+        }
+    }
+    assert!(sent_from.iter().all(|&count| count > 0), "{:?}", sent_from);
+
+    // The second account's messages are someone else's without its number
+    let threads = directory_run(&options(&["--me-phone", accounts[0]]));
+    let work_line = threads
+        .iter()
+        .flat_map(|t| &t.messages)
+        .find(|m| m.from.phone == accounts[1])
+        .unwrap();
+    assert_eq!(work_line.direction, Direction::Incoming);
+}
+
+#[test]
+fn labels_keep_colons_and_come_from_every_tags_element() {
+    let threads = fixture("Yuki Tanaka - Text - 2022-02-14T09_30_00Z.html", &options(&[]));
+    assert_eq!(threads[0].labels, ["Text", "Project: Apollo", "Inbox", "Starred"]);
+}
+
+#[test]
+fn br_line_breaks_are_kept_in_content() {
+    let threads = fixture("Mateo Silva - Text - 2023-04-22T18_45_10Z.html", &options(&[]));
+    let texts: Vec<Option<&str>> = threads[0].messages
+        .iter()
+        .map(|m| m.body.text())
+        .collect();
+    assert_eq!(texts[0], Some("Grocery list for tonight:\n- tortillas\n- limes\n- cilantro\n\nAnything else?"));
+    assert_eq!(texts[1], Some("Sour cream too\nThanks!"));
+}
+
+#[test]
+fn tied_timestamps_keep_document_order() {
+    let name = "Group Conversation - 2024-01-01T08_00_05Z.html";
+    let threads = fixture(name, &options(&["--sort-messages"]));
+    let thread = &threads[0];
+    let texts: Vec<&str> = thread.messages
+        .iter()
+        .map(|m| m.body.text().unwrap())
+        .collect();
+    assert_eq!(texts, [
+        "Happy new year!",
+        "Hope 2024 treats you both well",
+        "Happy new year, all",
+        "Jonas, fireworks were great from here",
+        "Happy new year to you too!",
+    ]);
+    for _ in 0..5 {
+        let again = fixture(name, &options(&["--sort-messages"]));
+        assert_eq!(serde_json::to_string(&again[0]).unwrap(), serde_json::to_string(thread).unwrap());
+    }
+    // Jonas wrote at the same second as you, which doesn't make it a reply
+    let jonas = thread.participant_stats
+        .iter()
+        .find(|stats| stats.phone == "+12065550182")
+        .unwrap();
+    assert_eq!(jonas.role, ParticipantRole::CoInitiator);
+    assert_eq!(jonas.median_reply_secs, None);
+}
+
+#[test]
+fn participant_summary_is_the_same_for_any_number_of_jobs() {
+    let summaries: Vec<String> = ["1", "8"]
+        .into_iter()
+        .map(|jobs| {
+            let options = options(&["--jobs", jobs, "--sort-files", "none"]);
+            let files = crate::expand_files(&[Path::new("test").to_path_buf()], &options);
+            let mut tally = ParticipantTally::default();
+            crate::parse_concurrently(&files, &options, |_, parsed| {
+                parsed?.iter().for_each(|thread| tally.record(thread));
+                Ok(())
+            }).unwrap();
+            tally.summary()
+        })
+        .collect();
+    assert!(summaries[0].contains("+16175550144"), "{}", summaries[0]);
+    assert_eq!(summaries[0], summaries[1]);
+}
+
+#[test]
+fn indices_are_contiguous_from_zero() {
+    let mut next = 0;
+    let mut written = Vec::new();
+    for mut thread in directory_run(&options(&[])) {
+        crate::assign_indices(&mut thread, &mut next);
+        written.push(serde_json::to_value(&thread).unwrap());
+    }
+    assert!(written.len() > 1);
+    for (thread_index, thread) in written.iter().enumerate() {
+        assert_eq!(thread["thread_index"], thread_index);
+        let messages = thread["messages"].as_array().unwrap();
+        for (message_index, message) in messages.iter().enumerate() {
+            assert_eq!(message["message_index"], message_index);
+        }
+    }
+}
+
+const SELF_PHONE_FIXTURE: &str = "Farah Aziz - Text - 2024-07-21T16_40_12Z.html";
+
+// The senders of your messages in the first thread
+fn outgoing_phones(threads: &[Thread]) -> Vec<&str> {
+    threads[0].messages
+        .iter()
+        .filter(|m| m.direction == Direction::Outgoing)
+        .map(|m| m.from.phone.as_str())
+        .collect()
+}
+
+#[test]
+fn me_phone_is_stamped_on_all_of_your_messages() {
+    // One of your two messages links the export's number, which the other one takes too
+    let threads = fixture(SELF_PHONE_FIXTURE, &options(&[]));
+    assert_eq!(outgoing_phones(&threads), ["+16502074150"; 2]);
+    let threads = fixture(SELF_PHONE_FIXTURE, &options(&["--me-phone", "+16505550100"]));
+    assert_eq!(outgoing_phones(&threads), ["+16505550100"; 2]);
+    assert_eq!(threads[0].participants[1].phone, "+16505550100");
+
+    let path = Path::new("test").join(SELF_PHONE_FIXTURE);
+    let unlinked = std::fs::read_to_string(&path)
+        .unwrap()
+        .replace(
+            "<a class=\"tel\" href=\"tel:+16502074150\"><abbr class=\"fn\" title=\"\">Me</abbr></a>",
+            "<abbr class=\"fn\" title=\"\">Me</abbr>"
+        );
+    let threads = parse_html(&unlinked, &path, &options(&[])).unwrap();
+    let phones = outgoing_phones(&threads);
+    assert!(phones[0].starts_with("me:"), "{:?}", phones);
+    assert_eq!(phones[0], phones[1]);
+    assert!(!threads[0].self_phone_known);
+    let threads = parse_html(&unlinked, &path, &options(&["--me-phone", "+16505550100"])).unwrap();
+    assert_eq!(outgoing_phones(&threads), ["+16505550100"; 2]);
+    assert!(threads[0].self_phone_known);
+}
+
+#[test]
+fn repeated_messages_are_dropped_unless_kept() {
+    let name = "Yusuf Demir - Text - 2024-08-03T13_15_02Z.html";
+    let threads = fixture(name, &options(&[]));
+    assert_eq!(threads[0].message_count, 5);
+    assert!(matches!(threads[0].warnings.as_slice(), [ParseWarning::RepeatedMessages { dropped: 2 }]));
+    let threads = fixture(name, &options(&["--keep-intra-duplicates"]));
+    assert_eq!(threads[0].message_count, 7);
+    assert!(threads[0].warnings.is_empty());
+}
+
+#[test]
+fn windows_exports_parse_like_unix_ones() {
+    let name = "Tomas Visser - Text - 2023-02-11T18_45_03Z.html";
+    let raw = std::fs::read_to_string(Path::new("test").join(name)).unwrap();
+    assert!(raw.starts_with('\u{feff}') && raw.contains("\r\n"));
+    let threads = fixture(name, &options(&[]));
+    let thread = &threads[0];
+    assert_eq!(thread.participants[0].name, "Tomas Visser");
+    for participant in &thread.participants {
+        assert!(!participant.name.starts_with('\u{feff}'), "{:?}", participant.name);
+    }
+    for message in &thread.messages {
+        assert!(!message.from.name.starts_with('\u{feff}'), "{:?}", message.from.name);
+        assert!(!message.body.text().unwrap_or("").contains('\r'), "{:?}", message.body.text());
+    }
+    assert_eq!(thread.messages[0].body.text(), Some("Exported this on my Windows laptop\nhope it reads fine"));
+}
+
+#[test]
+fn missing_timestamps_are_told_from_unparseable_ones() {
+    let missing = "Kofi Mensah - Text - 2023-08-12T14_05_31Z.html";
+    let unparseable = "Ines Moreau - Text - 2023-03-27T06_14_02Z.html";
+    let threads = fixture(missing, &options(&[]));
+    assert!(matches!(
+        threads[0].warnings.as_slice(),
+        [ParseWarning::MissingTimestamp { message_index: 1, .. }]
+    ));
+    assert_eq!(threads[0].messages.len(), 3);
+    let threads = fixture(unparseable, &options(&[]));
+    assert!(matches!(
+        threads[0].warnings.as_slice(),
+        [
+            ParseWarning::InvalidTimestamp { message_index: 1, title: Some(title), .. },
+            ParseWarning::InvalidTimestamp { message_index: 2, title: None, .. },
+        ] if title == "27/03/2023 08:20"
+    ));
+
+    // Only messages without a timestamp element are dropped
+    let dropping = options(&["--drop-undated"]);
+    let threads = fixture(missing, &dropping);
+    assert_eq!(threads[0].messages.len(), 2);
+    assert_eq!(threads[0].message_count, 2);
+    assert!(threads[0].messages.iter().all(|m| m.timestamp.timestamp() != 0));
+    assert_eq!(fixture(unparseable, &dropping)[0].messages.len(), 3);
+}
+
+#[test]
+fn sort_messages_orders_by_time_and_keeps_ties_in_document_order() {
+    let name = "Priya Raman - Text - 2022-07-09T22_01_02Z.html";
+    let texts = |threads: &[Thread]| -> Vec<Option<String>> {
+        threads[0].messages
+            .iter()
+            .map(|m| m.body.text().map(str::to_string))
+            .collect()
+    };
+    let in_document = texts(&fixture(name, &options(&[])));
+    assert_eq!(in_document[0].as_deref(), Some("Are we still on for dinner?"));
+    assert_eq!(in_document[4].as_deref(), Some("Hey!"));
+
+    let threads = fixture(name, &options(&["--sort-messages"]));
+    let timestamps: Vec<_> = threads[0].messages
+        .iter()
+        .map(|m| m.timestamp)
+        .collect();
+    assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", timestamps);
+    let sorted = texts(&threads);
+    assert_eq!(sorted, [
+        Some("Hey!".to_string()),
+        Some("Are we still on for dinner?".to_string()),
+        None,
+        Some("Yes! Here's the place".to_string()),
+        Some("Reservation is at 7:30".to_string()),
+    ]);
+}
+
+#[test]
+fn moving_an_export_keeps_its_ids_and_placeholders() {
+    let name = "Unknown sender - Text - 2021-02-17T14_30_00Z.html";
+    let dir = std::env::temp_dir().join(format!("gvi-moved-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy(Path::new("test").join(name), dir.join(name)).unwrap();
+    let options = options(&[]);
+    let moved = parse_threads(&dir.join(name), &options).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let original = fixture(name, &options);
+    let ids = |threads: &[Thread]| -> Vec<String> {
+        threads
+            .iter()
+            .flat_map(|t| {
+                std::iter::once(t.thread_id.clone())
+                    .chain(t.participants.iter().map(|p| p.phone.clone()))
+                    .chain(t.messages.iter().map(|m| m.id.clone()))
+            })
+            .collect()
+    };
+    assert!(ids(&original).iter().any(|id| id.starts_with("unknown:")), "{:?}", ids(&original));
+    assert_eq!(ids(&moved), ids(&original));
+}
@@ -0,0 +1,207 @@
+use anyhow::{ Context, Result };
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{ self, File };
+use std::io::{ BufRead, BufReader, BufWriter, Lines, Write };
+use std::path::{ Path, PathBuf };
+use crate::manifest::csv_field;
+use crate::timestamp;
+use crate::writer::to_json;
+use crate::{ is_group_thread, Message, OutputFormat, OutputOptions, Thread };
+use log::info;
+
+// Messages held in memory before they are sorted and spilled to a run file
+const RUN_SIZE: usize = 100_000;
+
+pub const CSV_HEADER: &str =
+    "timestamp,thread_id,source_path,from_name,from_phone,to,direction,kind,content,thread_participants,is_group";
+
+// `--timeline`: every message of the run in one file in time order, each with the
+// context of its thread, as NDJSON or CSV by the path's extension. Rows are formatted as
+// threads arrive and collected `RUN_SIZE` at a time; each full batch is sorted and
+// spilled to a run file next to the output, and when the run finishes the runs are
+// merged into the timeline. Memory use is therefore bounded by one batch whatever the
+// size of the archive, at the cost of about the timeline's size in temporary files.
+// Messages with equal times keep the order they arrived in.
+pub struct Timeline {
+    path: PathBuf,
+    format: TimelineFormat,
+    options: OutputOptions,
+    /// Time in milliseconds and formatted row of the messages not yet spilled
+    batch: Vec<(i64, String)>,
+    runs: Vec<PathBuf>,
+    messages: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TimelineFormat {
+    Csv,
+    Ndjson,
+}
+
+#[derive(Serialize)]
+struct TimelineMessage<'a> {
+    thread_id: &'a str,
+    source_path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread_index: Option<usize>,
+    thread_participants: &'a [&'a str],
+    is_group: bool,
+    #[serde(flatten)]
+    message: &'a Message,
+}
+
+impl Timeline {
+    pub fn create(path: &Path, options: OutputOptions) -> Result<Self> {
+        let format = match OutputFormat::from_extension(path) {
+            Some(OutputFormat::Csv) => TimelineFormat::Csv,
+            Some(OutputFormat::Ndjson) => TimelineFormat::Ndjson,
+            _ => anyhow::bail!("--timeline writes NDJSON or CSV, so {:?} needs a .ndjson, .jsonl or .csv extension", path),
+        };
+        // Fail before the run rather than after it if the path can't be written
+        File::create(path).with_context(|| format!("Failed to create timeline: {:?}", path))?;
+        Ok(Timeline {
+            path: path.to_path_buf(),
+            format,
+            // Every line is one message, whatever `--compact` says
+            options: OutputOptions { compact_json: true, ..options },
+            batch: Vec::new(),
+            runs: Vec::new(),
+            messages: 0,
+        })
+    }
+
+    pub fn record(&mut self, thread: &Thread) -> Result<()> {
+        let participants = participant_labels(thread);
+        let is_group = is_group_thread(thread);
+        for message in &thread.messages {
+            let row = match self.format {
+                TimelineFormat::Ndjson => {
+                    let entry = TimelineMessage {
+                        thread_id: &thread.thread_id,
+                        source_path: &thread.source_path,
+                        thread_index: thread.thread_index,
+                        thread_participants: &participants,
+                        is_group,
+                        message,
+                    };
+                    to_json(&entry, &self.options)?
+                }
+                TimelineFormat::Csv => csv_row(thread, message, &participants, is_group),
+            };
+            self.batch.push((message.timestamp.timestamp_millis(), row));
+            self.messages += 1;
+            if self.batch.len() >= RUN_SIZE {
+                self.spill()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        let file = File::create(&self.path).with_context(|| format!("Failed to create timeline: {:?}", self.path))?;
+        let mut out = BufWriter::new(file);
+        if self.format == TimelineFormat::Csv {
+            writeln!(out, "{}", CSV_HEADER)?;
+        }
+        if self.runs.is_empty() {
+            // Everything fit in one batch, so there is nothing to merge
+            self.batch.sort_by_key(|(timestamp, _)| *timestamp);
+            for (_, row) in &self.batch {
+                writeln!(out, "{}", row)?;
+            }
+        } else {
+            if !self.batch.is_empty() {
+                self.spill()?;
+            }
+            merge_runs(&self.runs, &mut out)?;
+            for run in &self.runs {
+                fs::remove_file(run).with_context(|| format!("Failed to remove {:?}", run))?;
+            }
+        }
+        out.flush().with_context(|| format!("Failed to write {:?}", self.path))?;
+        info!("Timeline complete. Total messages written: {}", self.messages);
+        Ok(())
+    }
+
+    // Sort the batch (stably, so equal times keep arrival order) into a new run file.
+    // A row is stored as its key and the row as a JSON string, as CSV rows can contain
+    // line breaks.
+    fn spill(&mut self) -> Result<()> {
+        self.batch.sort_by_key(|(timestamp, _)| *timestamp);
+        let mut run = self.path.as_os_str().to_owned();
+        run.push(format!(".run{}.partial", self.runs.len()));
+        let run = PathBuf::from(run);
+        let file = File::create(&run).with_context(|| format!("Failed to create {:?}", run))?;
+        let mut out = BufWriter::new(file);
+        for (timestamp, row) in self.batch.drain(..) {
+            writeln!(out, "{}\t{}", timestamp, serde_json::to_string(&row)?)?;
+        }
+        out.flush().with_context(|| format!("Failed to write {:?}", run))?;
+        self.runs.push(run);
+        Ok(())
+    }
+}
+
+// Merge the sorted runs, taking the earliest message next and on equal times the one
+// from the earlier run, which arrived first
+fn merge_runs(runs: &[PathBuf], out: &mut impl Write) -> Result<()> {
+    let mut readers: Vec<Lines<BufReader<File>>> = runs
+        .iter()
+        .map(|run| -> Result<_> {
+            Ok(BufReader::new(File::open(run).with_context(|| format!("Failed to read {:?}", run))?).lines())
+        })
+        .collect::<Result<_>>()?;
+    let mut heads = BinaryHeap::new();
+    for (index, reader) in readers.iter_mut().enumerate() {
+        if let Some((timestamp, row)) = next_row(reader)? {
+            heads.push(Reverse((timestamp, index, row)));
+        }
+    }
+    while let Some(Reverse((_, index, row))) = heads.pop() {
+        writeln!(out, "{}", row)?;
+        if let Some((timestamp, row)) = next_row(&mut readers[index])? {
+            heads.push(Reverse((timestamp, index, row)));
+        }
+    }
+    Ok(())
+}
+
+fn next_row(reader: &mut Lines<BufReader<File>>) -> Result<Option<(i64, String)>> {
+    let Some(line) = reader.next().transpose().context("Failed to read a timeline run")? else {
+        return Ok(None);
+    };
+    let (timestamp, row) = line.split_once('\t').context("Corrupt timeline run")?;
+    Ok(Some((timestamp.parse()?, serde_json::from_str(row)?)))
+}
+
+// How each participant is listed in `thread_participants`: by name, or by phone when
+// unnamed
+pub fn participant_labels(thread: &Thread) -> Vec<&str> {
+    thread.participants
+        .iter()
+        .map(|p| if p.name.is_empty() { p.phone.as_str() } else { p.name.as_str() })
+        .collect()
+}
+
+pub fn csv_row(thread: &Thread, message: &Message, participants: &[&str], is_group: bool) -> String {
+    let to: Vec<&str> = message.to
+        .iter()
+        .map(|p| p.phone.as_str())
+        .collect();
+    let fields = [
+        timestamp::value(&message.timestamp).to_string(),
+        thread.thread_id.clone(),
+        csv_field(&thread.source_path),
+        csv_field(&message.from.name),
+        csv_field(&message.from.phone),
+        csv_field(&to.join("; ")),
+        message.direction.as_str().to_string(),
+        message.kind.as_str().to_string(),
+        csv_field(message.body.text().unwrap_or("")),
+        csv_field(&participants.join("; ")),
+        is_group.to_string(),
+    ];
+    fields.join(",")
+}
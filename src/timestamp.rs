@@ -0,0 +1,95 @@
+use chrono::{ DateTime, SecondsFormat, Utc };
+use serde::{ Serialize, Serializer };
+use std::cell::Cell;
+use std::sync::OnceLock;
+
+// How timestamps are written in structured output (`--timestamp-format`). Serde derives
+// can't take run options, so the format is set once at startup and read by the
+// `serialize_with` helpers below; human-readable formats (HTML, iCalendar, the TUI) and
+// typed columns (Parquet) don't use it.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampFormat {
+    /// RFC 3339 with fractional seconds where the source has them
+    #[default]
+    Rfc3339,
+    /// RFC 3339 truncated to whole seconds
+    Rfc3339Secs,
+    /// Integer milliseconds since the Unix epoch
+    EpochMs,
+    /// Integer seconds since the Unix epoch
+    EpochS,
+}
+
+static FORMAT: OnceLock<TimestampFormat> = OnceLock::new();
+
+pub fn set_format(format: TimestampFormat) {
+    // Only set once per process; a second call keeps the first value
+    let _ = FORMAT.set(format);
+}
+
+fn format() -> TimestampFormat {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+// A timestamp rendered in the run's format, as text or as an integer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum TimestampValue {
+    Text(String),
+    Integer(i64),
+}
+
+impl std::fmt::Display for TimestampValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimestampValue::Text(text) => f.write_str(text),
+            TimestampValue::Integer(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// Render for outputs that write timestamps themselves (Neo4j parameters, the manifest)
+pub fn value(timestamp: &DateTime<Utc>) -> TimestampValue {
+    match format() {
+        TimestampFormat::Rfc3339 => TimestampValue::Text(timestamp.to_rfc3339()),
+        TimestampFormat::Rfc3339Secs =>
+            TimestampValue::Text(timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        TimestampFormat::EpochMs => TimestampValue::Integer(timestamp.timestamp_millis()),
+        TimestampFormat::EpochS => TimestampValue::Integer(timestamp.timestamp()),
+    }
+}
+
+thread_local! {
+    static CANONICAL: Cell<bool> = const { Cell::new(false) };
+}
+
+// Run `f` with timestamps serialized as chrono does whatever the run's format, for data
+// that is read back rather than output (the `--cache`), so it deserializes as usual
+pub fn canonical<T>(f: impl FnOnce() -> T) -> T {
+    CANONICAL.set(true);
+    let result = f();
+    CANONICAL.set(false);
+    result
+}
+
+// `serialize_with` for timestamp fields. The default keeps chrono's own serialization
+// (`2024-01-10T03:42:10.123Z`) so output is unchanged without the flag.
+pub fn serialize<S: Serializer>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    if CANONICAL.get() {
+        return timestamp.serialize(serializer);
+    }
+    match format() {
+        TimestampFormat::Rfc3339 => timestamp.serialize(serializer),
+        _ => value(timestamp).serialize(serializer),
+    }
+}
+
+pub fn serialize_option<S: Serializer>(
+    timestamp: &Option<DateTime<Utc>>,
+    serializer: S
+) -> Result<S::Ok, S::Error> {
+    match timestamp {
+        Some(timestamp) => serialize(timestamp, serializer),
+        None => serializer.serialize_none(),
+    }
+}
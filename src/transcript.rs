@@ -0,0 +1,114 @@
+use anyhow::Result;
+use chrono_tz::Tz;
+use std::collections::BTreeSet;
+use std::io::{ self, BufWriter, Write };
+use crate::html_writer::kind_label;
+use crate::takeout_writer::other_participants;
+use crate::writer::ThreadWriter;
+use crate::{ is_participant, Message, Thread };
+
+// The default format with a single `--participant`: the conversation with that contact
+// as a plain-text transcript on stdout in place of the statistics. The messages of every
+// thread that passes the filters are merged into one list in time order, whichever file
+// they came from (messages at the same time keep the order their threads arrived in),
+// and printed under a heading per day in `tz`.
+pub struct TranscriptWriter {
+    query: String,
+    tz: Tz,
+    /// Names and phones the contact appeared under in the matching threads
+    names: BTreeSet<String>,
+    phones: BTreeSet<String>,
+    sources: BTreeSet<String>,
+    messages: Vec<Message>,
+}
+
+impl TranscriptWriter {
+    pub fn new(query: &str, tz: Tz) -> Self {
+        TranscriptWriter {
+            query: query.to_string(),
+            tz,
+            names: BTreeSet::new(),
+            phones: BTreeSet::new(),
+            sources: BTreeSet::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    fn heading(&self) -> String {
+        let names: Vec<&str> = self.names.iter().map(String::as_str).collect();
+        let phones: Vec<&str> = self.phones.iter().map(String::as_str).collect();
+        match (names.is_empty(), phones.is_empty()) {
+            (false, false) => format!("{} ({})", names.join(", "), phones.join(", ")),
+            (false, true) => names.join(", "),
+            _ => phones.join(", "),
+        }
+    }
+}
+
+impl ThreadWriter for TranscriptWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        for participant in other_participants(&thread) {
+            if is_participant(&self.query, participant) {
+                if !participant.name.trim().is_empty() {
+                    self.names.insert(participant.name.clone());
+                }
+                self.phones.insert(participant.phone.clone());
+            }
+        }
+        self.sources.insert(thread.source_path);
+        self.messages.extend(thread.messages);
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        if self.messages.is_empty() {
+            eprintln!("No messages with {:?}", self.query);
+            return Ok(());
+        }
+        // Stable, so equal times keep their arrival order
+        self.messages.sort_by_key(|m| m.timestamp);
+        let mut out = BufWriter::new(io::stdout().lock());
+        writeln!(out, "Conversation with {}", self.heading())?;
+        writeln!(
+            out,
+            "{} messages from {} {}, {} to {}",
+            self.messages.len(),
+            self.sources.len(),
+            if self.sources.len() == 1 { "file" } else { "files" },
+            self.messages[0].timestamp.with_timezone(&self.tz).format("%Y-%m-%d"),
+            self.messages[self.messages.len() - 1].timestamp.with_timezone(&self.tz).format("%Y-%m-%d")
+        )?;
+        let mut day = None;
+        for message in &self.messages {
+            let local = message.timestamp.with_timezone(&self.tz);
+            let date = local.date_naive();
+            if day != Some(date) {
+                writeln!(out, "\n{}", local.format("%A, %B %-d, %Y"))?;
+                day = Some(date);
+            }
+            let sender = if message.from.name.trim().is_empty() { &message.from.phone } else { &message.from.name };
+            let mut lines = transcript_lines(message).into_iter();
+            let first = lines.next().unwrap_or_default();
+            writeln!(out, "  {}  {}: {}", local.format("%H:%M"), sender, first)?;
+            // Continuation lines are indented past the time
+            for line in lines {
+                writeln!(out, "         {}", line)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+// What a message says, one entry per line: its text, then a `[kind: src]` note for each
+// attachment
+pub fn transcript_lines(message: &Message) -> Vec<String> {
+    let mut lines: Vec<String> = message.body
+        .text()
+        .map(|text| text.lines().map(str::to_string).collect())
+        .unwrap_or_default();
+    for attachment in message.body.media() {
+        lines.push(format!("[{}: {}]", kind_label(attachment.kind), attachment.src));
+    }
+    lines
+}
@@ -0,0 +1,248 @@
+use anyhow::{ Context, Result };
+use chrono_tz::Tz;
+use ratatui::crossterm::event::{ self, Event, KeyCode, KeyEventKind };
+use ratatui::layout::{ Constraint, Layout };
+use ratatui::style::{ Color, Modifier, Style };
+use ratatui::text::{ Line, Span };
+use ratatui::widgets::{ Block, Borders, List, ListItem, ListState, Paragraph, Wrap };
+use ratatui::{ DefaultTerminal, Frame };
+use std::collections::HashMap;
+use std::path::PathBuf;
+use crate::{ parse_threads, ParseOptions, Thread };
+
+// Interactive browser over the input files. Files are only parsed when first opened
+// (or when a search needs their content), and parsed threads are cached for the session.
+struct Browser<'a> {
+    files: Vec<PathBuf>,
+    options: &'a ParseOptions,
+    tz: Tz,
+    /// Parsed threads by index into `files`; a failed parse is kept as its error text
+    parsed: HashMap<usize, Result<Vec<Thread>, String>>,
+    /// Indices into `files` matching the current search
+    visible: Vec<usize>,
+    list_state: ListState,
+    search: String,
+    searching: bool,
+    scroll: u16,
+}
+
+pub fn run(files: Vec<PathBuf>, options: &ParseOptions, tz: Tz) -> Result<()> {
+    let mut browser = Browser {
+        visible: (0..files.len()).collect(),
+        files,
+        options,
+        tz,
+        parsed: HashMap::new(),
+        list_state: ListState::default().with_selected(Some(0)),
+        search: String::new(),
+        searching: false,
+        scroll: 0,
+    };
+    let mut terminal = ratatui::init();
+    let result = browser.event_loop(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl Browser<'_> {
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            self.open_selected();
+            terminal.draw(|frame| self.draw(frame)).context("Failed to draw terminal UI")?;
+
+            let Event::Key(key) = event::read().context("Failed to read terminal event")? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if self.searching {
+                match key.code {
+                    KeyCode::Enter | KeyCode::Esc => {
+                        self.searching = false;
+                    }
+                    KeyCode::Backspace => {
+                        self.search.pop();
+                        self.apply_search();
+                    }
+                    KeyCode::Char(c) => {
+                        self.search.push(c);
+                        self.apply_search();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => {
+                    return Ok(());
+                }
+                KeyCode::Char('/') => {
+                    self.searching = true;
+                }
+                KeyCode::Down | KeyCode::Char('j') => self.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.select_previous(),
+                KeyCode::PageDown | KeyCode::Char(' ') => {
+                    self.scroll = self.scroll.saturating_add(10);
+                }
+                KeyCode::PageUp => {
+                    self.scroll = self.scroll.saturating_sub(10);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn selected_file(&self) -> Option<usize> {
+        self.list_state.selected().and_then(|i| self.visible.get(i).copied())
+    }
+
+    fn select_next(&mut self) {
+        if !self.visible.is_empty() {
+            let next = self.list_state.selected().map_or(0, |i| (i + 1).min(self.visible.len() - 1));
+            self.list_state.select(Some(next));
+            self.scroll = 0;
+        }
+    }
+
+    fn select_previous(&mut self) {
+        let previous = self.list_state.selected().map_or(0, |i| i.saturating_sub(1));
+        self.list_state.select(Some(previous));
+        self.scroll = 0;
+    }
+
+    fn open_selected(&mut self) {
+        if let Some(index) = self.selected_file() {
+            self.parse(index);
+        }
+    }
+
+    fn parse(&mut self, index: usize) {
+        let (files, options) = (&self.files, self.options);
+        self.parsed.entry(index).or_insert_with(|| {
+            parse_threads(&files[index], options).map_err(|e| format!("{:#}", e))
+        });
+    }
+
+    // Filter the file list by participant name, phone or message content. The file name
+    // is checked first so files that already match by name don't need to be parsed.
+    fn apply_search(&mut self) {
+        let needle = self.search.to_lowercase();
+        let mut visible = Vec::new();
+        for index in 0..self.files.len() {
+            let name_matches = self.files[index]
+                .to_string_lossy()
+                .to_lowercase()
+                .contains(&needle);
+            if name_matches {
+                visible.push(index);
+                continue;
+            }
+            self.parse(index);
+            if let Some(Ok(threads)) = self.parsed.get(&index) {
+                if threads.iter().any(|thread| thread_matches(thread, &needle)) {
+                    visible.push(index);
+                }
+            }
+        }
+        self.visible = visible;
+        self.list_state.select(if self.visible.is_empty() { None } else { Some(0) });
+        self.scroll = 0;
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] = Layout::vertical([Constraint::Min(1), Constraint::Length(3)]).areas(
+            frame.area()
+        );
+        let [list_area, thread_area] = Layout::horizontal([
+            Constraint::Percentage(35),
+            Constraint::Percentage(65),
+        ]).areas(main);
+
+        let items: Vec<ListItem> = self.visible
+            .iter()
+            .map(|&index| {
+                let name = self.files[index]
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                ListItem::new(name)
+            })
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("Threads ({}/{})", self.visible.len(), self.files.len()))
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        let lines = match self.selected_file().and_then(|index| self.parsed.get(&index)) {
+            Some(Ok(threads)) => thread_lines(threads, self.tz),
+            Some(Err(error)) => vec![Line::styled(error.clone(), Style::default().fg(Color::Red))],
+            None => vec![Line::raw("No thread selected")],
+        };
+        let messages = Paragraph::new(lines)
+            .block(Block::default().borders(Borders::ALL).title("Messages"))
+            .wrap(Wrap { trim: false })
+            .scroll((self.scroll, 0));
+        frame.render_widget(messages, thread_area);
+
+        let prompt = if self.searching {
+            format!("/{}", self.search)
+        } else if self.search.is_empty() {
+            "j/k: move  space/PgUp: scroll  /: search  q: quit".to_string()
+        } else {
+            format!("filter: {}  (/ to edit)", self.search)
+        };
+        let search = Paragraph::new(prompt).block(Block::default().borders(Borders::ALL).title("Search"));
+        frame.render_widget(search, status);
+    }
+}
+
+fn thread_matches(thread: &Thread, needle: &str) -> bool {
+    thread.participants
+        .iter()
+        .any(|p| p.name.to_lowercase().contains(needle) || p.phone.contains(needle)) ||
+        thread.messages
+            .iter()
+            .filter_map(|m| m.body.text())
+            .any(|content| content.to_lowercase().contains(needle))
+}
+
+fn thread_lines(threads: &[Thread], tz: Tz) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    for thread in threads {
+        let names: Vec<&str> = thread.participants
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        lines.push(
+            Line::styled(
+                format!("{} ({} messages)", names.join(", "), thread.message_count),
+                Style::default().add_modifier(Modifier::BOLD)
+            )
+        );
+        for m in &thread.messages {
+            let content = match m.body.text() {
+                Some(content) => content.to_string(),
+                None => "[attachment]".to_string(),
+            };
+            lines.push(
+                Line::from(
+                    vec![
+                        Span::styled(
+                            m.timestamp.with_timezone(&tz).format("%Y-%m-%d %H:%M ").to_string(),
+                            Style::default().fg(Color::DarkGray)
+                        ),
+                        Span::styled(format!("{}: ", m.from.name), Style::default().fg(Color::Cyan)),
+                        Span::raw(content)
+                    ]
+                )
+            );
+        }
+        lines.push(Line::raw(""));
+    }
+    lines
+}
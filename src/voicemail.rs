@@ -0,0 +1,201 @@
+use chrono::{ DateTime, Utc };
+use scraper::Html;
+use std::path::Path;
+use crate::error::ParseError;
+use crate::{
+    apply_participant_stats,
+    content_text,
+    counted_messages,
+    for_each_participant_mut,
+    geo,
+    message_id,
+    parse_attachments,
+    parse_labels,
+    participant,
+    selectors,
+    sender_phone,
+    thread_id,
+    thread_participant_stats,
+    unknown_self_phone,
+    Direction,
+    Message,
+    MessageBody,
+    MessageKind,
+    ParseOptions,
+    ParseWarning,
+    Participant,
+    Thread,
+    DATETIME_FORMAT,
+};
+
+// What Google Voice puts in place of a transcription it couldn't make, compared as
+// `is_placeholder` normalizes them
+const PLACEHOLDERS: &[&str] = &[
+    "unable to transcribe",
+    "unable to transcribe this message",
+    "transcription not available",
+    "transcript not available",
+    "no transcription available",
+];
+
+// Transcriptions that stand for none: the built-in English placeholders plus those given
+// with `--untranscribed-placeholder`, e.g. for exports in other languages
+#[derive(Debug, Clone)]
+pub struct Placeholders {
+    placeholders: Vec<String>,
+}
+
+impl Default for Placeholders {
+    fn default() -> Self {
+        Placeholders::new(&[])
+    }
+}
+
+impl Placeholders {
+    pub fn new(extra: &[String]) -> Self {
+        let placeholders = PLACEHOLDERS
+            .iter()
+            .map(|p| p.to_string())
+            .chain(extra.iter().map(|p| normalize_placeholder(p)))
+            .filter(|p| !p.is_empty())
+            .collect();
+        Placeholders { placeholders }
+    }
+
+    // Whether a transcription is missing, blank or one of the placeholders
+    pub fn is_untranscribed(&self, transcription: Option<&str>) -> bool {
+        let text = normalize_placeholder(transcription.unwrap_or(""));
+        text.is_empty() || self.placeholders.contains(&text)
+    }
+}
+
+// Trimmed and lowercased, without enclosing parentheses or a final period, so
+// `(Unable to transcribe)` and `Unable to transcribe.` are the same placeholder
+fn normalize_placeholder(text: &str) -> String {
+    let text = text.trim();
+    let text = text
+        .strip_prefix('(')
+        .and_then(|rest| rest.strip_suffix(')'))
+        .unwrap_or(text)
+        .trim();
+    text.strip_suffix('.').unwrap_or(text).trim().to_lowercase()
+}
+
+// A voicemail file (`... - Voicemail - ...`) as a thread of one incoming message from
+// the caller to you, holding the transcription and the recording. A missing or
+// placeholder transcription is recorded as an `untranscribed_voicemail` warning, which
+// `--skip-untranscribed` drops the thread by.
+pub fn parse_voicemail(document: &Html, filename: &Path, options: &ParseOptions) -> Result<Thread, ParseError> {
+    let selectors = selectors::get();
+    let voicemail = document
+        .select(&selectors.voicemail)
+        .next()
+        .ok_or(ParseError::EmptyDocument)?;
+    let caller_element = voicemail
+        .select(&selectors.caller)
+        .next()
+        .ok_or(ParseError::MissingSender { message_index: 0 })?;
+    let name = caller_element
+        .select(&selectors.name)
+        .next()
+        .and_then(|el| el.text().next())
+        .unwrap_or("");
+
+    let self_phone_known = options.me_phone.is_some();
+    let self_phone = options.me_phone.clone().unwrap_or_else(|| unknown_self_phone(filename));
+    let phone_number = sender_phone(&caller_element, name, &[], &self_phone, filename, options);
+    let caller = participant(name, &phone_number, options);
+    let me = Participant::new(options.me_name.as_deref().unwrap_or("Me"), &self_phone);
+
+    let mut warnings = Vec::new();
+    let published = voicemail.select(&selectors.published).next();
+    let title = published.and_then(|el| el.value().attr("title"));
+    let parsed = title
+        .and_then(|date_str| DateTime::parse_from_str(date_str, DATETIME_FORMAT).ok())
+        .map(|dt| dt.with_timezone(&Utc));
+    let timestamp = match (published, parsed) {
+        (_, Some(timestamp)) => Some(timestamp),
+        (None, None) => {
+            warnings.push(ParseWarning::MissingTimestamp { message_index: 0, source_position: None });
+            (!options.drop_undated).then_some(DateTime::UNIX_EPOCH)
+        }
+        (Some(_), None) => {
+            warnings.push(ParseWarning::InvalidTimestamp {
+                message_index: 0,
+                title: title.map(str::to_string),
+                source_position: None,
+            });
+            Some(DateTime::UNIX_EPOCH)
+        }
+    };
+
+    let transcription_element = voicemail.select(&selectors.transcription).next();
+    let transcription = transcription_element.map(|el| content_text(&el).trim().to_string());
+    if options.untranscribed.is_untranscribed(transcription.as_deref()) {
+        warnings.push(ParseWarning::UntranscribedVoicemail {
+            transcription: transcription.clone().filter(|text| !text.is_empty()),
+        });
+    }
+
+    let mut participants = vec![caller.clone(), me.clone()];
+    let mut messages: Vec<Message> = timestamp
+        .map(|timestamp| Message {
+            id: message_id(
+                &caller.phone,
+                &timestamp,
+                transcription_element.map(|el| el.text().collect::<String>()).as_deref(),
+                filename
+            ),
+            from: caller,
+            to: vec![me],
+            direction: Direction::Incoming,
+            kind: MessageKind::Regular,
+            timestamp,
+            body: MessageBody::new(
+                transcription.filter(|text| !text.is_empty()),
+                parse_attachments(&voicemail, &selectors.attachment, &selectors.reply, &selectors.sender)
+            ),
+            reply_to: None,
+            language: None,
+            flags: Vec::new(),
+            truncated: false,
+            source_position: None,
+            raw_html: options.include_raw.then(|| voicemail.html()),
+            message_index: None,
+        })
+        .into_iter()
+        .collect();
+
+    if let Some(rename_map) = &options.rename_map {
+        for_each_participant_mut(&mut participants, &mut messages, |participant| {
+            rename_map.rename(participant)
+        });
+    }
+    if let Some(classifier) = &options.classifier {
+        for_each_participant_mut(&mut participants, &mut messages, |participant| {
+            participant.participant_type = Some(classifier.classify(participant));
+        });
+    }
+    apply_participant_stats(&mut participants, &mut messages);
+    if options.geo {
+        for_each_participant_mut(&mut participants, &mut messages, |participant| {
+            participant.region = geo::region_for_phone(&participant.phone).map(str::to_string);
+        });
+    }
+
+    let message_count = counted_messages(&messages);
+    Ok(Thread {
+        thread_id: thread_id(&participants, filename, 0),
+        source_path: filename.to_string_lossy().to_string(),
+        participant_stats: thread_participant_stats(&participants, &messages),
+        messages,
+        participants,
+        labels: parse_labels(document),
+        message_count,
+        is_self_thread: false,
+        self_phone_known,
+        session_index: None,
+        thread_index: None,
+        warnings,
+    })
+}
@@ -0,0 +1,62 @@
+use anyhow::{ Context, Result };
+use notify::{ Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher };
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::mpsc::{ self, Receiver, RecvTimeoutError };
+use std::time::Duration;
+
+// How long the directories must stay quiet before a batch of changes is processed, so
+// a file still being copied in (many write events) is parsed once, when complete
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+// How often waiting for events checks for Ctrl-C
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// `--watch`: file system notifications for the input directories after the initial
+// pass, delivered as debounced batches of created or modified paths
+pub struct DirectoryWatcher {
+    // Dropping the watcher stops the notifications
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+}
+
+impl DirectoryWatcher {
+    pub fn new(dirs: &[PathBuf]) -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).context("Failed to start watching")?;
+        for dir in dirs {
+            watcher
+                .watch(dir, RecursiveMode::Recursive)
+                .with_context(|| format!("Failed to watch {:?}", dir))?;
+        }
+        Ok(DirectoryWatcher { _watcher: watcher, events })
+    }
+
+    // Wait for the next batch of changed paths, in path order. Returns `None` once
+    // `stop` says so (checked while waiting) or the watcher has shut down.
+    pub fn next_batch(&self, stop: impl Fn() -> bool) -> Option<Vec<PathBuf>> {
+        let mut changed = BTreeSet::new();
+        loop {
+            if stop() {
+                return None;
+            }
+            // Once something changed, wait for a quiet period rather than indefinitely
+            let timeout = if changed.is_empty() { POLL_INTERVAL } else { DEBOUNCE };
+            match self.events.recv_timeout(timeout) {
+                Ok(Ok(event)) => {
+                    if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                        changed.extend(event.paths);
+                    }
+                }
+                Ok(Err(e)) => log::warn!("Watch error: {}", e),
+                Err(RecvTimeoutError::Timeout) if !changed.is_empty() => {
+                    return Some(changed.into_iter().collect());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use anyhow::{ Context, Result };
+use serde::Serialize;
+use serde_json::{ Map, Value };
+use std::fs;
+use std::io::{ self, BufWriter, Write };
+use std::path::Path;
+use crate::archive_writer::ArchiveWriter;
+use crate::chunk_writer::ChunkWriter;
+use crate::csv_writer::CsvWriter;
+use crate::cypher_writer::Neo4jWriter;
+use crate::html_writer::HtmlWriter;
+use crate::ics_writer::IcsWriter;
+use crate::markdown_writer::MarkdownWriter;
+use crate::parquet_writer::ParquetWriter;
+use crate::protobuf_writer::ProtobufWriter;
+use crate::sample_writer::SampleWriter;
+use crate::signal_writer::SignalJsonWriter;
+use crate::split_writer::SplitWriter;
+use crate::takeout_writer::TakeoutWriter;
+use crate::{ JsonCase, Neo4jOptions, OutputFormat, OutputOptions, Thread };
+
+// Destination for the threads of a run. A writer is selected once from the output
+// format, `begin` is called before the first thread and `finish` after the last one,
+// so adding a format means adding an implementation here rather than extending
+// matches throughout the processing code.
+pub trait ThreadWriter {
+    // Prepare the output; called once before any thread is written
+    fn begin(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_thread(&mut self, thread: Thread) -> Result<()>;
+
+    // Flush and finalize the output; called once after the last thread
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+// Select the writer for `options.format`. `output` is the `--output` path, which the
+// file-based formats require and the text formats use instead of stdout; `source_root`
+// is the input as given on the command line, recorded by the archive format.
+// `chunks` is the `--chunk-output` prefix and chunk size, which replace the single
+// JSON stream, and `neo4j` configures the Cypher writer.
+pub fn create_writer(
+    options: &OutputOptions,
+    output: Option<&Path>,
+    source_root: &Path,
+    chunks: Option<(&Path, usize)>,
+    neo4j: &Neo4jOptions
+) -> Result<Box<dyn ThreadWriter>> {
+    if let Some(sample) = options.sample {
+        return match options.format {
+            OutputFormat::Json | OutputFormat::Default =>
+                Ok(
+                    Box::new(
+                        SampleWriter::new(open_output(output)?, *options, sample.size, sample.seed)
+                    )
+                ),
+            format => anyhow::bail!("--sample writes JSON and can't be used with the {:?} format", format),
+        };
+    }
+    if let Some(period) = options.split_by {
+        return match (options.format, output) {
+            (OutputFormat::Json | OutputFormat::Default, Some(dir)) =>
+                Ok(Box::new(SplitWriter::create(dir, period, options.tz, *options)?)),
+            (OutputFormat::Json | OutputFormat::Default, None) => anyhow::bail!("--split-by requires --output <DIR>"),
+            (format, _) => anyhow::bail!("--split-by writes NDJSON and can't be used with the {:?} format", format),
+        };
+    }
+    if let Some((prefix, chunk_size)) = chunks {
+        // The default format keeps its progress bar and statistics alongside the chunks
+        return match options.format {
+            OutputFormat::Json | OutputFormat::Default =>
+                Ok(Box::new(ChunkWriter::new(prefix, chunk_size, *options))),
+            format => anyhow::bail!("--chunk-output writes JSON and can't be used with the {:?} format", format),
+        };
+    }
+    let writer: Box<dyn ThreadWriter> = match (options.format, output) {
+        (OutputFormat::Default, _) => Box::new(DiscardWriter),
+        (OutputFormat::Debug | OutputFormat::Json | OutputFormat::Yaml, output) =>
+            Box::new(TextWriter {
+                out: open_output(output)?,
+                options: *options,
+            }),
+        // Every line is one thread, whatever `--compact` says
+        (OutputFormat::Ndjson, output) =>
+            Box::new(TextWriter {
+                out: open_output(output)?,
+                options: OutputOptions { compact_json: true, ..*options },
+            }),
+        (OutputFormat::Csv, output) => Box::new(CsvWriter::create(open_output(output)?)?),
+        (OutputFormat::Markdown, output) => Box::new(MarkdownWriter::create(open_output(output)?, options.tz)),
+        (OutputFormat::Archive, output) =>
+            Box::new(ArchiveWriter::create(open_output(output)?, source_root)?),
+        (OutputFormat::SignalJson, output) =>
+            Box::new(SignalJsonWriter::create(open_output(output)?)?),
+        (OutputFormat::Ics, output) =>
+            Box::new(IcsWriter::create(open_output(output)?, options.tz, options.ics_events)?),
+        (OutputFormat::Protobuf, output) => Box::new(ProtobufWriter::create(open_output(output)?)),
+        (OutputFormat::Cypher, _) =>
+            Box::new(
+                Neo4jWriter::new(
+                    neo4j.checkpoint.clone(),
+                    neo4j.channel_capacity,
+                    neo4j.send_batch,
+                    neo4j.max_rate,
+                    neo4j.target.clone(),
+                    neo4j.conversed_with
+                )
+            ),
+        (OutputFormat::Parquet, Some(output)) => Box::new(ParquetWriter::create(output)?),
+        (OutputFormat::Html, Some(output)) =>
+            Box::new(HtmlWriter::create(output, options.tz, options.embed_media, options.collapse)?),
+        (OutputFormat::Parquet, None) => anyhow::bail!("Parquet output requires --output"),
+        (OutputFormat::Takeout, Some(output)) => Box::new(TakeoutWriter::create(output)?),
+        (OutputFormat::Html, None) => anyhow::bail!("HTML output requires --output <DIR>"),
+        (OutputFormat::Takeout, None) => anyhow::bail!("Takeout output requires --output <DIR>"),
+    };
+    Ok(writer)
+}
+
+// Writers for `--emit`: every format in `formats` gets the whole thread stream, written
+// below `dir` to the file or subdirectory `emit_path` names for it
+pub fn create_multi_writer(
+    formats: &[OutputFormat],
+    options: &OutputOptions,
+    dir: &Path,
+    source_root: &Path
+) -> Result<Box<dyn ThreadWriter>> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create output directory: {:?}", dir))?;
+    let mut writers = Vec::new();
+    let mut emitted = Vec::new();
+    for &format in formats {
+        if emitted.contains(&format) {
+            continue;
+        }
+        let Some(name) = emit_path(format) else {
+            anyhow::bail!("The {:?} format has no output file and can't be used with --emit", format);
+        };
+        let options = OutputOptions { format, ..*options };
+        let path = dir.join(name);
+        writers.push(
+            create_writer(&options, Some(&path), source_root, None, &Neo4jOptions::default())?
+        );
+        emitted.push(format);
+    }
+    Ok(Box::new(MultiWriter { writers }))
+}
+
+// Where `--emit` writes each format inside the output directory. The default format
+// (statistics only) and Cypher (written to Neo4j, with its own resume state) have no
+// file and are run on their own.
+fn emit_path(format: OutputFormat) -> Option<&'static str> {
+    match format {
+        OutputFormat::Debug => Some("threads.txt"),
+        OutputFormat::Json => Some("threads.json"),
+        OutputFormat::Ndjson => Some("threads.ndjson"),
+        OutputFormat::Csv => Some("messages.csv"),
+        OutputFormat::Markdown => Some("threads.md"),
+        OutputFormat::Yaml => Some("threads.yaml"),
+        OutputFormat::Archive => Some("archive.json"),
+        OutputFormat::SignalJson => Some("signal-backup.json"),
+        OutputFormat::Parquet => Some("messages.parquet"),
+        OutputFormat::Html => Some("html"),
+        OutputFormat::Takeout => Some("takeout"),
+        OutputFormat::Ics => Some("calendar.ics"),
+        OutputFormat::Protobuf => Some("threads.pb"),
+        OutputFormat::Default | OutputFormat::Cypher => None,
+    }
+}
+
+// Fans each thread out to several writers; the last one gets the thread itself
+struct MultiWriter {
+    writers: Vec<Box<dyn ThreadWriter>>,
+}
+
+impl ThreadWriter for MultiWriter {
+    fn begin(&mut self) -> Result<()> {
+        self.writers.iter_mut().try_for_each(|writer| writer.begin())
+    }
+
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        let Some((last, rest)) = self.writers.split_last_mut() else {
+            return Ok(());
+        };
+        for writer in rest {
+            writer.write_thread(thread.clone())?;
+        }
+        last.write_thread(thread)
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.writers.into_iter().try_for_each(|writer| writer.finish())
+    }
+}
+
+// The default format only reports statistics, so threads themselves are dropped
+struct DiscardWriter;
+
+impl ThreadWriter for DiscardWriter {
+    fn write_thread(&mut self, _thread: Thread) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Debug, JSON, NDJSON and YAML output to stdout or the `--output` file
+struct TextWriter {
+    out: Box<dyn Write>,
+    options: OutputOptions,
+}
+
+impl ThreadWriter for TextWriter {
+    fn write_thread(&mut self, thread: Thread) -> Result<()> {
+        match self.options.format {
+            OutputFormat::Debug => writeln!(self.out, "{:#?}", thread)?,
+            OutputFormat::Json | OutputFormat::Ndjson =>
+                writeln!(self.out, "{}", to_json(&thread, &self.options)?)?,
+            OutputFormat::Yaml => write_yaml_item(&mut self.out, &thread)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush().context("Failed to flush output")
+    }
+}
+
+// Serialize JSON output honoring `--compact` and `--json-case`. Camel case is applied to
+// the serialized keys, so the structs keep their snake_case serde names.
+pub fn to_json<T: Serialize>(value: &T, options: &OutputOptions) -> Result<String> {
+    let json = match options.json_case {
+        JsonCase::Snake if options.compact_json => serde_json::to_string(value)?,
+        JsonCase::Snake => serde_json::to_string_pretty(value)?,
+        JsonCase::Camel => {
+            let value = camel_case_keys(serde_json::to_value(value)?);
+            if options.compact_json {
+                serde_json::to_string(&value)?
+            } else {
+                serde_json::to_string_pretty(&value)?
+            }
+        }
+    };
+    Ok(json)
+}
+
+fn camel_case_keys(value: Value) -> Value {
+    match value {
+        Value::Object(object) =>
+            Value::Object(
+                object
+                    .into_iter()
+                    .map(|(key, value)| (camel_case(&key), camel_case_keys(value)))
+                    .collect::<Map<_, _>>()
+            ),
+        Value::Array(items) => Value::Array(items.into_iter().map(camel_case_keys).collect()),
+        value => value,
+    }
+}
+
+// `message_count` becomes `messageCount`; keys without underscores are unchanged
+fn camel_case(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' && !camel.is_empty() {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+// Write a thread as one item of a top-level YAML sequence, so every thread of a run
+// streams into a single document without being held in memory
+fn write_yaml_item(out: &mut dyn Write, thread: &Thread) -> Result<()> {
+    let yaml = serde_yaml::to_string(thread).context("Failed to serialize thread to YAML")?;
+    let mut lines = yaml.lines();
+    if let Some(first) = lines.next() {
+        writeln!(out, "- {}", first)?;
+    }
+    for line in lines {
+        if line.is_empty() {
+            writeln!(out)?;
+        } else {
+            writeln!(out, "  {}", line)?;
+        }
+    }
+    Ok(())
+}
+
+// Open the destination for thread output: the `--output` file if given, else stdout
+fn open_output(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    match path {
+        Some(path) => {
+            let file = fs::File::create(path)
+                .with_context(|| format!("Failed to create output file: {:?}", path))?;
+            Ok(Box::new(BufWriter::new(file)))
+        }
+        None => Ok(Box::new(io::stdout())),
+    }
+}